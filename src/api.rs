@@ -1,82 +1,352 @@
 use std::sync::Arc;
 
-use axum::extract::{Request, State};
-use axum::http::StatusCode;
+use axum::extract::{DefaultBodyLimit, FromRequest, Multipart, Path, Query, Request, State};
+use axum::http::{HeaderMap, Method, StatusCode, header};
 use axum::middleware::{self, Next};
-use axum::response::IntoResponse;
+use axum::response::{IntoResponse, Response};
 use axum::routing::{get, post};
-use axum::{Json, Router};
+use axum::{Extension, Json, Router};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as B64;
 use serde::{Deserialize, Serialize};
 
-use crate::agent::Agent;
+use crate::attachments::{Attachment, MAX_ATTACHMENT_BYTES, guess_mime};
+use crate::auth::{self, KeyStore, Principal, Scope};
+use crate::backup;
+use crate::export::{self, ExportFilter};
+use crate::facts::FactQuery;
+use crate::jobs::JobRequest;
+use crate::llm::Content;
+use crate::memory_api;
+use crate::openai;
+use crate::ratelimit::Limiter;
+use crate::session::Session;
+use crate::tenant::{Tenant, Tenants};
+use crate::ws;
 
 #[derive(Clone)]
 pub struct AppState {
-    pub agent: Arc<Agent>,
+    /// Handlers get the caller's tenant as an `Extension<Arc<Tenant>>`.
+    pub tenants: Arc<Tenants>,
+    pub limits: Arc<Limiter>,
     pub name: String,
-    pub api_key: Option<String>,
+    pub keys: Arc<KeyStore>,
+    /// Archived (without secrets) by `POST /backup`.
+    pub config_path: std::path::PathBuf,
 }
 
 #[derive(Deserialize)]
 pub struct MessageRequest {
+    #[serde(default)]
     pub text: String,
     #[serde(default = "default_channel")]
     pub channel: String,
-    #[serde(default = "default_user")]
-    pub user: String,
+    /// Defaults to the tenant's `default_user`.
+    pub user: Option<String>,
+    #[serde(default)]
+    pub attachments: Vec<AttachmentUpload>,
+    /// Return a job id at once and run the agent in the background.
+    #[serde(default, rename = "async")]
+    pub run_async: bool,
+    /// Where to POST the result of an async job.
+    pub callback_url: Option<String>,
+    /// Idempotency key; the `Idempotency-Key` header takes precedence.
+    pub message_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttachmentUpload {
+    pub filename: String,
+    pub mime_type: Option<String>,
+    /// Base64 contents (a `data:` URL is accepted too).
+    pub data: String,
 }
 
 fn default_channel() -> String {
     "cli".into()
 }
-fn default_user() -> String {
-    "masaki".into()
-}
 
 #[derive(Serialize)]
 pub struct MessageResponse {
     pub text: String,
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub actions: Vec<serde_json::Value>,
+    /// The run was cancelled with `POST /sessions/{key}/cancel`.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub cancelled: bool,
 }
 
+/// Room for a few attachments plus base64 overhead.
+const MAX_MESSAGE_BYTES: usize = 2 * MAX_ATTACHMENT_BYTES;
+
 pub fn router(state: AppState) -> Router {
     Router::new()
         .route("/", get(chat_page))
-        .route("/message", post(handle_message))
+        .route("/login", get(login_with_token).post(login))
+        .route("/login/state", get(login_state))
+        .route("/login/token", post(issue_login_token))
+        .route("/logout", post(logout))
+        .route(
+            "/message",
+            post(handle_message).layer(DefaultBodyLimit::max(MAX_MESSAGE_BYTES)),
+        )
         .route("/health", get(handle_health))
+        .route("/facts", get(handle_facts))
+        .route("/export", get(handle_export))
+        .route("/backup", post(handle_backup))
+        .route("/jobs/:id", get(handle_job))
+        .route("/sessions", get(handle_sessions))
+        .route("/sessions/:key", get(handle_session))
+        .route("/sessions/:key/cancel", post(cancel_session))
+        .merge(openai::routes())
+        .merge(memory_api::routes())
+        .merge(ws::routes())
         .layer(middleware::from_fn_with_state(state.clone(), auth_layer))
         .with_state(state)
 }
 
-async fn auth_layer(State(state): State<AppState>, req: Request, next: Next) -> impl IntoResponse {
-    if let Some(ref expected) = state.api_key
-        && !matches!(req.uri().path(), "/health" | "/")
-    {
-        let auth_ok = req
-            .headers()
-            .get("authorization")
-            .and_then(|v| v.to_str().ok())
-            .and_then(|v| v.strip_prefix("Bearer "))
-            .is_some_and(|t| t == expected);
-        if !auth_ok {
-            return (
-                StatusCode::UNAUTHORIZED,
-                Json(serde_json::json!({"error": "Unauthorized"})),
-            )
-                .into_response();
+/// Scope needed for a path; `None` for public pages.
+fn required_scope(path: &str) -> Option<Scope> {
+    match path {
+        "/" | "/health" | "/login" | "/login/state" | "/logout" => None,
+        "/message" | "/ws" => Some(Scope::Message),
+        p if p.starts_with("/sessions/") && p.ends_with("/cancel") => Some(Scope::Message),
+        p if p.starts_with("/jobs/") || p.starts_with("/v1/") => Some(Scope::Message),
+        "/facts" | "/export" | "/sessions" => Some(Scope::SessionsRead),
+        p if p.starts_with("/sessions/") || p.starts_with("/memory/") => Some(Scope::SessionsRead),
+        _ => Some(Scope::Admin),
+    }
+}
+
+fn unauthorized(status: StatusCode) -> Response {
+    let error = if status == StatusCode::FORBIDDEN {
+        "Forbidden"
+    } else {
+        "Unauthorized"
+    };
+    (status, Json(serde_json::json!({ "error": error }))).into_response()
+}
+
+/// The browser login behind the request's session cookie. State-changing
+/// requests must also echo its CSRF token in `X-CSRF-Token`.
+fn web_principal(state: &AppState, req: &Request) -> Result<Principal, StatusCode> {
+    let csrf = auth::cookie(req.headers(), auth::SESSION_COOKIE)
+        .and_then(|id| state.keys.web_session(id))
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+    let safe = matches!(*req.method(), Method::GET | Method::HEAD);
+    let sent = req
+        .headers()
+        .get("x-csrf-token")
+        .and_then(|v| v.to_str().ok());
+    if !safe && !sent.is_some_and(|t| auth::ct_eq(t, &csrf)) {
+        tracing::warn!(
+            "Rejected {} {} without a valid CSRF token",
+            req.method(),
+            req.uri().path()
+        );
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(Principal::web())
+}
+
+async fn auth_layer(State(state): State<AppState>, mut req: Request, next: Next) -> Response {
+    let Some(scope) = required_scope(req.uri().path()) else {
+        return next.run(req).await;
+    };
+    let principal = if state.keys.is_empty() {
+        Principal::anonymous()
+    } else if let Some(token) = auth::bearer_token(req.headers()) {
+        match state.keys.authenticate(token) {
+            Some(p) => p,
+            None => return unauthorized(StatusCode::UNAUTHORIZED),
+        }
+    } else {
+        match web_principal(&state, &req) {
+            Ok(p) => p,
+            Err(status) => return unauthorized(status),
         }
+    };
+    if !principal.allows(scope) {
+        tracing::warn!(
+            "API key '{}' lacks {scope} for {}",
+            principal.name,
+            req.uri().path()
+        );
+        return unauthorized(StatusCode::FORBIDDEN);
     }
-    next.run(req).await.into_response()
+    // Keys are reloaded without a restart, tenants are not.
+    let Some(tenant) = state.tenants.get(principal.tenant.as_deref()) else {
+        tracing::warn!(
+            "API key '{}' belongs to unknown tenant {:?}",
+            principal.name,
+            principal.tenant
+        );
+        return unauthorized(StatusCode::FORBIDDEN);
+    };
+    tracing::info!(
+        "API key '{}': {} {}",
+        principal.name,
+        req.method(),
+        req.uri().path()
+    );
+    req.extensions_mut().insert(principal);
+    req.extensions_mut().insert(tenant);
+    next.run(req).await
+}
+
+type BadRequest = (StatusCode, Json<MessageResponse>);
+
+fn bad_request(msg: impl std::fmt::Display) -> BadRequest {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(MessageResponse {
+            text: format!("Error: {msg}"),
+            actions: vec![],
+            cancelled: false,
+        }),
+    )
+}
+
+/// Parse `POST /message` from JSON (attachments as base64) or
+/// multipart/form-data (`text`, `channel`, `user` fields plus file parts).
+/// An `Idempotency-Key` header overrides `message_id`.
+async fn parse_message(req: Request) -> Result<(MessageRequest, Vec<Attachment>), BadRequest> {
+    let idempotency_key = req
+        .headers()
+        .get("idempotency-key")
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty())
+        .map(str::to_string);
+    let (mut msg, attachments) = parse_body(req).await?;
+    if idempotency_key.is_some() {
+        msg.message_id = idempotency_key;
+    }
+    Ok((msg, attachments))
+}
+
+async fn parse_body(req: Request) -> Result<(MessageRequest, Vec<Attachment>), BadRequest> {
+    let is_multipart = req
+        .headers()
+        .get(axum::http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("multipart/form-data"));
+    if !is_multipart {
+        let Json(mut msg) = Json::<MessageRequest>::from_request(req, &())
+            .await
+            .map_err(|e| bad_request(e.body_text()))?;
+        let attachments = std::mem::take(&mut msg.attachments)
+            .iter()
+            .map(|a| Attachment::from_base64(&a.filename, a.mime_type.as_deref(), &a.data))
+            .collect::<anyhow::Result<_>>()
+            .map_err(bad_request)?;
+        return Ok((msg, attachments));
+    }
+
+    let mut form = Multipart::from_request(req, &())
+        .await
+        .map_err(|e| bad_request(e.body_text()))?;
+    let mut msg = MessageRequest {
+        text: String::new(),
+        channel: default_channel(),
+        user: None,
+        attachments: Vec::new(),
+        run_async: false,
+        callback_url: None,
+        message_id: None,
+    };
+    let mut attachments = Vec::new();
+    while let Some(field) = form.next_field().await.map_err(bad_request)? {
+        if let Some(filename) = field.file_name().map(str::to_string) {
+            let mime_type = field
+                .content_type()
+                .filter(|m| *m != "application/octet-stream")
+                .map_or_else(|| guess_mime(&filename).to_string(), str::to_string);
+            let data = field.bytes().await.map_err(bad_request)?.to_vec();
+            attachments.push(Attachment {
+                filename,
+                mime_type,
+                data,
+            });
+            continue;
+        }
+        let name = field.name().unwrap_or_default().to_string();
+        let value = field.text().await.map_err(bad_request)?;
+        match name.as_str() {
+            "text" => msg.text = value,
+            "channel" => msg.channel = value,
+            "user" => msg.user = Some(value),
+            "async" => msg.run_async = matches!(value.as_str(), "true" | "1"),
+            "callback_url" => msg.callback_url = Some(value),
+            "message_id" => msg.message_id = Some(value),
+            _ => {}
+        }
+    }
+    Ok((msg, attachments))
 }
 
 async fn handle_message(
     State(state): State<AppState>,
-    Json(req): Json<MessageRequest>,
-) -> impl IntoResponse {
-    match state
+    Extension(principal): Extension<Principal>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    req: Request,
+) -> Response {
+    let (mut req, attachments) = match parse_message(req).await {
+        Ok(m) => m,
+        Err(e) => return e.into_response(),
+    };
+    let mut user = req
+        .user
+        .take()
+        .unwrap_or_else(|| tenant.default_user.clone());
+    principal.bind(&mut user, &mut req.channel);
+    if req.text.trim().is_empty() && attachments.is_empty() {
+        return bad_request("'text' or an attachment is required").into_response();
+    }
+    let session = format!("{}:{user}", req.channel);
+    if let Err(e) = state
+        .limits
+        .check(&principal.name, &tenant.scoped(&session))
+    {
+        return e.into_response();
+    }
+    if req.run_async {
+        let job = JobRequest {
+            text: req.text,
+            channel: req.channel,
+            user,
+            attachments: attachments
+                .iter()
+                .map(|a| AttachmentUpload {
+                    filename: a.filename.clone(),
+                    mime_type: Some(a.mime_type.clone()),
+                    data: B64.encode(&a.data),
+                })
+                .collect(),
+            callback_url: req.callback_url,
+            message_id: req.message_id,
+        };
+        return match tenant.jobs.submit(job) {
+            Ok(job) => (StatusCode::ACCEPTED, Json(job.to_json())).into_response(),
+            Err(e) => bad_request(e).into_response(),
+        };
+    }
+    if req.callback_url.is_some() {
+        return bad_request("'callback_url' requires \"async\": true").into_response();
+    }
+    let _permit = match state.limits.acquire().await {
+        Ok(p) => p,
+        Err(e) => return e.into_response(),
+    };
+    match tenant
         .agent
-        .handle_message(&req.text, &req.channel, &req.user)
+        .handle_message(
+            &req.text,
+            &req.channel,
+            &user,
+            attachments,
+            req.message_id.as_deref(),
+            None,
+        )
         .await
     {
         Ok(resp) => (
@@ -84,6 +354,7 @@ async fn handle_message(
             Json(MessageResponse {
                 text: resp.text.unwrap_or_else(|| "(no response)".into()),
                 actions: resp.actions,
+                cancelled: resp.cancelled,
             }),
         ),
         Err(e) => {
@@ -93,10 +364,154 @@ async fn handle_message(
                 Json(MessageResponse {
                     text: format!("Error: {e}"),
                     actions: vec![],
+                    cancelled: false,
                 }),
             )
         }
     }
+    .into_response()
+}
+
+async fn handle_job(
+    Extension(principal): Extension<Principal>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    Path(id): Path<String>,
+) -> Response {
+    let job = tenant
+        .jobs
+        .get(&id)
+        .filter(|j| principal.can_see(&j.request.user, &j.request.channel));
+    match job {
+        Some(job) => Json(job.to_json()).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error": "Job not found"})),
+        )
+            .into_response(),
+    }
+}
+
+/// Split a `channel:user` session key.
+fn session_owner(key: &str) -> (&str, &str) {
+    key.split_once(':').unwrap_or((key, ""))
+}
+
+/// Last thing said in a session, for the sidebar.
+fn preview(session: &Session) -> String {
+    let text = session
+        .messages
+        .iter()
+        .rev()
+        .filter(|m| matches!(m.role.as_str(), "user" | "assistant"))
+        .find_map(|m| m.content.as_ref().map(Content::text))
+        .unwrap_or_default();
+    text.chars().take(80).collect()
+}
+
+/// `GET /sessions`: the caller's sessions, most recent first.
+async fn handle_sessions(
+    Extension(principal): Extension<Principal>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+) -> impl IntoResponse {
+    let mut sessions: Vec<Session> = tenant
+        .sessions
+        .list()
+        .await
+        .into_iter()
+        .filter(|s| {
+            let (channel, user) = session_owner(&s.key);
+            principal.can_see(user, channel)
+        })
+        .collect();
+    sessions.sort_by_key(|s| std::cmp::Reverse(s.updated_at));
+    let list: Vec<_> = sessions
+        .iter()
+        .map(|s| {
+            let (channel, user) = session_owner(&s.key);
+            serde_json::json!({
+                "key": s.key,
+                "channel": channel,
+                "user": user,
+                "updated_at": s.updated_at,
+                "messages": s.messages.len(),
+                "preview": preview(s),
+            })
+        })
+        .collect();
+    Json(serde_json::json!({ "sessions": list }))
+}
+
+/// `GET /sessions/{key}`: the messages of one session, with tool calls and
+/// results as separate entries.
+async fn handle_session(
+    Extension(principal): Extension<Principal>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    Path(key): Path<String>,
+) -> Response {
+    let (channel, user) = session_owner(&key);
+    let session = if principal.can_see(user, channel) {
+        tenant.sessions.get(&key).await
+    } else {
+        None
+    };
+    let Some(session) = session else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error": "Session not found"})),
+        )
+            .into_response();
+    };
+    let messages: Vec<_> = session
+        .messages
+        .iter()
+        .map(|m| {
+            let tool_calls: Vec<_> = m
+                .tool_calls
+                .iter()
+                .flatten()
+                .map(|tc| {
+                    serde_json::json!({
+                        "id": tc.id,
+                        "name": tc.function.name,
+                        "arguments": tc.function.arguments,
+                    })
+                })
+                .collect();
+            serde_json::json!({
+                "role": m.role,
+                "text": m.content.as_ref().map(Content::describe).unwrap_or_default(),
+                "tool_calls": tool_calls,
+                "tool_call_id": m.tool_call_id,
+            })
+        })
+        .collect();
+    Json(serde_json::json!({
+        "key": session.key,
+        "summary": session.summary,
+        "updated_at": session.updated_at,
+        "messages": messages,
+    }))
+    .into_response()
+}
+
+/// `POST /sessions/{key}/cancel`: stop whatever the agent is doing in a
+/// session.
+async fn cancel_session(
+    Extension(principal): Extension<Principal>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    Path(key): Path<String>,
+) -> Response {
+    let (channel, user) = session_owner(&key);
+    if !principal.can_see(user, channel) {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error": "Session not found"})),
+        )
+            .into_response();
+    }
+    let cancelled = tenant.agent.cancel(&key);
+    tracing::info!("'{}' cancelled {cancelled} runs in {key}", principal.name);
+    Json(serde_json::json!({ "cancelled": cancelled })).into_response()
 }
 
 async fn handle_health(State(state): State<AppState>) -> impl IntoResponse {
@@ -104,13 +519,238 @@ async fn handle_health(State(state): State<AppState>) -> impl IntoResponse {
         "status": "ok",
         "name": state.name,
         "version": env!("CARGO_PKG_VERSION"),
+        "agent": state.limits.stats(),
+    }))
+}
+
+/// Facts are not per-user or per-channel, so keys bound to either are
+/// refused.
+async fn handle_facts(
+    Extension(principal): Extension<Principal>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    Query(q): Query<FactQuery>,
+) -> Response {
+    if principal.user.is_some() || principal.channel.is_some() {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(serde_json::json!({
+                "error": "Keys bound to a user or channel cannot read facts"
+            })),
+        )
+            .into_response();
+    }
+    Json(serde_json::json!({ "facts": tenant.facts.query(&q) })).into_response()
+}
+
+async fn handle_export(
+    Extension(principal): Extension<Principal>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    Query(mut filter): Query<ExportFilter>,
+) -> impl IntoResponse {
+    if principal.channel.is_some() {
+        filter.channel.clone_from(&principal.channel);
+    }
+    if principal.user.is_some() {
+        filter.user.clone_from(&principal.user);
+    }
+    let mut buf = Vec::new();
+    match export::export(
+        &tenant.memory,
+        &tenant.sessions,
+        &tenant.facts,
+        &filter,
+        &mut buf,
+    )
+    .await
+    {
+        Ok(_) => (
+            StatusCode::OK,
+            [(axum::http::header::CONTENT_TYPE, "application/x-ndjson")],
+            buf,
+        )
+            .into_response(),
+        Err(e) => {
+            tracing::error!("Export error: {e}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({"error": e.to_string()})),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// `POST /backup` (admin): a backup archive of the caller's tenant, taken
+/// with session writes paused. `1koro backup` uses this while the server
+/// runs; the `X-Backup-Tenant` and `X-Backup-Files` headers describe it.
+async fn handle_backup(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+) -> Response {
+    let (base_dir, config_path, sessions) = (
+        tenant.memory.base_dir().to_path_buf(),
+        state.config_path.clone(),
+        tenant.sessions.clone(),
+    );
+    let result = tokio::task::spawn_blocking(move || {
+        let mut buf = Vec::new();
+        let manifest = backup::write(&base_dir, Some(&config_path), &mut buf, Some(&sessions))?;
+        anyhow::Ok((manifest.files.len(), buf))
+    })
+    .await
+    .map_err(anyhow::Error::from)
+    .and_then(|r| r);
+    match result {
+        Ok((files, buf)) => {
+            tracing::info!(
+                "Backup of tenant '{}' ({files} files) sent to '{}'",
+                tenant.name,
+                principal.name
+            );
+            (
+                [
+                    (header::CONTENT_TYPE, "application/gzip".to_string()),
+                    (
+                        header::HeaderName::from_static("x-backup-tenant"),
+                        tenant.name.clone(),
+                    ),
+                    (
+                        header::HeaderName::from_static("x-backup-files"),
+                        files.to_string(),
+                    ),
+                ],
+                buf,
+            )
+                .into_response()
+        }
+        Err(e) => {
+            tracing::error!("Backup error: {e}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({"error": e.to_string()})),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Sent after a failed password so guessing is slow.
+const LOGIN_FAILURE_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+
+fn session_cookie(headers: &HeaderMap, id: &str, max_age: u64) -> String {
+    let secure = headers
+        .get("x-forwarded-proto")
+        .is_some_and(|v| v.as_bytes() == b"https");
+    format!(
+        "{}={id}; Path=/; HttpOnly; SameSite=Strict; Max-Age={max_age}{}",
+        auth::SESSION_COOKIE,
+        if secure { "; Secure" } else { "" }
+    )
+}
+
+fn logged_in(state: &AppState, headers: &HeaderMap) -> Response {
+    let (id, csrf) = state.keys.start_web_session();
+    let cookie = session_cookie(headers, &id, auth::WEB_SESSION_TTL.as_secs());
+    (
+        [(header::SET_COOKIE, cookie)],
+        Json(serde_json::json!({ "logged_in": true, "csrf": csrf, "user": web_user(state) })),
+    )
+        .into_response()
+}
+
+/// The user the web chat talks as: the default tenant's, like its login.
+fn web_user(state: &AppState) -> Option<String> {
+    state.tenants.get(None).map(|t| t.default_user.clone())
+}
+
+/// Whether the browser is logged in, its CSRF token and user if so. The page
+/// fetches this instead of having anything secret baked in.
+async fn login_state(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    if state.keys.is_empty() {
+        return Json(
+            serde_json::json!({ "logged_in": true, "csrf": null, "user": web_user(&state) }),
+        );
+    }
+    let csrf =
+        auth::cookie(&headers, auth::SESSION_COOKIE).and_then(|id| state.keys.web_session(id));
+    Json(serde_json::json!({
+        "logged_in": csrf.is_some(),
+        "csrf": csrf,
+        "user": csrf.is_some().then(|| web_user(&state)).flatten(),
+        "password": state.keys.has_password(),
+    }))
+}
+
+#[derive(Deserialize)]
+struct LoginRequest {
+    password: String,
+}
+
+async fn login(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<LoginRequest>,
+) -> Response {
+    if !state.keys.check_password(&req.password) {
+        tracing::warn!("Failed web login");
+        tokio::time::sleep(LOGIN_FAILURE_DELAY).await;
+        return unauthorized(StatusCode::UNAUTHORIZED);
+    }
+    tracing::info!("Web login with password");
+    logged_in(&state, &headers)
+}
+
+#[derive(Deserialize)]
+struct LoginLink {
+    token: String,
+}
+
+/// `GET /login?token=…`: redeem a one-time login link and go to the chat.
+async fn login_with_token(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(link): Query<LoginLink>,
+) -> Response {
+    if !state.keys.redeem_login_token(&link.token) {
+        tokio::time::sleep(LOGIN_FAILURE_DELAY).await;
+        return (StatusCode::UNAUTHORIZED, "Invalid or expired login link").into_response();
+    }
+    tracing::info!("Web login with one-time token");
+    let (id, _) = state.keys.start_web_session();
+    let cookie = session_cookie(&headers, &id, auth::WEB_SESSION_TTL.as_secs());
+    (
+        StatusCode::SEE_OTHER,
+        [(header::SET_COOKIE, cookie), (header::LOCATION, "/".into())],
+    )
+        .into_response()
+}
+
+/// `POST /login/token` (admin): mint a one-time login link for a browser.
+async fn issue_login_token(State(state): State<AppState>) -> impl IntoResponse {
+    let token = state.keys.issue_login_token();
+    Json(serde_json::json!({
+        "url": format!("/login?token={token}"),
+        "expires_in": auth::LOGIN_TOKEN_TTL.as_secs(),
     }))
 }
 
-async fn chat_page(State(state): State<AppState>) -> impl IntoResponse {
-    let token = state.api_key.as_deref().unwrap_or("");
-    let html = CHAT_HTML.replace("{{API_TOKEN}}", token);
-    axum::response::Html(html)
+async fn logout(State(state): State<AppState>, req: Request) -> Response {
+    if let Err(status) = web_principal(&state, &req) {
+        return unauthorized(status);
+    }
+    if let Some(id) = auth::cookie(req.headers(), auth::SESSION_COOKIE) {
+        state.keys.end_web_session(id);
+    }
+    (
+        [(header::SET_COOKIE, session_cookie(req.headers(), "", 0))],
+        Json(serde_json::json!({ "logged_in": false })),
+    )
+        .into_response()
+}
+
+async fn chat_page() -> impl IntoResponse {
+    axum::response::Html(CHAT_HTML)
 }
 
 const CHAT_HTML: &str = r##"<!DOCTYPE html>
@@ -140,7 +780,52 @@ const CHAT_HTML: &str = r##"<!DOCTYPE html>
   }
 }
 html,body{height:100%;font-family:-apple-system,BlinkMacSystemFont,"Segoe UI",Roboto,sans-serif;background:var(--bg-chat);color:var(--text)}
-#app{display:flex;flex-direction:column;height:100%;max-width:768px;margin:0 auto;background:var(--bg)}
+#layout{display:flex;height:100%}
+#sidebar{width:260px;flex-shrink:0;border-right:1px solid var(--border);background:var(--bg);display:flex;flex-direction:column}
+#sidebar .top{padding:12px;border-bottom:1px solid var(--border)}
+#new-chat{width:100%;padding:8px;border:1px solid var(--border);border-radius:8px;background:transparent;color:var(--text);font-size:13px;cursor:pointer}
+#new-chat:hover{background:var(--code-bg)}
+#session-list{flex:1;overflow-y:auto;padding:6px}
+.session{padding:8px 10px;border-radius:8px;cursor:pointer;font-size:13px}
+.session:hover{background:var(--code-bg)}
+.session.active{background:var(--bg-bot)}
+.session .name,.session .preview{white-space:nowrap;overflow:hidden;text-overflow:ellipsis}
+.session .name{font-weight:600}
+.session .preview{color:var(--text-muted);font-size:12px;margin-top:2px}
+#menu{display:none;border:none;background:none;color:var(--text);font-size:20px;cursor:pointer}
+@media(max-width:720px){
+  #sidebar{position:fixed;z-index:40;height:100%;transform:translateX(-100%);transition:transform .2s}
+  #sidebar.open{transform:none}
+  #menu{display:block}
+}
+#app{flex:1;min-width:0;display:flex;flex-direction:column;height:100%;background:var(--bg)}
+details.tool{align-self:flex-start;max-width:85%;margin-left:38px;font-size:12px;color:var(--text-muted)}
+details.tool summary{cursor:pointer}
+details.tool pre{white-space:pre-wrap;word-break:break-word;background:var(--code-bg);border:1px solid var(--code-border);padding:8px;border-radius:6px;margin-top:4px;max-height:240px;overflow:auto;color:var(--text)}
+#views{display:flex;gap:6px;margin-bottom:8px}
+#views button{flex:1;padding:6px;border:1px solid var(--border);border-radius:8px;background:transparent;color:var(--text-muted);font-size:13px;cursor:pointer}
+#views button.active{background:var(--bg-bot);color:var(--text)}
+#memory{flex:1;overflow-y:auto;padding:16px 20px;font-size:14px}
+#mem-tabs{display:flex;gap:4px;border-bottom:1px solid var(--border);margin-bottom:12px}
+#mem-tabs button{border:none;background:none;color:var(--text-muted);padding:6px 12px;cursor:pointer;font-size:13px;border-bottom:2px solid transparent}
+#mem-tabs button.active{color:var(--text);border-bottom-color:var(--bg-user)}
+.mem-row{display:flex;gap:6px;flex-wrap:wrap;margin-bottom:10px}
+.mem-row button,.mem-btn{padding:4px 10px;border:1px solid var(--border);border-radius:8px;background:transparent;color:var(--text);font-size:13px;cursor:pointer}
+.mem-row button.active{background:var(--bg-bot)}
+.mem-btn.primary{background:var(--bg-user);color:#fff;border-color:var(--bg-user)}
+#memory textarea,#memory input[type=search]{width:100%;border:1px solid var(--input-border);border-radius:8px;background:var(--input-bg);color:var(--text);font:13px/1.5 "SF Mono",Monaco,Consolas,monospace;padding:8px}
+#memory textarea{min-height:260px;resize:vertical}
+.mem-note{font-size:12px;color:var(--text-muted);margin:6px 0}
+.mem-doc{border:1px solid var(--border);border-radius:8px;padding:12px;margin-top:10px;line-height:1.5;white-space:pre-wrap;word-break:break-word}
+pre.diff{font:12px/1.4 "SF Mono",Monaco,Consolas,monospace;white-space:pre-wrap;background:var(--code-bg);border-radius:8px;padding:8px;margin-top:8px}
+pre.diff .add{color:#16a34a}pre.diff .del{color:#dc2626}pre.diff .hunk{color:var(--text-muted)}
+.cal{display:grid;grid-template-columns:repeat(7,1fr);gap:4px;max-width:360px}
+.cal div{text-align:center;font-size:12px;color:var(--text-muted);padding:4px}
+.cal button{padding:6px 0;border:1px solid var(--border);border-radius:6px;background:transparent;color:var(--text);cursor:pointer}
+.cal button:disabled{border-color:transparent;color:var(--text-muted);cursor:default;opacity:.5}
+.hit{border-bottom:1px solid var(--border);padding:8px 0;white-space:pre-wrap;font-size:13px}
+.hit .ctx{color:var(--text-muted)}
+#readonly{font-size:12px;color:var(--text-muted);text-align:center;margin-bottom:6px}
 header{padding:12px 20px;border-bottom:1px solid var(--header-border);background:var(--header-bg);display:flex;align-items:center;gap:10px;flex-shrink:0}
 header .avatar{width:32px;height:32px;border-radius:50%;background:linear-gradient(135deg,#6366f1,#8b5cf6);display:flex;align-items:center;justify-content:center;color:#fff;font-weight:700;font-size:14px}
 header h1{font-size:16px;font-weight:600}
@@ -186,76 +871,434 @@ header h1{font-size:16px;font-weight:600}
 #send{width:32px;height:32px;border-radius:50%;border:none;background:var(--bg-user);color:#fff;cursor:pointer;display:flex;align-items:center;justify-content:center;flex-shrink:0;transition:opacity .15s}
 #send:disabled{opacity:.4;cursor:default}
 #send svg{width:16px;height:16px}
+#attach{width:32px;height:32px;border-radius:50%;border:none;background:transparent;color:var(--text-muted);cursor:pointer;display:flex;align-items:center;justify-content:center;flex-shrink:0}
+#attach:hover{color:var(--text)}
+#attach svg{width:18px;height:18px}
+#files{display:flex;flex-wrap:wrap;gap:6px;margin-bottom:6px}
+#files:empty{display:none}
+.chip{display:flex;align-items:center;gap:4px;background:var(--code-bg);border:1px solid var(--code-border);border-radius:12px;padding:2px 8px;font-size:12px;color:var(--text)}
+.chip button{border:none;background:none;color:var(--text-muted);cursor:pointer;font-size:14px;line-height:1}
 .empty-state{display:flex;flex-direction:column;align-items:center;justify-content:center;flex:1;color:var(--text-muted);gap:12px}
 .empty-state .icon{width:48px;height:48px;border-radius:50%;background:linear-gradient(135deg,#6366f1,#8b5cf6);display:flex;align-items:center;justify-content:center;color:#fff;font-size:24px;font-weight:700}
 .empty-state p{font-size:14px}
+#logout{margin-left:auto;border:1px solid var(--border);background:transparent;color:var(--text-muted);border-radius:8px;padding:4px 10px;font-size:12px;cursor:pointer}
+#logout:hover{color:var(--text)}
+#login{position:fixed;inset:0;background:var(--bg-chat);display:flex;align-items:center;justify-content:center;z-index:50}
+#login form{display:flex;flex-direction:column;gap:12px;width:280px;padding:24px;background:var(--bg);border:1px solid var(--border);border-radius:16px}
+#login input{padding:8px 12px;border:1px solid var(--input-border);border-radius:8px;background:var(--input-bg);color:var(--text);font-size:14px}
+#login button{padding:8px;border:none;border-radius:8px;background:var(--bg-user);color:#fff;font-size:14px;cursor:pointer}
+#login p{font-size:13px;color:var(--text-muted)}
+.notice{position:fixed;top:16px;right:16px;max-width:360px;background:var(--bg);color:var(--text);border:1px solid var(--border);border-left:4px solid var(--bg-user);padding:10px 14px;border-radius:8px;font-size:13px;z-index:100;box-shadow:0 4px 12px rgba(0,0,0,.15);cursor:pointer;white-space:pre-wrap;animation:fadeIn .2s ease}
 .error-toast{position:fixed;top:16px;left:50%;transform:translateX(-50%);background:#ef4444;color:#fff;padding:10px 20px;border-radius:8px;font-size:13px;z-index:100;animation:fadeIn .2s ease}
 </style>
 </head>
 <body>
+<div id="layout">
+<aside id="sidebar">
+  <div class="top">
+    <div id="views"><button data-view="chat" class="active">会話</button><button data-view="memory">メモリ</button></div>
+    <button id="new-chat">＋ 新しい会話</button>
+  </div>
+  <div id="session-list"></div>
+</aside>
 <div id="app">
   <header>
+    <button id="menu" title="会話一覧">☰</button>
     <div class="avatar">1</div>
-    <h1>1koro</h1>
+    <h1 id="title">1koro</h1>
+    <button id="logout" hidden>ログアウト</button>
   </header>
-  <div id="messages">
-    <div class="empty-state" id="empty">
-      <div class="icon">1</div>
-      <p>1koro にメッセージを送ってみよう</p>
-    </div>
+  <div id="messages"></div>
+  <div id="memory" hidden>
+    <nav id="mem-tabs"><button data-tab="core">コア</button><button data-tab="logs">日次ログ</button><button data-tab="summaries">サマリー</button><button data-tab="search">検索</button></nav>
+    <div id="mem-body"></div>
   </div>
   <div id="input-area">
+    <p id="readonly" hidden>この会話は Web 以外のチャンネルのものなので閲覧のみです</p>
+    <div id="files"></div>
     <div id="input-wrap">
+      <button id="attach" title="ファイルを添付"><svg viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2" stroke-linecap="round" stroke-linejoin="round"><path d="M21.44 11.05l-9.19 9.19a6 6 0 0 1-8.49-8.49l9.19-9.19a4 4 0 0 1 5.66 5.66l-9.2 9.19a2 2 0 0 1-2.83-2.83l8.49-8.48"/></svg></button>
+      <input type="file" id="file-input" multiple hidden>
       <textarea id="input" rows="1" placeholder="メッセージを入力..."></textarea>
       <button id="send" disabled><svg viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2.5" stroke-linecap="round" stroke-linejoin="round"><line x1="22" y1="2" x2="11" y2="13"/><polygon points="22 2 15 22 11 13 2 9 22 2"/></svg></button>
     </div>
   </div>
 </div>
+</div>
+<div id="login" hidden>
+  <form id="login-form">
+    <h1>1koro</h1>
+    <input type="password" id="password" placeholder="パスワード" autocomplete="current-password">
+    <button type="submit">ログイン</button>
+    <p id="login-hint" hidden>パスワードが未設定です。<code>POST /login/token</code> でログインリンクを発行してください。</p>
+  </form>
+</div>
 <script>
 (function(){
-const TOKEN="{{API_TOKEN}}";
+let csrf=null;
+const loginEl=document.getElementById("login");
+const logoutBtn=document.getElementById("logout");
 const messagesEl=document.getElementById("messages");
 const inputEl=document.getElementById("input");
 const sendBtn=document.getElementById("send");
-const emptyEl=document.getElementById("empty");
-let sending=false;
+const fileInput=document.getElementById("file-input");
+const filesEl=document.getElementById("files");
+const sidebarEl=document.getElementById("sidebar");
+const listEl=document.getElementById("session-list");
+const titleEl=document.getElementById("title");
+const readonlyEl=document.getElementById("readonly");
+let USER="";
+let sending=false,files=[],current="",sessions=[];
+let ws=null,wsOpen=false,retry=1000,seq=0,run=null;
 
+function canSend(){return (inputEl.value.trim()||files.length)&&!sending&&wsOpen&&isWeb(current)}
+function updateSend(){sendBtn.disabled=run?false:!canSend()}
+function channelOf(key){return key.slice(0,key.indexOf(":"))}
+function isWeb(key){const c=channelOf(key);return c==="web"||c.startsWith("web#")}
+function nameOf(key){
+  const c=channelOf(key);
+  if(c==="web")return "Web";
+  return c.startsWith("web#")?c.slice(4):key;
+}
 inputEl.addEventListener("input",()=>{
   inputEl.style.height="auto";
   inputEl.style.height=Math.min(inputEl.scrollHeight,120)+"px";
-  sendBtn.disabled=!inputEl.value.trim()||sending;
+  updateSend();
+});
+document.getElementById("attach").addEventListener("click",()=>fileInput.click());
+fileInput.addEventListener("change",()=>{
+  files.push(...fileInput.files);fileInput.value="";renderFiles();
 });
+function renderFiles(){
+  filesEl.innerHTML="";
+  files.forEach((f,i)=>{
+    const c=document.createElement("span");c.className="chip";
+    c.textContent=f.name;
+    const x=document.createElement("button");x.textContent="×";
+    x.onclick=()=>{files.splice(i,1);renderFiles()};
+    c.appendChild(x);filesEl.appendChild(c);
+  });
+  updateSend();
+}
 inputEl.addEventListener("keydown",e=>{
   if(e.key==="Enter"&&!e.shiftKey&&!e.isComposing){e.preventDefault();doSend()}
 });
-sendBtn.addEventListener("click",doSend);
+sendBtn.addEventListener("click",()=>{
+  if(run)ws.send(JSON.stringify({type:"cancel",id:run.id}));else doSend();
+});
 
+function showLogin(hasPassword){
+  loginEl.hidden=false;logoutBtn.hidden=true;
+  document.getElementById("password").hidden=!hasPassword;
+  document.getElementById("login-hint").hidden=hasPassword;
+}
+function loggedIn(token,user){
+  csrf=token;USER=user;loginEl.hidden=true;logoutBtn.hidden=!token;
+  if(!ws)connect();
+  const params=new URLSearchParams(location.search);
+  const key=params.get("session");
+  selectSession(key&&key.includes(":")?key:"web:"+USER);
+  if(params.get("view")==="memory")showView("memory");
+}
+
+function showEmpty(){
+  messagesEl.innerHTML='<div class="empty-state" id="empty"><div class="icon">1</div><p>1koro にメッセージを送ってみよう</p></div>';
+}
+function loadSessions(){
+  return fetch("/sessions").then(r=>r.ok?r.json():{sessions:[]}).then(d=>{
+    sessions=d.sessions;renderSessions();
+  });
+}
+function renderSessions(){
+  listEl.innerHTML="";
+  const list=sessions.some(s=>s.key===current)?sessions:[{key:current,preview:""},...sessions];
+  list.forEach(s=>{
+    const el=document.createElement("div");
+    el.className="session"+(s.key===current?" active":"");
+    const n=document.createElement("div");n.className="name";n.textContent=nameOf(s.key);
+    const p=document.createElement("div");p.className="preview";p.textContent=s.preview||"";
+    el.appendChild(n);el.appendChild(p);
+    el.onclick=()=>{sidebarEl.classList.remove("open");selectSession(s.key)};
+    listEl.appendChild(el);
+  });
+}
+function selectSession(key){
+  current=key;
+  const url=new URL(location.href);url.searchParams.set("session",key);
+  history.replaceState(null,"",url);
+  titleEl.textContent=nameOf(key);
+  readonlyEl.hidden=isWeb(key);
+  inputEl.disabled=!isWeb(key);
+  updateSend();
+  showEmpty();renderSessions();
+  fetch("/sessions/"+encodeURIComponent(key)).then(r=>{
+    if(r.status===401){showLogin(true);return null}
+    return r.ok?r.json():null;
+  }).then(d=>{
+    if(d&&current===key)renderHistory(d.messages);
+  });
+  loadSessions();
+  inputEl.focus();
+}
+function renderHistory(msgs){
+  if(!msgs.length)return;
+  messagesEl.innerHTML="";
+  const calls={};
+  msgs.forEach(m=>{
+    if(m.role==="user")addMsg("user",m.text);
+    else if(m.role==="assistant"){
+      if(m.text)addMsg("bot",m.text);
+      m.tool_calls.forEach(tc=>{calls[tc.id]=addTool(tc.name,tc.arguments)});
+    }else if(m.role==="tool"){
+      const d=calls[m.tool_call_id]||addTool("tool","");
+      const pre=document.createElement("pre");pre.textContent=m.text;d.appendChild(pre);
+    }
+  });
+  messagesEl.scrollTop=messagesEl.scrollHeight;
+}
+function addTool(name,args){
+  const d=document.createElement("details");d.className="tool";
+  const s=document.createElement("summary");s.textContent="🔧 "+name;d.appendChild(s);
+  if(args){const pre=document.createElement("pre");pre.textContent=args;d.appendChild(pre)}
+  messagesEl.appendChild(d);
+  return d;
+}
+// --- Memory browser ---
+const memoryEl=document.getElementById("memory");
+const memBody=document.getElementById("mem-body");
+const inputArea=document.getElementById("input-area");
+function api(path,opts){
+  return fetch(path,opts).then(r=>r.json().catch(()=>({})).then(d=>{
+    if(r.status===401)showLogin(true);
+    if(!r.ok)throw new Error(d.error||r.status+" "+r.statusText);
+    return d;
+  }));
+}
+function el(tag,cls,text){const e=document.createElement(tag);if(cls)e.className=cls;if(text!==undefined)e.textContent=text;return e}
+function doc(md){const d=el("div","mem-doc");d.innerHTML=renderMd(md);return d}
+function row(items,onPick,active){
+  const r=el("div","mem-row");
+  items.forEach(i=>{const b=el("button",i===active?"active":"",i);b.onclick=()=>onPick(i);r.appendChild(b)});
+  return r;
+}
+function showView(v){
+  document.querySelectorAll("#views button").forEach(b=>b.classList.toggle("active",b.dataset.view===v));
+  const mem=v==="memory";
+  memoryEl.hidden=!mem;messagesEl.hidden=mem;inputArea.hidden=mem;
+  document.getElementById("new-chat").hidden=mem;listEl.hidden=mem;
+  titleEl.textContent=mem?"メモリ":nameOf(current);
+  const url=new URL(location.href);
+  if(mem)url.searchParams.set("view","memory");else url.searchParams.delete("view");
+  history.replaceState(null,"",url);
+  if(mem&&!memBody.childElementCount)memTab("core");
+}
+function memTab(t){
+  document.querySelectorAll("#mem-tabs button").forEach(b=>b.classList.toggle("active",b.dataset.tab===t));
+  memBody.innerHTML="";
+  ({core:coreTab,logs:logsTab,summaries:summariesTab,search:searchTab})[t]();
+}
+function coreTab(file){
+  api("/memory/core").then(d=>{
+    file=file||d.files[0].file;
+    memBody.innerHTML="";
+    memBody.appendChild(row(d.files.map(f=>f.file),coreTab,file));
+    const info=d.files.find(f=>f.file===file);
+    return api("/memory/core/"+encodeURIComponent(file)).then(c=>{
+      const ta=el("textarea");ta.value=c.content;
+      const note=el("p","mem-note",info.llm_writable?"エージェントも更新するファイルです":"エージェントは読み取りのみ。編集には admin 権限が必要です");
+      const save=el("button","mem-btn primary","保存");
+      save.onclick=()=>api("/memory/core/"+encodeURIComponent(file),{
+        method:"PUT",headers:{"Content-Type":"application/json",...(csrf?{"X-CSRF-Token":csrf}:{})},
+        body:JSON.stringify({content:ta.value})
+      }).then(()=>coreTab(file)).catch(e=>showError(e.message));
+      memBody.append(note,ta,save,el("h3","mem-note","変更履歴"));
+      if(!c.history.length)memBody.appendChild(el("p","mem-note","まだ変更はありません"));
+      const diff=el("pre","diff");diff.hidden=true;
+      const hist=el("div","mem-row");
+      c.history.forEach(v=>{
+        const b=el("button","",v.replace(/^(\d{4})(\d\d)(\d\d)T(\d\d)(\d\d)(\d\d)/,"$1-$2-$3 $4:$5:$6"));
+        b.onclick=()=>api("/memory/core/"+encodeURIComponent(file)+"/history/"+v).then(h=>{
+          diff.innerHTML="";diff.hidden=false;
+          h.diff.split("\n").forEach(l=>{
+            const cls=l.startsWith("+")?"add":l.startsWith("-")?"del":l.startsWith("@@")?"hunk":"";
+            diff.appendChild(el("span",cls,l+"\n"));
+          });
+        }).catch(e=>showError(e.message));
+        hist.appendChild(b);
+      });
+      memBody.append(hist,diff);
+    });
+  }).catch(e=>showError(e.message));
+}
+function logsTab(){
+  api("/memory/logs").then(d=>{
+    const dates=new Set(d.dates);
+    const last=d.dates.length?d.dates[d.dates.length-1]:new Date().toISOString().slice(0,10);
+    let y=+last.slice(0,4),m=+last.slice(5,7)-1;
+    const head=el("div","mem-row"),cal=el("div","cal"),view=el("div");
+    const prev=el("button","","‹"),next=el("button","","›"),label=el("span","mem-note");
+    head.append(prev,label,next);
+    function draw(){
+      cal.innerHTML="";label.textContent=y+"年"+(m+1)+"月";
+      "日月火水木金土".split("").forEach(w=>cal.appendChild(el("div","",w)));
+      const first=new Date(y,m,1).getDay(),days=new Date(y,m+1,0).getDate();
+      for(let i=0;i<first;i++)cal.appendChild(el("div"));
+      for(let day=1;day<=days;day++){
+        const date=y+"-"+String(m+1).padStart(2,"0")+"-"+String(day).padStart(2,"0");
+        const b=el("button","",day);b.disabled=!dates.has(date);
+        b.onclick=()=>api("/memory/logs/"+date).then(l=>{
+          view.innerHTML="";view.append(el("h3","mem-note",date),doc(l.content));
+        }).catch(e=>showError(e.message));
+        cal.appendChild(b);
+      }
+    }
+    prev.onclick=()=>{if(--m<0){m=11;y--}draw()};
+    next.onclick=()=>{if(++m>11){m=0;y++}draw()};
+    draw();memBody.append(head,cal,view);
+  }).catch(e=>showError(e.message));
+}
+function summariesTab(){
+  const view=el("div");
+  ["monthly","weekly"].forEach(p=>api("/memory/summaries/"+p).then(d=>{
+    memBody.insertBefore(el("h3","mem-note",p==="monthly"?"月次":"週次"),view);
+    memBody.insertBefore(row(d.ids.slice().reverse(),id=>api("/memory/summaries/"+p+"/"+id).then(s=>{
+      view.innerHTML="";view.append(el("h3","mem-note",id),doc(s.content));
+    }).catch(e=>showError(e.message))),view);
+  }).catch(e=>showError(e.message)));
+  memBody.appendChild(view);
+}
+function searchTab(){
+  const form=el("form"),q=el("input"),results=el("div");
+  q.type="search";q.placeholder="ログを検索...";form.appendChild(q);
+  form.onsubmit=e=>{
+    e.preventDefault();
+    api("/memory/search?context=1&limit=50&query="+encodeURIComponent(q.value)).then(d=>{
+      results.innerHTML="";
+      if(!d.hits.length)results.appendChild(el("p","mem-note","見つかりませんでした"));
+      d.hits.forEach(h=>{
+        const div=el("div","hit");
+        h.before.forEach(e=>div.appendChild(el("div","ctx",e.text)));
+        div.appendChild(el("div","",h.entry.date+" "+h.entry.text));
+        h.after.forEach(e=>div.appendChild(el("div","ctx",e.text)));
+        results.appendChild(div);
+      });
+    }).catch(e=>showError(e.message));
+  };
+  memBody.append(form,results);q.focus();
+}
+document.querySelectorAll("#views button").forEach(b=>b.onclick=()=>showView(b.dataset.view));
+document.querySelectorAll("#mem-tabs button").forEach(b=>b.onclick=()=>memTab(b.dataset.tab));
+
+document.getElementById("menu").addEventListener("click",()=>sidebarEl.classList.toggle("open"));
+document.getElementById("new-chat").addEventListener("click",()=>{
+  const name=(prompt("会話の名前")||"").trim().replace(/[:#\s]+/g,"-");
+  if(name)selectSession("web#"+name+":"+USER);
+  sidebarEl.classList.remove("open");
+});
+fetch("/login/state").then(r=>r.json()).then(s=>{
+  if(s.logged_in)loggedIn(s.csrf,s.user);else showLogin(s.password);
+});
+document.getElementById("login-form").addEventListener("submit",e=>{
+  e.preventDefault();
+  const pw=document.getElementById("password");
+  fetch("/login",{method:"POST",headers:{"Content-Type":"application/json"},body:JSON.stringify({password:pw.value})})
+    .then(r=>{if(!r.ok)throw new Error("ログインできませんでした");return r.json()})
+    .then(d=>{pw.value="";loggedIn(d.csrf,d.user)})
+    .catch(e=>showError(e.message));
+});
+logoutBtn.addEventListener("click",()=>{
+  fetch("/logout",{method:"POST",headers:{"X-CSRF-Token":csrf||""}}).finally(()=>location.reload());
+});
+
+// --- WebSocket ---
+const SEND_ICON=sendBtn.innerHTML;
+const STOP_ICON='<svg viewBox="0 0 24 24" fill="currentColor"><rect x="6" y="6" width="12" height="12" rx="2"/></svg>';
+function connect(){
+  ws=new WebSocket((location.protocol==="https:"?"wss://":"ws://")+location.host+"/ws");
+  let opened=false;
+  ws.onopen=()=>{opened=true;wsOpen=true;retry=1000;updateSend()};
+  ws.onmessage=e=>onFrame(JSON.parse(e.data));
+  ws.onclose=()=>{
+    wsOpen=false;
+    if(run){run.typing.remove();finishRun();showError("接続が切れました")}
+    if(!opened)fetch("/login/state").then(r=>r.json()).then(s=>{if(!s.logged_in)showLogin(s.password)});
+    setTimeout(connect,retry);retry=Math.min(retry*2,30000);
+  };
+}
+function onFrame(f){
+  if(f.type==="notification"){showNotice(f.text);return}
+  if(f.type==="error"&&!f.id){showError(f.message);return}
+  if(!run||f.id!==run.id)return;
+  const visible=current===run.key;
+  switch(f.type){
+  case "delta":
+    if(!visible)break;
+    if(!run.bubble){run.bubble=addMsg("bot","");run.text=""}
+    run.text+=f.text;run.bubble.innerHTML=renderMd(run.text);
+    messagesEl.appendChild(run.typing);messagesEl.scrollTop=messagesEl.scrollHeight;
+    break;
+  case "tool_call":
+    if(!visible)break;
+    run.bubble=null;run.tools[f.call_id]=addTool(f.name,f.arguments);
+    messagesEl.appendChild(run.typing);
+    break;
+  case "tool_result":{
+    const d=run.tools[f.call_id];
+    if(d){const pre=document.createElement("pre");pre.textContent=f.output;d.appendChild(pre)}
+    break;
+  }
+  case "done":
+    run.typing.remove();
+    if(visible){
+      const text=f.text||"(no response)";
+      if(run.bubble)run.bubble.innerHTML=renderMd(text);else addMsg("bot",text);
+    }
+    finishRun();loadSessions();
+    break;
+  case "cancelled":
+    run.typing.remove();
+    if(visible)addMsg("bot","（キャンセルしました）");
+    finishRun();
+    break;
+  case "error":
+    run.typing.remove();showError("Error: "+f.message);finishRun();
+    break;
+  }
+}
+function finishRun(){
+  run=null;sending=false;
+  sendBtn.innerHTML=SEND_ICON;sendBtn.title="";
+  updateSend();
+  inputEl.focus();
+}
+function readFile(f){
+  return new Promise((ok,fail)=>{
+    const r=new FileReader();
+    r.onload=()=>ok({filename:f.name,mime_type:f.type||null,data:r.result});
+    r.onerror=()=>fail(r.error);
+    r.readAsDataURL(f);
+  });
+}
 function doSend(){
   const text=inputEl.value.trim();
-  if(!text||sending)return;
-  if(emptyEl)emptyEl.remove();
-  addMsg("user",text);
+  if(!canSend())return;
+  const empty=document.getElementById("empty");
+  if(empty)empty.remove();
+  const key=current,channel=channelOf(key);
+  const names=files.map(f=>"📎 "+f.name);
+  addMsg("user",[text,...names].filter(Boolean).join("\n"));
+  const pending=files;
+  files=[];renderFiles();
   inputEl.value="";inputEl.style.height="auto";
-  sendBtn.disabled=true;sending=true;
-  const typing=showTyping();
-  fetch("/message",{
-    method:"POST",
-    headers:{"Content-Type":"application/json",
-      ...(TOKEN?{"Authorization":"Bearer "+TOKEN}:{})},
-    body:JSON.stringify({text,channel:"web",user:"masaki"})
-  }).then(r=>{
-    if(!r.ok)throw new Error(r.status+" "+r.statusText);
-    return r.json();
-  }).then(d=>{
-    typing.remove();
-    addMsg("bot",d.text||"(no response)");
+  sending=true;
+  run={id:String(++seq),key,typing:showTyping(),bubble:null,text:"",tools:{}};
+  sendBtn.innerHTML=STOP_ICON;sendBtn.title="停止";updateSend();
+  const id=run.id;
+  Promise.all(pending.map(readFile)).then(attachments=>{
+    ws.send(JSON.stringify({type:"message",id,text,channel,user:key.slice(channel.length+1),attachments}));
   }).catch(e=>{
-    typing.remove();
+    if(run&&run.id===id){run.typing.remove();finishRun()}
     showError("Error: "+e.message);
-  }).finally(()=>{
-    sending=false;
-    sendBtn.disabled=!inputEl.value.trim();
-    inputEl.focus();
   });
 }
 
@@ -271,6 +1314,7 @@ function addMsg(role,text){
   wrap.appendChild(av);wrap.appendChild(bub);
   messagesEl.appendChild(wrap);
   messagesEl.scrollTop=messagesEl.scrollHeight;
+  return bub;
 }
 
 function showTyping(){
@@ -282,6 +1326,14 @@ function showTyping(){
   return el;
 }
 
+function showNotice(msg){
+  const el=document.createElement("div");
+  el.className="notice";el.textContent="🔔 "+msg;
+  el.onclick=()=>el.remove();
+  document.body.appendChild(el);
+  setTimeout(()=>el.remove(),15000);
+}
+
 function showError(msg){
   const el=document.createElement("div");
   el.className="error-toast";el.textContent=msg;
@@ -344,7 +1396,8 @@ function renderMd(src){
   h=h.replace(/`([^`]+)`/g,"<code>$1</code>");
   h=h.replace(/\*\*(.+?)\*\*/g,"<strong>$1</strong>");
   h=h.replace(/(?<!\*)\*([^*]+)\*(?!\*)/g,"<em>$1</em>");
-  h=h.replace(/\[([^\]]+)\]\(([^)]+)\)/g,'<a href="$2" target="_blank" rel="noopener">$1</a>');
+  // links: only web and mail URLs, so logged text can't plant javascript: hrefs
+  h=h.replace(/\[([^\]]+)\]\(([^)\s]+)\)/g,(m,text,url)=>/^(https?:\/\/|mailto:)/i.test(url)?'<a href="'+url+'" target="_blank" rel="noopener">'+text+'</a>':m);
   // restore code blocks
   cb.forEach((b,i)=>{h=h.replace("\x00CB"+i+"\x00",b);});
   // paragraphs (skip block elements)
@@ -363,3 +1416,216 @@ inputEl.focus();
 </script>
 </body>
 </html>"##;
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::config::{Config, DEFAULT_TENANT};
+
+    /// A server over a fresh default tenant in `root`, with `api` appended to
+    /// its config (usually `[[api.keys]]` tables).
+    pub(crate) async fn state(root: &std::path::Path, api: &str) -> AppState {
+        let _ = std::fs::remove_dir_all(root);
+        let cfg: Config = toml::from_str(&format!(
+            "[agent]\nname = \"1koro\"\n[llm]\nmodel = \"m\"\napi_key = \"k\"\nbase_url = \"http://127.0.0.1:9\"\n[memory]\nbase_dir = \"{}\"\n{api}",
+            root.display()
+        ))
+        .unwrap();
+        let memory = cfg.tenant_memory(DEFAULT_TENANT).unwrap();
+        crate::config::init_memory_dir(&memory.base_dir)
+            .await
+            .unwrap();
+        let llm = crate::llm::create_client(&cfg.llm).unwrap();
+        let limits = Arc::new(Limiter::new(&cfg.api));
+        let tenant = Tenant::open(DEFAULT_TENANT, &cfg, &memory, llm, limits.clone()).unwrap();
+        AppState {
+            tenants: Arc::new(Tenants::new([tenant])),
+            limits,
+            name: "1koro".into(),
+            keys: Arc::new(KeyStore::new(&cfg.api)),
+            config_path: root.join("config.toml"),
+        }
+    }
+
+    /// Send one request through the router; `headers` are added as given.
+    pub(crate) async fn call(
+        app: &Router,
+        method: Method,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: Option<serde_json::Value>,
+    ) -> (StatusCode, serde_json::Value) {
+        use tower::ServiceExt;
+
+        let mut req = axum::http::Request::builder().method(method).uri(path);
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        let req = match body {
+            Some(body) => req
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(axum::body::Body::from(body.to_string())),
+            None => req.body(axum::body::Body::empty()),
+        }
+        .unwrap();
+        let resp = app.clone().oneshot(req).await.unwrap();
+        let status = resp.status();
+        let bytes = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (
+            status,
+            serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null),
+        )
+    }
+
+    const KEYS: &str = r#"
+        [[api.keys]]
+        name = "bot"
+        key = "message-key"
+        scopes = ["message"]
+        [[api.keys]]
+        name = "reader"
+        key = "reader-key"
+        scopes = ["sessions:read"]
+    "#;
+
+    #[test]
+    fn test_required_scope() {
+        for path in ["/", "/health", "/login", "/login/state", "/logout"] {
+            assert_eq!(required_scope(path), None, "{path}");
+        }
+        for path in [
+            "/message",
+            "/ws",
+            "/jobs/1",
+            "/v1/models",
+            "/sessions/cli:a/cancel",
+        ] {
+            assert_eq!(required_scope(path), Some(Scope::Message), "{path}");
+        }
+        for path in [
+            "/facts",
+            "/export",
+            "/sessions",
+            "/sessions/cli:a",
+            "/memory/core",
+        ] {
+            assert_eq!(required_scope(path), Some(Scope::SessionsRead), "{path}");
+        }
+        for path in ["/login/token", "/backup", "/unknown"] {
+            assert_eq!(required_scope(path), Some(Scope::Admin), "{path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_key_scopes() {
+        let root = std::env::temp_dir().join(format!("1koro-api-scopes-{}", std::process::id()));
+        let app = router(state(&root, KEYS).await);
+        let bearer = |key: &str| format!("Bearer {key}");
+
+        let (status, _) = call(&app, Method::GET, "/sessions", &[], None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let wrong = bearer("nope");
+        let (status, _) = call(
+            &app,
+            Method::GET,
+            "/sessions",
+            &[("authorization", &wrong)],
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let message = bearer("message-key");
+        for path in ["/memory/core", "/memory/logs", "/sessions", "/facts"] {
+            let (status, _) = call(
+                &app,
+                Method::GET,
+                path,
+                &[("authorization", &message)],
+                None,
+            )
+            .await;
+            assert_eq!(status, StatusCode::FORBIDDEN, "message key on {path}");
+        }
+        let reader = bearer("reader-key");
+        let (status, body) = call(
+            &app,
+            Method::GET,
+            "/sessions",
+            &[("authorization", &reader)],
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body["sessions"].is_array());
+        let (status, _) = call(
+            &app,
+            Method::POST,
+            "/login/token",
+            &[("authorization", &reader)],
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "minting logins needs admin");
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_cookie_login_needs_csrf() {
+        let root = std::env::temp_dir().join(format!("1koro-api-csrf-{}", std::process::id()));
+        let state = state(&root, KEYS).await;
+        let (id, csrf) = state.keys.start_web_session();
+        let app = router(state);
+        let cookie = format!("{}={id}", auth::SESSION_COOKIE);
+        let cancel = "/sessions/cli:masaki/cancel";
+
+        let (status, _) = call(&app, Method::GET, "/sessions", &[("cookie", &cookie)], None).await;
+        assert_eq!(status, StatusCode::OK, "reads need no CSRF token");
+        let (status, _) = call(&app, Method::POST, cancel, &[("cookie", &cookie)], None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let forged = [("cookie", cookie.as_str()), ("x-csrf-token", "guess")];
+        let (status, _) = call(&app, Method::POST, cancel, &forged, None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let valid = [("cookie", cookie.as_str()), ("x-csrf-token", csrf.as_str())];
+        let (status, body) = call(&app, Method::POST, cancel, &valid, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["cancelled"], 0);
+
+        let (_, body) = call(
+            &app,
+            Method::GET,
+            "/login/state",
+            &[("cookie", &cookie)],
+            None,
+        )
+        .await;
+        assert_eq!(body["user"], crate::config::FALLBACK_USER);
+
+        let stale = format!("{}=not-a-session", auth::SESSION_COOKIE);
+        let (status, _) = call(&app, Method::GET, "/sessions", &[("cookie", &stale)], None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_messages_default_to_tenant_user() {
+        let root = std::env::temp_dir().join(format!("1koro-api-user-{}", std::process::id()));
+        let app = router(state(&root, KEYS).await);
+        let auth = [("authorization", "Bearer message-key")];
+        let job = |user: Option<&str>| {
+            let mut body = serde_json::json!({ "text": "hi", "async": true });
+            if let Some(user) = user {
+                body["user"] = user.into();
+            }
+            call(&app, Method::POST, "/message", &auth, Some(body))
+        };
+        let (status, body) = job(None).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(body["user"], crate::config::FALLBACK_USER);
+        let (_, body) = job(Some("ken")).await;
+        assert_eq!(body["user"], "ken");
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
@@ -0,0 +1,360 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as B64;
+use chrono::{Local, NaiveDate};
+
+use crate::crypto::{self, Cipher};
+use crate::llm::{ContentPart, ImageUrl, InputAudio};
+use crate::trust;
+
+/// Largest single attachment accepted.
+pub const MAX_ATTACHMENT_BYTES: usize = 20 * 1024 * 1024;
+/// Extracted text beyond this is cut off before it reaches the model.
+const MAX_EXTRACTED_CHARS: usize = 20_000;
+/// Media parts in sessions point at the stored file instead of embedding
+/// the data; `resolve_media` inlines them just before calling the LLM.
+const URL_SCHEME: &str = "attachment:";
+
+/// An uploaded file as received from the API.
+pub struct Attachment {
+    pub filename: String,
+    pub mime_type: String,
+    pub data: Vec<u8>,
+}
+
+impl Attachment {
+    /// Decode a base64 upload. `mime_type` is guessed from the file name
+    /// when absent.
+    pub fn from_base64(filename: &str, mime_type: Option<&str>, data: &str) -> Result<Self> {
+        // Accept data URLs as well as bare base64
+        let data = data.split_once(";base64,").map_or(data, |(_, d)| d);
+        let data = B64
+            .decode(data.trim())
+            .with_context(|| format!("Attachment {filename} is not valid base64"))?;
+        Ok(Self {
+            filename: filename.to_string(),
+            mime_type: mime_type
+                .map(str::to_string)
+                .unwrap_or_else(|| guess_mime(filename).into()),
+            data,
+        })
+    }
+}
+
+/// An attachment after it has been written under `files/`.
+pub struct StoredAttachment {
+    /// Path relative to the memory directory, e.g. `files/2026-02-17/a.pdf`.
+    pub path: String,
+    pub mime_type: String,
+    pub size: usize,
+    /// Text pulled out of text, Markdown and PDF files.
+    pub text: Option<String>,
+}
+
+impl StoredAttachment {
+    pub fn is_image(&self) -> bool {
+        matches!(
+            self.mime_type.as_str(),
+            "image/png" | "image/jpeg" | "image/gif" | "image/webp"
+        )
+    }
+
+    /// Reference line for the daily log.
+    pub fn log_line(&self) -> String {
+        format!(
+            "[attachment: {} ({}, {})]",
+            self.path,
+            self.mime_type,
+            human_size(self.size)
+        )
+    }
+
+    /// `wav` or `mp3` for audio the model can take as `input_audio`.
+    fn audio_format(&self) -> Option<&'static str> {
+        match self.mime_type.as_str() {
+            "audio/wav" | "audio/x-wav" | "audio/wave" => Some("wav"),
+            "audio/mpeg" | "audio/mp3" => Some("mp3"),
+            _ => None,
+        }
+    }
+
+    /// Content parts describing this attachment to the model. Images and
+    /// audio are only sent as media parts when the model supports them.
+    pub fn content_parts(&self, vision: bool, audio: bool) -> Vec<ContentPart> {
+        let mut parts = vec![ContentPart::Text {
+            text: match &self.text {
+                Some(t) => format!("{}\n\n{}", self.log_line(), trust::wrap("attachment", t)),
+                None => self.log_line(),
+            },
+        }];
+        if vision && self.is_image() {
+            parts.push(ContentPart::ImageUrl {
+                image_url: ImageUrl {
+                    url: format!("{URL_SCHEME}{}", self.path),
+                },
+            });
+        }
+        if audio && let Some(format) = self.audio_format() {
+            parts.push(ContentPart::InputAudio {
+                input_audio: InputAudio {
+                    data: format!("{URL_SCHEME}{}", self.path),
+                    format: format.into(),
+                },
+            });
+        }
+        parts
+    }
+}
+
+pub fn guess_mime(filename: &str) -> &'static str {
+    let ext = filename
+        .rsplit_once('.')
+        .map(|(_, e)| e.to_ascii_lowercase());
+    match ext.as_deref() {
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("pdf") => "application/pdf",
+        Some("md" | "markdown") => "text/markdown",
+        Some("txt" | "log") => "text/plain",
+        Some("csv") => "text/csv",
+        Some("json") => "application/json",
+        Some("wav") => "audio/wav",
+        Some("mp3") => "audio/mpeg",
+        _ => "application/octet-stream",
+    }
+}
+
+fn human_size(n: usize) -> String {
+    match n {
+        n if n >= 1024 * 1024 => format!("{:.1} MB", n as f64 / (1024.0 * 1024.0)),
+        n if n >= 1024 => format!("{} KB", n / 1024),
+        n => format!("{n} B"),
+    }
+}
+
+/// Keep the base name and only characters that are safe in a path.
+fn sanitize_filename(name: &str) -> String {
+    let base = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let clean: String = base
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let clean = clean.trim_start_matches('.');
+    if clean.is_empty() {
+        "file".into()
+    } else {
+        clean.chars().take(100).collect()
+    }
+}
+
+/// A path under `dir` named `name` that does not exist yet.
+fn unique_path(dir: &Path, name: &str) -> PathBuf {
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((s, e)) if !s.is_empty() => (s, format!(".{e}")),
+        _ => (name, String::new()),
+    };
+    let mut path = dir.join(name);
+    let mut n = 1;
+    while path.exists() {
+        path = dir.join(format!("{stem}-{n}{ext}"));
+        n += 1;
+    }
+    path
+}
+
+fn extract_text(mime_type: &str, data: &[u8]) -> Option<String> {
+    let text = match mime_type {
+        "application/pdf" => {
+            // pdf-extract panics on some malformed files
+            let data = data.to_vec();
+            std::panic::catch_unwind(move || pdf_extract::extract_text_from_mem(&data))
+                .ok()?
+                .ok()?
+        }
+        m if m.starts_with("text/") || m == "application/json" => {
+            String::from_utf8_lossy(data).into_owned()
+        }
+        _ => return None,
+    };
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+    Some(match text.char_indices().nth(MAX_EXTRACTED_CHARS) {
+        Some((i, _)) => format!("{}\n[... truncated]", &text[..i]),
+        None => text.to_string(),
+    })
+}
+
+/// Write an attachment to `files/<date>/` (encrypted if a key is set) and
+/// extract its text.
+pub fn store(
+    base_dir: &Path,
+    cipher: Option<&Cipher>,
+    date: NaiveDate,
+    att: &Attachment,
+) -> Result<StoredAttachment> {
+    if att.data.len() > MAX_ATTACHMENT_BYTES {
+        bail!(
+            "Attachment {} is larger than {}",
+            att.filename,
+            human_size(MAX_ATTACHMENT_BYTES)
+        );
+    }
+    let rel_dir = format!("files/{}", date.format("%Y-%m-%d"));
+    let dir = base_dir.join(&rel_dir);
+    std::fs::create_dir_all(&dir)?;
+    let path = unique_path(&dir, &sanitize_filename(&att.filename));
+    crypto::write_bytes(cipher, &path, &att.data)?;
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    Ok(StoredAttachment {
+        path: format!("{rel_dir}/{name}"),
+        mime_type: att.mime_type.clone(),
+        size: att.data.len(),
+        text: extract_text(&att.mime_type, &att.data),
+    })
+}
+
+/// Store every attachment of a message under today's date.
+pub async fn store_all(
+    base_dir: &Path,
+    cipher: Option<std::sync::Arc<Cipher>>,
+    attachments: Vec<Attachment>,
+) -> Result<Vec<StoredAttachment>> {
+    let base_dir = base_dir.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let today = Local::now().date_naive();
+        attachments
+            .iter()
+            .map(|a| store(&base_dir, cipher.as_deref(), today, a))
+            .collect()
+    })
+    .await?
+}
+
+fn read_stored(base_dir: &Path, cipher: Option<&Cipher>, rel: &str) -> Option<Vec<u8>> {
+    if rel.starts_with("files/") && !rel.contains("..") {
+        crypto::read_bytes(cipher, &base_dir.join(rel)).ok()
+    } else {
+        None
+    }
+}
+
+/// Replace `attachment:` references in image and audio parts with the
+/// stored data, inline as base64.
+pub fn resolve_media(
+    base_dir: &Path,
+    cipher: Option<&Cipher>,
+    parts: &[ContentPart],
+) -> Vec<ContentPart> {
+    let missing = |rel: &str| ContentPart::Text {
+        text: format!("[attachment no longer available: {rel}]"),
+    };
+    parts
+        .iter()
+        .map(|p| match p {
+            ContentPart::ImageUrl { image_url } => {
+                let Some(rel) = image_url.url.strip_prefix(URL_SCHEME) else {
+                    return p.clone();
+                };
+                match read_stored(base_dir, cipher, rel) {
+                    Some(data) => ContentPart::ImageUrl {
+                        image_url: ImageUrl {
+                            url: format!("data:{};base64,{}", guess_mime(rel), B64.encode(data)),
+                        },
+                    },
+                    None => missing(rel),
+                }
+            }
+            ContentPart::InputAudio { input_audio } => {
+                let Some(rel) = input_audio.data.strip_prefix(URL_SCHEME) else {
+                    return p.clone();
+                };
+                match read_stored(base_dir, cipher, rel) {
+                    Some(data) => ContentPart::InputAudio {
+                        input_audio: InputAudio {
+                            data: B64.encode(data),
+                            format: input_audio.format.clone(),
+                        },
+                    },
+                    None => missing(rel),
+                }
+            }
+            ContentPart::Text { .. } => p.clone(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_filename() {
+        assert_eq!(sanitize_filename("../../etc/passwd"), "passwd");
+        assert_eq!(sanitize_filename("C:\\Users\\me\\写真 1.jpg"), "___1.jpg");
+        assert_eq!(sanitize_filename(".env"), "env");
+        assert_eq!(sanitize_filename(""), "file");
+    }
+
+    #[test]
+    fn test_store_and_resolve() {
+        let base = std::env::temp_dir().join(format!("1koro-attach-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&base);
+        let date = NaiveDate::from_ymd_opt(2026, 2, 17).unwrap();
+        let cipher = Cipher::new(b"test key for attachments", b"0123456789abcdef").unwrap();
+
+        let note =
+            Attachment::from_base64("notes.md", None, &B64.encode("# Plan\n\nship it")).unwrap();
+        let a = store(&base, Some(&cipher), date, &note).unwrap();
+        let b = store(&base, Some(&cipher), date, &note).unwrap();
+        assert_eq!(a.path, "files/2026-02-17/notes.md");
+        assert_eq!(b.path, "files/2026-02-17/notes-1.md");
+        assert_eq!(a.mime_type, "text/markdown");
+        assert_eq!(a.text.as_deref(), Some("# Plan\n\nship it"));
+        assert!(crypto::is_encrypted(
+            &std::fs::read(base.join(&a.path)).unwrap()
+        ));
+
+        let png = Attachment {
+            filename: "photo.png".into(),
+            mime_type: "image/png".into(),
+            data: vec![0x89, b'P', b'N', b'G'],
+        };
+        let img = store(&base, Some(&cipher), date, &png).unwrap();
+        assert!(img.text.is_none());
+        assert_eq!(img.content_parts(false, true).len(), 1);
+        let parts = resolve_media(&base, Some(&cipher), &img.content_parts(true, false));
+        let ContentPart::ImageUrl { image_url } = &parts[1] else {
+            panic!("expected image part");
+        };
+        assert_eq!(image_url.url, "data:image/png;base64,iVBORw==");
+
+        let wav = Attachment::from_base64("memo.wav", None, "UklGRg==").unwrap();
+        let memo = store(&base, Some(&cipher), date, &wav).unwrap();
+        let parts = resolve_media(&base, Some(&cipher), &memo.content_parts(true, true));
+        assert_eq!(
+            parts[1],
+            ContentPart::InputAudio {
+                input_audio: InputAudio {
+                    data: "UklGRg==".into(),
+                    format: "wav".into(),
+                },
+            }
+        );
+        std::fs::remove_file(base.join(&memo.path)).unwrap();
+        let parts = resolve_media(&base, Some(&cipher), &memo.content_parts(true, true));
+        assert!(matches!(&parts[1], ContentPart::Text { text } if text.contains("no longer")));
+        let _ = std::fs::remove_dir_all(base);
+    }
+}
@@ -0,0 +1,377 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use axum::http::HeaderMap;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::config::{self, ApiConfig, ApiKeyConfig};
+use crate::crypto;
+
+/// Cookie holding the id of a browser login.
+pub const SESSION_COOKIE: &str = "1koro_session";
+pub const WEB_SESSION_TTL: Duration = Duration::from_secs(30 * 24 * 3600);
+pub const LOGIN_TOKEN_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// What an API key may do. `admin` implies every other scope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Scope {
+    /// `POST /message`, jobs and the OpenAI-compatible endpoints.
+    #[serde(rename = "message")]
+    Message,
+    /// Reading `/facts`, `/export`, sessions (`/sessions`, `/sessions/{key}`)
+    /// and the memory browser (`/memory/*`).
+    #[serde(rename = "sessions:read")]
+    SessionsRead,
+    /// MCP tools that change memory, and editing core memory other than
+    /// `identity.md` in the memory browser.
+    #[serde(rename = "memory:write")]
+    MemoryWrite,
+    #[serde(rename = "admin")]
+    Admin,
+    /// The MCP server.
+    #[serde(rename = "mcp")]
+    Mcp,
+}
+
+impl std::fmt::Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Message => "message",
+            Self::SessionsRead => "sessions:read",
+            Self::MemoryWrite => "memory:write",
+            Self::Admin => "admin",
+            Self::Mcp => "mcp",
+        })
+    }
+}
+
+/// The caller behind a request, as established by its bearer token.
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    /// Every message from this key is attributed to this user.
+    pub user: Option<String>,
+    /// Every message from this key goes to this channel.
+    pub channel: Option<String>,
+    /// Whose memory the key works on; `None` for the default tenant.
+    pub tenant: Option<String>,
+}
+
+impl Principal {
+    /// Used when no keys are configured (localhost-only servers).
+    pub fn anonymous() -> Self {
+        Self {
+            name: "anonymous".into(),
+            scopes: vec![Scope::Admin],
+            user: None,
+            channel: None,
+            tenant: None,
+        }
+    }
+
+    /// A browser logged in with the web password or a login link.
+    pub fn web() -> Self {
+        Self {
+            name: "web".into(),
+            scopes: vec![Scope::Admin],
+            user: None,
+            channel: None,
+            tenant: None,
+        }
+    }
+
+    pub fn allows(&self, scope: Scope) -> bool {
+        self.scopes
+            .iter()
+            .any(|s| *s == scope || *s == Scope::Admin)
+    }
+
+    /// Override a requested user/channel with the key's binding, if any.
+    pub fn bind(&self, user: &mut String, channel: &mut String) {
+        if let Some(u) = &self.user {
+            u.clone_into(user);
+        }
+        if let Some(c) = &self.channel {
+            c.clone_into(channel);
+        }
+    }
+
+    /// Whether a session or job of `user` on `channel` belongs to this key.
+    pub fn can_see(&self, user: &str, channel: &str) -> bool {
+        self.user.as_deref().is_none_or(|u| u == user)
+            && self.channel.as_deref().is_none_or(|c| c == channel)
+    }
+}
+
+fn principal_of(key: &ApiKeyConfig) -> Principal {
+    Principal {
+        name: key.name.clone(),
+        scopes: key.scopes.clone(),
+        user: key.user.clone(),
+        channel: key.channel.clone(),
+        tenant: key.tenant.clone(),
+    }
+}
+
+/// Compare two secrets in time independent of where they differ. Hashing
+/// first also hides the length of the expected key.
+pub fn ct_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (Sha256::digest(a.as_bytes()), Sha256::digest(b.as_bytes()));
+    a.iter()
+        .zip(b.iter())
+        .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+        == 0
+}
+
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+}
+
+pub fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all("cookie")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|c| c.trim().split_once('='))
+        .find(|(k, _)| *k == name)
+        .map(|(_, v)| v)
+}
+
+struct WebSession {
+    csrf: String,
+    expires: Instant,
+}
+
+/// The configured API keys and browser logins. The legacy `[api] api_key`
+/// becomes an admin key named `default`. Keys are replaced wholesale on
+/// reload (SIGHUP); browser logins are dropped if the web password changed.
+pub struct KeyStore {
+    keys: RwLock<Vec<ApiKeyConfig>>,
+    password: RwLock<Option<String>>,
+    web: Mutex<HashMap<String, WebSession>>,
+    login_tokens: Mutex<HashMap<String, Instant>>,
+}
+
+impl KeyStore {
+    pub fn new(api: &ApiConfig) -> Self {
+        Self {
+            keys: RwLock::new(collect_keys(api)),
+            password: RwLock::new(api.web_password.clone().filter(|p| !p.is_empty())),
+            web: Mutex::new(HashMap::new()),
+            login_tokens: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys
+            .read()
+            .expect("key store lock poisoned")
+            .is_empty()
+    }
+
+    /// Find the key matching `token`. Every key is compared so the time
+    /// taken does not depend on which one matched.
+    pub fn authenticate(&self, token: &str) -> Option<Principal> {
+        let keys = self.keys.read().expect("key store lock poisoned");
+        let mut found = None;
+        for key in keys.iter() {
+            if ct_eq(&key.key, token) && found.is_none() {
+                found = Some(principal_of(key));
+            }
+        }
+        found
+    }
+
+    pub fn has_password(&self) -> bool {
+        self.password
+            .read()
+            .expect("key store lock poisoned")
+            .is_some()
+    }
+
+    pub fn check_password(&self, password: &str) -> bool {
+        self.password
+            .read()
+            .expect("key store lock poisoned")
+            .as_deref()
+            .is_some_and(|p| ct_eq(p, password))
+    }
+
+    /// A one-time token that `GET /login?token=` exchanges for a browser
+    /// login.
+    pub fn issue_login_token(&self) -> String {
+        let token = crypto::random_id();
+        let now = Instant::now();
+        let mut tokens = self.login_tokens.lock().expect("key store lock poisoned");
+        tokens.retain(|_, expires| *expires > now);
+        tokens.insert(token.clone(), now + LOGIN_TOKEN_TTL);
+        token
+    }
+
+    pub fn redeem_login_token(&self, token: &str) -> bool {
+        self.login_tokens
+            .lock()
+            .expect("key store lock poisoned")
+            .remove(token)
+            .is_some_and(|expires| expires > Instant::now())
+    }
+
+    /// Start a browser login. Returns the session id and its CSRF token.
+    pub fn start_web_session(&self) -> (String, String) {
+        let (id, csrf) = (crypto::random_id(), crypto::random_id());
+        let now = Instant::now();
+        let mut web = self.web.lock().expect("key store lock poisoned");
+        web.retain(|_, s| s.expires > now);
+        web.insert(
+            id.clone(),
+            WebSession {
+                csrf: csrf.clone(),
+                expires: now + WEB_SESSION_TTL,
+            },
+        );
+        (id, csrf)
+    }
+
+    /// The CSRF token of a live browser login.
+    pub fn web_session(&self, id: &str) -> Option<String> {
+        self.web
+            .lock()
+            .expect("key store lock poisoned")
+            .get(id)
+            .filter(|s| s.expires > Instant::now())
+            .map(|s| s.csrf.clone())
+    }
+
+    pub fn end_web_session(&self, id: &str) {
+        self.web.lock().expect("key store lock poisoned").remove(id);
+    }
+
+    /// Re-read the keys from the config file. Keeps the current keys if the
+    /// file does not parse or would leave a non-localhost server open.
+    pub fn reload(&self, config_path: &Path, localhost: bool) -> Result<usize> {
+        let cfg = config::load(&config_path.to_string_lossy())?;
+        let keys = collect_keys(&cfg.api);
+        if keys.is_empty() && !localhost {
+            anyhow::bail!("refusing to remove every API key from a non-localhost server");
+        }
+        let n = keys.len();
+        *self.keys.write().expect("key store lock poisoned") = keys;
+        let password = cfg.api.web_password.filter(|p| !p.is_empty());
+        let mut current = self.password.write().expect("key store lock poisoned");
+        if *current != password {
+            self.web.lock().expect("key store lock poisoned").clear();
+            *current = password;
+        }
+        Ok(n)
+    }
+}
+
+fn collect_keys(api: &ApiConfig) -> Vec<ApiKeyConfig> {
+    let mut keys: Vec<ApiKeyConfig> = api
+        .api_key
+        .iter()
+        .map(|key| ApiKeyConfig {
+            name: "default".into(),
+            key: key.clone(),
+            scopes: vec![Scope::Admin],
+            user: None,
+            channel: None,
+            tenant: None,
+        })
+        .collect();
+    for key in &api.keys {
+        if key.key.is_empty() {
+            tracing::warn!("Ignoring API key '{}' with an empty secret", key.name);
+            continue;
+        }
+        keys.push(key.clone());
+    }
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> KeyStore {
+        let api: ApiConfig = toml::from_str(
+            r#"
+            api_key = "legacy"
+            [[keys]]
+            name = "n8n"
+            key = "n8n-secret"
+            scopes = ["message"]
+            user = "bot"
+            channel = "n8n"
+            [[keys]]
+            name = "reader"
+            key = "reader-secret"
+            scopes = ["sessions:read"]
+            tenant = "aiko"
+            "#,
+        )
+        .unwrap();
+        KeyStore::new(&api)
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let keys = store();
+        let admin = keys.authenticate("legacy").unwrap();
+        assert_eq!(admin.name, "default");
+        assert!(admin.allows(Scope::MemoryWrite));
+
+        let n8n = keys.authenticate("n8n-secret").unwrap();
+        assert!(n8n.allows(Scope::Message));
+        assert!(!n8n.allows(Scope::SessionsRead));
+        let (mut user, mut channel) = ("masaki".to_string(), "cli".to_string());
+        n8n.bind(&mut user, &mut channel);
+        assert_eq!((user.as_str(), channel.as_str()), ("bot", "n8n"));
+        assert!(n8n.can_see("bot", "n8n"));
+        assert!(!n8n.can_see("masaki", "n8n"));
+
+        assert!(n8n.tenant.is_none());
+        let reader = keys.authenticate("reader-secret").unwrap();
+        assert_eq!(reader.tenant.as_deref(), Some("aiko"));
+
+        assert!(keys.authenticate("reader-secre").is_none());
+        assert!(keys.authenticate("").is_none());
+    }
+
+    #[test]
+    fn test_web_login() {
+        let keys = store();
+        assert!(!keys.has_password());
+        assert!(!keys.check_password(""));
+
+        let token = keys.issue_login_token();
+        assert!(keys.redeem_login_token(&token));
+        assert!(!keys.redeem_login_token(&token), "tokens are single-use");
+
+        let (id, csrf) = keys.start_web_session();
+        assert_eq!(keys.web_session(&id), Some(csrf));
+        keys.end_web_session(&id);
+        assert!(keys.web_session(&id).is_none());
+
+        let mut headers = HeaderMap::new();
+        headers.insert("cookie", "a=1; 1koro_session=abc; b=2".parse().unwrap());
+        assert_eq!(cookie(&headers, SESSION_COOKIE), Some("abc"));
+        assert_eq!(cookie(&headers, "c"), None);
+    }
+
+    #[test]
+    fn test_ct_eq() {
+        assert!(ct_eq("abc", "abc"));
+        assert!(!ct_eq("abc", "abd"));
+        assert!(!ct_eq("abc", "abcd"));
+    }
+}
@@ -0,0 +1,434 @@
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Local};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::session::SessionStore;
+
+const FORMAT_VERSION: u32 = 1;
+const MANIFEST: &str = "manifest.json";
+const CONFIG_ENTRY: &str = "config.toml";
+/// Directories (relative to the memory directory) included in a backup.
+const BACKUP_DIRS: &[&str] = &["core", "logs", "skills", "files"];
+/// Single files included in a backup.
+const BACKUP_FILES: &[&str] = &["facts.json", "memory.salt"];
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub format_version: u32,
+    pub created_at: DateTime<Local>,
+    pub agent_version: String,
+    pub files: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// Archive member path and contents.
+type FileEntry = (String, Vec<u8>);
+
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+fn collect_files(dir: &Path, rel: &str, out: &mut Vec<String>) -> Result<()> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Ok(());
+    };
+    for e in entries.flatten() {
+        let name = e.file_name().to_string_lossy().to_string();
+        let rel = format!("{rel}/{name}");
+        let ft = e.file_type()?;
+        if ft.is_dir() {
+            collect_files(&e.path(), &rel, out)?;
+        } else if ft.is_file() && !name.ends_with(".tmp") {
+            out.push(rel);
+        }
+    }
+    Ok(())
+}
+
+/// Drop anything that looks like a credential from the config before it is
+/// archived. A restored config must have its secrets filled in again.
+fn strip_secrets(value: &mut toml::Value) {
+    match value {
+        toml::Value::Table(t) => {
+            t.retain(|k, _| !is_secret_key(k));
+            t.iter_mut().for_each(|(_, v)| strip_secrets(v));
+        }
+        toml::Value::Array(a) => a.iter_mut().for_each(strip_secrets),
+        _ => {}
+    }
+}
+
+fn is_secret_key(key: &str) -> bool {
+    let k = key.to_ascii_lowercase();
+    k == "key"
+        || k == "token"
+        || k.ends_with("api_key")
+        || k.ends_with("secret")
+        || k.ends_with("password")
+}
+
+/// Write a gzip'd tar of the memory directory plus a secret-free copy of the
+/// config to `out`. When `sessions` is given (running server), session writes
+/// are paused while the session files are read so the snapshot is consistent.
+pub fn create(
+    base_dir: &Path,
+    config_path: Option<&Path>,
+    out: &Path,
+    sessions: Option<&SessionStore>,
+) -> Result<Manifest> {
+    if let Some(parent) = out.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp = out.with_extension(format!("{}.tmp", std::process::id()));
+    let file = std::fs::File::create(&tmp)
+        .with_context(|| format!("Failed to create backup: {}", tmp.display()))?;
+    let manifest = write(base_dir, config_path, file, sessions)?;
+    std::fs::rename(&tmp, out)?;
+    Ok(manifest)
+}
+
+/// `create`, into any writer. The server uses this to send a snapshot back
+/// to `1koro backup`.
+pub fn write(
+    base_dir: &Path,
+    config_path: Option<&Path>,
+    out: impl Write,
+    sessions: Option<&SessionStore>,
+) -> Result<Manifest> {
+    let mut entries: Vec<FileEntry> = Vec::new();
+
+    {
+        let _quiet = sessions.map(SessionStore::quiesce);
+        let mut names = Vec::new();
+        collect_files(&base_dir.join("sessions"), "sessions", &mut names)?;
+        for rel in names {
+            entries.push((rel.clone(), std::fs::read(base_dir.join(&rel))?));
+        }
+    }
+
+    let mut names = Vec::new();
+    for d in BACKUP_DIRS {
+        collect_files(&base_dir.join(d), d, &mut names)?;
+    }
+    for f in BACKUP_FILES {
+        if base_dir.join(f).is_file() {
+            names.push(f.to_string());
+        }
+    }
+    for rel in names {
+        entries.push((rel.clone(), std::fs::read(base_dir.join(&rel))?));
+    }
+
+    if let Some(cfg) = config_path.filter(|p| p.is_file()) {
+        let mut value: toml::Value = toml::from_str(&std::fs::read_to_string(cfg)?)
+            .context("Failed to parse config for backup")?;
+        strip_secrets(&mut value);
+        entries.push((CONFIG_ENTRY.into(), toml::to_string(&value)?.into_bytes()));
+    }
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let manifest = Manifest {
+        format_version: FORMAT_VERSION,
+        created_at: Local::now(),
+        agent_version: env!("CARGO_PKG_VERSION").into(),
+        files: entries
+            .iter()
+            .map(|(path, data)| ManifestEntry {
+                path: path.clone(),
+                size: data.len() as u64,
+                sha256: sha256_hex(data),
+            })
+            .collect(),
+    };
+
+    let mut tar = tar::Builder::new(GzEncoder::new(out, Compression::default()));
+    let manifest_json = serde_json::to_vec_pretty(&manifest)?;
+    append(&mut tar, MANIFEST, &manifest_json)?;
+    for (path, data) in &entries {
+        append(&mut tar, path, data)?;
+    }
+    tar.into_inner()?.finish()?.flush()?;
+    Ok(manifest)
+}
+
+pub(crate) fn append<W: Write>(tar: &mut tar::Builder<W>, path: &str, data: &[u8]) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o600);
+    header.set_mtime(Local::now().timestamp().max(0) as u64);
+    header.set_cksum();
+    tar.append_data(&mut header, path, data)?;
+    Ok(())
+}
+
+// --- Restore ---
+
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    Create,
+    Unchanged,
+    Overwrite,
+    Conflict,
+}
+
+pub struct RestorePlan {
+    pub manifest: Manifest,
+    pub actions: Vec<(String, Action)>,
+}
+
+fn read_archive(archive: &Path) -> Result<(Manifest, Vec<FileEntry>)> {
+    let file = std::fs::File::open(archive)
+        .with_context(|| format!("Failed to open backup: {}", archive.display()))?;
+    let mut tar = tar::Archive::new(GzDecoder::new(file));
+    let mut manifest: Option<Manifest> = None;
+    let mut files = Vec::new();
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().to_string();
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        if path == MANIFEST {
+            manifest = Some(serde_json::from_slice(&data).context("Invalid backup manifest")?);
+        } else {
+            files.push((path, data));
+        }
+    }
+    let manifest = manifest.context("Backup has no manifest")?;
+    if manifest.format_version > FORMAT_VERSION {
+        bail!(
+            "Backup format v{} is newer than supported v{FORMAT_VERSION}",
+            manifest.format_version
+        );
+    }
+    if manifest.files.len() != files.len() {
+        bail!("Backup file count does not match manifest");
+    }
+    for (path, data) in &files {
+        let expected = manifest
+            .files
+            .iter()
+            .find(|f| &f.path == path)
+            .with_context(|| format!("File not in manifest: {path}"))?;
+        if sha256_hex(data) != expected.sha256 {
+            bail!("Checksum mismatch: {path}");
+        }
+        if path.starts_with('/') || path.split('/').any(|c| c == "..") {
+            bail!("Refusing unsafe path in backup: {path}");
+        }
+    }
+    Ok((manifest, files))
+}
+
+/// Restore `archive` into `base_dir`. Existing files that differ are
+/// conflicts: skipped unless `force`, in which case they are overwritten.
+/// The archived config (secrets stripped) is written to `config_path` only
+/// if no config exists there. With `dry_run` nothing is written.
+pub fn restore(
+    archive: &Path,
+    base_dir: &Path,
+    config_path: &Path,
+    dry_run: bool,
+    force: bool,
+) -> Result<RestorePlan> {
+    let (manifest, files) = read_archive(archive)?;
+    let mut actions = Vec::new();
+    for (path, data) in files {
+        let target = if path == CONFIG_ENTRY {
+            config_path.to_path_buf()
+        } else {
+            base_dir.join(&path)
+        };
+        let action = match std::fs::read(&target) {
+            Err(_) => Action::Create,
+            Ok(existing) if existing == data => Action::Unchanged,
+            Ok(_) if force && path != CONFIG_ENTRY => Action::Overwrite,
+            Ok(_) => Action::Conflict,
+        };
+        if !dry_run && matches!(action, Action::Create | Action::Overwrite) {
+            write_atomic(&target, &data)?;
+        }
+        actions.push((path, action));
+    }
+    Ok(RestorePlan { manifest, actions })
+}
+
+fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension(format!("{}.tmp", std::process::id()));
+    std::fs::write(&tmp, data)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+// --- Scheduled rotation ---
+
+/// Remove all but the newest `keep` backups in `dir`.
+pub fn rotate(dir: &Path, keep: usize) -> Result<usize> {
+    let mut backups: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| {
+            p.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with("1koro-") && n.ends_with(".tar.gz"))
+        })
+        .collect();
+    backups.sort();
+    let excess = backups.len().saturating_sub(keep);
+    for p in &backups[..excess] {
+        std::fs::remove_file(p)?;
+    }
+    Ok(excess)
+}
+
+pub fn timestamped_name() -> String {
+    format!("1koro-{}.tar.gz", Local::now().format("%Y%m%d-%H%M%S"))
+}
+
+/// Periodically snapshot the memory directory into `dir`, keeping `keep`.
+pub fn spawn_rotation(
+    base_dir: PathBuf,
+    config_path: PathBuf,
+    dir: PathBuf,
+    interval: Duration,
+    keep: usize,
+    sessions: std::sync::Arc<SessionStore>,
+) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let (base_dir, config_path, dir, sessions) = (
+                base_dir.clone(),
+                config_path.clone(),
+                dir.clone(),
+                sessions.clone(),
+            );
+            let result = tokio::task::spawn_blocking(move || -> Result<(PathBuf, usize)> {
+                let out = dir.join(timestamped_name());
+                create(&base_dir, Some(&config_path), &out, Some(&sessions))?;
+                Ok((out, rotate(&dir, keep)?))
+            })
+            .await;
+            match result {
+                Ok(Ok((out, removed))) => {
+                    tracing::info!(
+                        "Backup written to {} ({removed} rotated out)",
+                        out.display()
+                    )
+                }
+                Ok(Err(e)) => tracing::error!("Scheduled backup failed: {e}"),
+                Err(e) => tracing::error!("Scheduled backup task panicked: {e}"),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("1koro-backup-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_strip_secrets() {
+        let mut v: toml::Value = toml::from_str(
+            "[llm]\nmodel = \"m\"\napi_key = \"sk-1\"\n[api]\nbind = \"x\"\napi_key = \"k\"\n[[api.keys]]\nname = \"n8n\"\nkey = \"abc\"\n",
+        )
+        .unwrap();
+        strip_secrets(&mut v);
+        let s = toml::to_string(&v).unwrap();
+        assert!(!s.contains("sk-1"));
+        assert!(!s.contains("abc"));
+        assert!(s.contains("model"));
+        assert!(s.contains("n8n"));
+    }
+
+    #[test]
+    fn test_backup_restore_roundtrip() {
+        let src = temp_dir("src");
+        std::fs::create_dir_all(src.join("core")).unwrap();
+        std::fs::create_dir_all(src.join("logs/daily")).unwrap();
+        std::fs::write(src.join("core/user.md"), "# User\n").unwrap();
+        std::fs::write(src.join("logs/daily/2026-02-17.md"), "- hi\n").unwrap();
+        let cfg = src.join("config.toml");
+        std::fs::write(&cfg, "[llm]\nmodel = \"m\"\napi_key = \"sk-secret\"\n").unwrap();
+
+        let archive = src.join("out/backup.tar.gz");
+        let manifest = create(&src, Some(&cfg), &archive, None).unwrap();
+        assert_eq!(manifest.files.len(), 3);
+
+        let dst = temp_dir("dst");
+        let dst_cfg = dst.join("config.toml");
+        let plan = restore(&archive, &dst, &dst_cfg, true, false).unwrap();
+        assert!(plan.actions.iter().all(|(_, a)| *a == Action::Create));
+        assert!(!dst.join("core/user.md").exists(), "dry run must not write");
+
+        restore(&archive, &dst, &dst_cfg, false, false).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dst.join("logs/daily/2026-02-17.md")).unwrap(),
+            "- hi\n"
+        );
+        assert!(
+            !std::fs::read_to_string(&dst_cfg)
+                .unwrap()
+                .contains("sk-secret")
+        );
+
+        std::fs::write(dst.join("core/user.md"), "# Changed\n").unwrap();
+        let plan = restore(&archive, &dst, &dst_cfg, false, false).unwrap();
+        let user = plan.actions.iter().find(|(p, _)| p == "core/user.md");
+        assert_eq!(user.unwrap().1, Action::Conflict);
+        assert_eq!(
+            std::fs::read_to_string(dst.join("core/user.md")).unwrap(),
+            "# Changed\n"
+        );
+
+        restore(&archive, &dst, &dst_cfg, false, true).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dst.join("core/user.md")).unwrap(),
+            "# User\n"
+        );
+        let _ = std::fs::remove_dir_all(src);
+        let _ = std::fs::remove_dir_all(dst);
+    }
+
+    #[test]
+    fn test_rotate_keeps_newest() {
+        let dir = temp_dir("rotate");
+        for n in ["20260101-000000", "20260102-000000", "20260103-000000"] {
+            std::fs::write(dir.join(format!("1koro-{n}.tar.gz")), "").unwrap();
+        }
+        std::fs::write(dir.join("unrelated.txt"), "").unwrap();
+        assert_eq!(rotate(&dir, 2).unwrap(), 1);
+        assert!(!dir.join("1koro-20260101-000000.tar.gz").exists());
+        assert!(dir.join("1koro-20260103-000000.tar.gz").exists());
+        assert!(dir.join("unrelated.txt").exists());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
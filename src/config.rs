@@ -1,6 +1,9 @@
 use anyhow::{Context, Result};
 use serde::Deserialize;
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::auth::Scope;
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
@@ -14,11 +17,257 @@ pub struct Config {
     pub memory: MemoryConfig,
     #[serde(default)]
     pub tools: ToolsConfig,
+    #[serde(default)]
+    pub backup: BackupConfig,
+    #[serde(default)]
+    pub retention: RetentionConfig,
+    #[serde(default)]
+    pub channels: ChannelProfiles,
+    #[serde(default)]
+    pub tenants: Vec<TenantConfig>,
+}
+
+impl Config {
+    /// Who a message to `tenant` is from when it doesn't say.
+    pub fn default_user(&self, tenant: &str) -> &str {
+        self.tenants
+            .iter()
+            .find(|t| t.name == tenant)
+            .and_then(|t| t.default_user.as_deref())
+            .or(self.agent.default_user.as_deref())
+            .unwrap_or(FALLBACK_USER)
+    }
+
+    /// `[memory]` with the base dir of `tenant` (`default` is `[memory]`
+    /// itself).
+    pub fn tenant_memory(&self, tenant: &str) -> Result<MemoryConfig> {
+        if tenant == DEFAULT_TENANT {
+            return Ok(self.memory.clone());
+        }
+        let t = self
+            .tenants
+            .iter()
+            .find(|t| t.name == tenant)
+            .with_context(|| format!("No tenant named '{tenant}' in [[tenants]]"))?;
+        Ok(MemoryConfig {
+            base_dir: expand_tilde(&t.base_dir.to_string_lossy()),
+            ..self.memory.clone()
+        })
+    }
+
+    /// Tenant names must be unique and their directories must not contain
+    /// one another (`read_file` could otherwise reach into a nested one).
+    /// Keys may only name configured tenants.
+    pub fn validate_tenants(&self) -> Result<()> {
+        let mut dirs: Vec<(&str, PathBuf)> = vec![(DEFAULT_TENANT, self.memory.base_dir.clone())];
+        for t in &self.tenants {
+            if t.name.is_empty() || dirs.iter().any(|(name, _)| *name == t.name) {
+                anyhow::bail!(
+                    "Tenant names must be unique and not '{DEFAULT_TENANT}': '{}'",
+                    t.name
+                );
+            }
+            dirs.push((&t.name, self.tenant_memory(&t.name)?.base_dir));
+        }
+        for (i, (a, a_dir)) in dirs.iter().enumerate() {
+            for (b, b_dir) in &dirs[i + 1..] {
+                if a_dir.starts_with(b_dir) || b_dir.starts_with(a_dir) {
+                    anyhow::bail!(
+                        "Tenants '{a}' and '{b}' share a directory ({} and {})",
+                        a_dir.display(),
+                        b_dir.display()
+                    );
+                }
+            }
+        }
+        for key in &self.api.keys {
+            if let Some(tenant) = &key.tenant
+                && !dirs.iter().any(|(name, _)| name == tenant)
+            {
+                anyhow::bail!("API key '{}' names unknown tenant '{tenant}'", key.name);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The tenant of keys not assigned to one, with the `[memory]` base dir.
+pub const DEFAULT_TENANT: &str = "default";
+
+/// The user of messages that don't name one, if `default_user` isn't set.
+pub const FALLBACK_USER: &str = "user";
+
+/// Someone else sharing the server, with memory of their own.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TenantConfig {
+    pub name: String,
+    /// Core memory, logs, sessions, skills, facts and jobs of this tenant.
+    /// Must not be inside another tenant's directory.
+    pub base_dir: PathBuf,
+    /// Overrides `[agent] default_user` for this tenant.
+    pub default_user: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct AgentConfig {
     pub name: String,
+    /// The user a message is from when it doesn't say: API and web requests
+    /// without a `user`, and imported ChatGPT or Markdown history.
+    pub default_user: Option<String>,
+    #[serde(flatten)]
+    pub limits: AgentLimits,
+}
+
+/// Bounds on one agent run, set under `[agent]` and overridable per channel
+/// with `[channels.<name>]`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct AgentLimits {
+    /// LLM calls with tool use allowed per message.
+    #[serde(default = "default_max_tool_iterations")]
+    pub max_tool_iterations: usize,
+    /// Session length (in messages) at which the older half is summarized.
+    #[serde(default = "default_compress_threshold")]
+    pub session_compress_threshold: usize,
+    /// A session summary longer than this is replaced instead of extended.
+    #[serde(default = "default_max_summary_length")]
+    pub max_summary_length: usize,
+    /// Seconds a message may take before the agent must answer; 0 for no
+    /// limit.
+    #[serde(default = "default_request_timeout")]
+    pub request_timeout_secs: u64,
+}
+
+impl Default for AgentLimits {
+    fn default() -> Self {
+        Self {
+            max_tool_iterations: default_max_tool_iterations(),
+            session_compress_threshold: default_compress_threshold(),
+            max_summary_length: default_max_summary_length(),
+            request_timeout_secs: default_request_timeout(),
+        }
+    }
+}
+
+fn default_max_tool_iterations() -> usize {
+    10
+}
+
+fn default_compress_threshold() -> usize {
+    20
+}
+
+fn default_max_summary_length() -> usize {
+    2000
+}
+
+fn default_request_timeout() -> u64 {
+    300
+}
+
+/// Parts of memory that can be put in the system prompt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MemoryTier {
+    Identity,
+    User,
+    State,
+    Monthly,
+    Weekly,
+}
+
+impl MemoryTier {
+    pub const ALL: [Self; 5] = [
+        Self::Identity,
+        Self::User,
+        Self::State,
+        Self::Monthly,
+        Self::Weekly,
+    ];
+}
+
+/// How the agent behaves on one channel, set with `[channels.<name>]`.
+/// Anything left out falls back to the global settings; unknown keys are an
+/// error so a typo doesn't silently do nothing.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelProfile {
+    /// Model to use instead of `[llm] model`.
+    pub model: Option<String>,
+    pub max_tokens: Option<u32>,
+    /// Names of the tools offered here; every registered tool when unset.
+    pub tools: Option<Vec<String>>,
+    /// Markdown appended to the system prompt; relative paths are under the
+    /// base dir.
+    pub system_prompt_file: Option<PathBuf>,
+    /// Memory put in the system prompt; everything when unset.
+    pub memory: Option<Vec<MemoryTier>>,
+    /// How replies should read, e.g. "One or two sentences, no Markdown."
+    pub style: Option<String>,
+    /// `AgentLimits` for this channel; unset ones keep `[agent]`'s.
+    pub max_tool_iterations: Option<usize>,
+    pub session_compress_threshold: Option<usize>,
+    pub max_summary_length: Option<usize>,
+    pub request_timeout_secs: Option<u64>,
+}
+
+impl ChannelProfile {
+    /// `global` with this channel's overrides applied.
+    pub fn limits(&self, global: AgentLimits) -> AgentLimits {
+        AgentLimits {
+            max_tool_iterations: self
+                .max_tool_iterations
+                .unwrap_or(global.max_tool_iterations),
+            session_compress_threshold: self
+                .session_compress_threshold
+                .unwrap_or(global.session_compress_threshold),
+            max_summary_length: self.max_summary_length.unwrap_or(global.max_summary_length),
+            request_timeout_secs: self
+                .request_timeout_secs
+                .unwrap_or(global.request_timeout_secs),
+        }
+    }
+
+    pub fn memory_tiers(&self) -> &[MemoryTier] {
+        self.memory.as_deref().unwrap_or(&MemoryTier::ALL)
+    }
+
+    pub fn allows_tool(&self, name: &str) -> bool {
+        self.tools
+            .as_ref()
+            .is_none_or(|tools| tools.iter().any(|t| t == name))
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct ChannelProfiles(pub HashMap<String, ChannelProfile>);
+
+impl ChannelProfiles {
+    /// The profile of `channel`: `slack#general` takes the settings of
+    /// `slack` and then its own.
+    pub fn for_channel(&self, channel: &str) -> ChannelProfile {
+        let mut profile = ChannelProfile::default();
+        let base = channel.split('#').next().unwrap_or(channel);
+        for name in [base, channel] {
+            let Some(p) = self.0.get(name) else {
+                continue;
+            };
+            let p = p.clone();
+            profile.model = p.model.or(profile.model);
+            profile.max_tokens = p.max_tokens.or(profile.max_tokens);
+            profile.tools = p.tools.or(profile.tools);
+            profile.system_prompt_file = p.system_prompt_file.or(profile.system_prompt_file);
+            profile.memory = p.memory.or(profile.memory);
+            profile.style = p.style.or(profile.style);
+            profile.max_tool_iterations = p.max_tool_iterations.or(profile.max_tool_iterations);
+            profile.session_compress_threshold = p
+                .session_compress_threshold
+                .or(profile.session_compress_threshold);
+            profile.max_summary_length = p.max_summary_length.or(profile.max_summary_length);
+            profile.request_timeout_secs = p.request_timeout_secs.or(profile.request_timeout_secs);
+        }
+        profile
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -28,6 +277,13 @@ pub struct LlmConfig {
     pub api_key: String,
     #[serde(default = "default_max_tokens")]
     pub max_tokens: u32,
+    /// The model accepts images; attached images are sent as content parts.
+    #[serde(default)]
+    pub vision: bool,
+    /// The model accepts `input_audio`; attached wav/mp3 files are sent as
+    /// audio parts.
+    #[serde(default)]
+    pub audio: bool,
 }
 
 fn default_max_tokens() -> u32 {
@@ -39,6 +295,25 @@ pub struct ApiConfig {
     #[serde(default = "default_api_bind")]
     pub bind: String,
     pub api_key: Option<String>,
+    /// HMAC key for signing job callbacks (defaults to `api_key`).
+    pub callback_secret: Option<String>,
+    /// Password for the web chat's login form.
+    pub web_password: Option<String>,
+    /// Named keys with their own scopes, in addition to `api_key`.
+    #[serde(default)]
+    pub keys: Vec<ApiKeyConfig>,
+    /// Messages per minute allowed for each API key; 0 disables the limit.
+    #[serde(default = "default_key_rate")]
+    pub key_rate_per_minute: u32,
+    /// Messages per minute allowed for each session; 0 disables the limit.
+    #[serde(default = "default_session_rate")]
+    pub session_rate_per_minute: u32,
+    /// Agent runs allowed at the same time.
+    #[serde(default = "default_max_concurrent")]
+    pub max_concurrent: usize,
+    /// Messages that may wait for a free slot before new ones get a 429.
+    #[serde(default = "default_max_queued")]
+    pub max_queued: usize,
 }
 
 impl Default for ApiConfig {
@@ -46,10 +321,51 @@ impl Default for ApiConfig {
         Self {
             bind: default_api_bind(),
             api_key: None,
+            callback_secret: None,
+            web_password: None,
+            keys: Vec::new(),
+            key_rate_per_minute: default_key_rate(),
+            session_rate_per_minute: default_session_rate(),
+            max_concurrent: default_max_concurrent(),
+            max_queued: default_max_queued(),
         }
     }
 }
 
+fn default_key_rate() -> u32 {
+    60
+}
+
+fn default_session_rate() -> u32 {
+    20
+}
+
+fn default_max_concurrent() -> usize {
+    4
+}
+
+fn default_max_queued() -> usize {
+    16
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiKeyConfig {
+    pub name: String,
+    pub key: String,
+    #[serde(default = "default_key_scopes")]
+    pub scopes: Vec<Scope>,
+    /// Fix the user of every message sent with this key.
+    pub user: Option<String>,
+    /// Fix the channel of every message sent with this key.
+    pub channel: Option<String>,
+    /// Whose memory this key works on; the `default` tenant when unset.
+    pub tenant: Option<String>,
+}
+
+fn default_key_scopes() -> Vec<Scope> {
+    vec![Scope::Message]
+}
+
 fn default_api_bind() -> String {
     "127.0.0.1:3000".to_string()
 }
@@ -83,31 +399,120 @@ pub struct ToolsConfig {
     pub shell_enabled: bool,
     #[serde(default = "default_shell_timeout")]
     pub shell_timeout: u64,
+    /// Read-only tool calls of one turn that may run at the same time.
+    #[serde(default = "default_max_parallel")]
+    pub max_parallel: usize,
+    /// Tools refused for the rest of a turn that has read untrusted content.
+    #[serde(default)]
+    pub untrusted_policy: UntrustedPolicy,
+}
+
+/// What a turn may still do once untrusted content (files, logs, command
+/// output, attachments) has entered it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UntrustedPolicy {
+    /// Refuse every tool that is not read-only, so injected text can't write
+    /// itself into core memory, summaries or facts either.
+    #[default]
+    Block,
+    /// Refuse tools that act outside memory: the shell, notifications and
+    /// tools 1koro does not know. Memory writes are versioned and allowed,
+    /// but what they write is trusted in every later prompt.
+    BlockExternal,
+    /// Refuse nothing; untrusted content is only marked as data.
+    Allow,
 }
 
 fn default_shell_timeout() -> u64 {
     30
 }
 
+fn default_max_parallel() -> usize {
+    4
+}
+
 impl Default for ToolsConfig {
     fn default() -> Self {
         Self {
             shell_enabled: false,
             shell_timeout: default_shell_timeout(),
+            max_parallel: default_max_parallel(),
+            untrusted_policy: UntrustedPolicy::default(),
         }
     }
 }
 
 #[derive(Debug, Deserialize)]
+pub struct BackupConfig {
+    /// Where scheduled backups are written (default: ~/.1koro/backups).
+    pub dir: Option<PathBuf>,
+    /// Hours between scheduled backups; 0 disables them.
+    #[serde(default)]
+    pub interval_hours: u64,
+    /// Number of scheduled backups to keep.
+    #[serde(default = "default_backup_keep")]
+    pub keep: usize,
+}
+
+fn default_backup_keep() -> usize {
+    7
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            interval_hours: 0,
+            keep: default_backup_keep(),
+        }
+    }
+}
+
+/// Nothing is deleted unless `prune_tool_output_after_months` or
+/// `delete_after_months` is set.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RetentionConfig {
+    /// Pack daily logs older than this many months into gzip monthly
+    /// archives under `logs/archive`; 0 disables compaction.
+    #[serde(default)]
+    pub compress_after_months: u32,
+    /// Replace tool results in sessions idle for this many months with a
+    /// placeholder; 0 keeps them.
+    #[serde(default)]
+    pub prune_tool_output_after_months: u32,
+    /// Delete monthly log archives older than this many months, but only
+    /// once a monthly summary exists for them; 0 keeps them forever.
+    #[serde(default)]
+    pub delete_after_months: u32,
+}
+
+impl RetentionConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.compress_after_months > 0
+            || self.prune_tool_output_after_months > 0
+            || self.delete_after_months > 0
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct MemoryConfig {
     #[serde(default = "default_base_dir")]
     pub base_dir: PathBuf,
+    /// Run an LLM extraction pass over each new conversation turn and
+    /// store any people/projects/dates it finds in the fact store.
+    #[serde(default)]
+    pub extract_facts: bool,
+    /// Secret used to encrypt memory at rest. `IKORO_MEMORY_KEY` overrides it.
+    pub encryption_key_file: Option<PathBuf>,
 }
 
 impl Default for MemoryConfig {
     fn default() -> Self {
         Self {
             base_dir: default_base_dir(),
+            extract_facts: false,
+            encryption_key_file: None,
         }
     }
 }
@@ -127,6 +532,16 @@ pub fn load(path: &str) -> Result<Config> {
 
 pub async fn init_config_dir() -> Result<()> {
     let base = default_base_dir();
+    init_memory_dir(&base).await?;
+    if !base.join("config.toml").exists() {
+        tokio::fs::write(base.join("config.toml"), CONFIG_TEMPLATE).await?;
+    }
+    Ok(())
+}
+
+/// Create the memory layout and starter core files under `base`, keeping
+/// whatever is already there.
+pub async fn init_memory_dir(base: &Path) -> Result<()> {
     for d in [
         "core",
         "logs/daily",
@@ -154,15 +569,12 @@ pub async fn init_config_dir() -> Result<()> {
     )
     .await?;
     write_if_missing(base.join("core/state.md"), "# State\n\n(No state yet)\n").await?;
-    write_if_missing(
-        base.join("config.toml"),
-        "[agent]\nname = \"1koro\"\n\n[llm]\nmodel = \"minimax/MiniMax-M1-80k\"\napi_key = \"YOUR_API_KEY\"\nmax_tokens = 8192\n\n[api]\nbind = \"127.0.0.1:3000\"\n# api_key = \"your-secret-key\"\n\n[mcp]\nenabled = false\nbind = \"127.0.0.1:3001\"\n\n[tools]\nshell_enabled = false\n# shell_timeout = 30\n",
-    )
-    .await?;
     Ok(())
 }
 
-fn expand_tilde(path: &str) -> PathBuf {
+const CONFIG_TEMPLATE: &str = "[agent]\nname = \"1koro\"\n# default_user = \"you\"\n# max_tool_iterations = 10\n# session_compress_threshold = 20\n# max_summary_length = 2000\n# request_timeout_secs = 300\n\n[llm]\nmodel = \"minimax/MiniMax-M1-80k\"\napi_key = \"YOUR_API_KEY\"\nmax_tokens = 8192\n# vision = false\n# audio = false\n\n[api]\nbind = \"127.0.0.1:3000\"\n# api_key = \"your-secret-key\"\n# callback_secret = \"signs-job-callbacks\"\n# web_password = \"for-the-browser-chat\"\n# key_rate_per_minute = 60\n# session_rate_per_minute = 20\n# max_concurrent = 4\n# max_queued = 16\n\n# [[api.keys]]\n# name = \"n8n\"\n# key = \"another-secret\"\n# scopes = [\"message\"]  # message, sessions:read, memory:write, admin, mcp\n# user = \"n8n\"\n# channel = \"n8n\"\n# tenant = \"aiko\"\n\n[mcp]\nenabled = false\nbind = \"127.0.0.1:3001\"\n\n[memory]\n# extract_facts = false\n# encryption_key_file = \"/etc/1koro/memory.key\"\n\n[tools]\nshell_enabled = false\n# shell_timeout = 30\n# max_parallel = 4\n# untrusted_policy = \"block\"  # block, block_external, allow\n\n[backup]\n# interval_hours = 24\n# keep = 7\n\n[retention]\n# compress_after_months = 3\n# prune_tool_output_after_months = 0\n# delete_after_months = 0\n\n# [channels.slack]\n# tools = [\"search_logs\", \"query_facts\", \"append_note\"]\n# style = \"Keep replies to one or two sentences, no Markdown.\"\n# max_tool_iterations = 4\n# request_timeout_secs = 60\n\n# [channels.family]\n# model = \"openai/gpt-4o-mini\"\n# max_tokens = 1024\n# memory = [\"identity\", \"user\"]  # identity, user, state, monthly, weekly\n# tools = [\"query_facts\", \"remember_fact\"]\n# system_prompt_file = \"prompts/family.md\"\n\n# [[tenants]]\n# name = \"aiko\"\n# base_dir = \"/srv/1koro/aiko\"\n# default_user = \"aiko\"\n";
+
+pub fn expand_tilde(path: &str) -> PathBuf {
     if let Some(rest) = path.strip_prefix("~/")
         && let Some(home) = dirs::home_dir()
     {
@@ -170,3 +582,126 @@ fn expand_tilde(path: &str) -> PathBuf {
     }
     PathBuf::from(path)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_agent_limits_per_channel() {
+        let cfg = config(
+            r#"
+            [channels.slack]
+            max_tool_iterations = 3
+            request_timeout_secs = 60
+            [channels."slack#ops"]
+            request_timeout_secs = 0
+            "#,
+        );
+        let global = AgentLimits {
+            max_tool_iterations: 8,
+            ..cfg.agent.limits
+        };
+        let limits = |channel| cfg.channels.for_channel(channel).limits(global);
+        let cli = limits("cli");
+        assert_eq!(cli.max_tool_iterations, 8);
+        assert_eq!(cli.session_compress_threshold, 20);
+        assert_eq!(limits("slack#general").max_tool_iterations, 3);
+        let ops = limits("slack#ops");
+        assert_eq!((ops.max_tool_iterations, ops.request_timeout_secs), (3, 0));
+
+        let agent: AgentConfig =
+            toml::from_str("name = \"1koro\"\nmax_tool_iterations = 4").unwrap();
+        assert_eq!(agent.limits.max_tool_iterations, 4);
+    }
+
+    fn config(extra: &str) -> Config {
+        toml::from_str(&format!(
+            "[agent]\nname = \"1koro\"\n[llm]\nmodel = \"m\"\napi_key = \"k\"\n[memory]\nbase_dir = \"/srv/1koro/owner\"\n{extra}"
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_tenants() {
+        let cfg = config(
+            r#"
+            [[tenants]]
+            name = "aiko"
+            base_dir = "/srv/1koro/aiko"
+            [[api.keys]]
+            name = "aiko-phone"
+            key = "secret"
+            tenant = "aiko"
+            "#,
+        );
+        cfg.validate_tenants().unwrap();
+        let aiko = cfg.tenant_memory("aiko").unwrap();
+        assert_eq!(aiko.base_dir, PathBuf::from("/srv/1koro/aiko"));
+        assert_eq!(
+            cfg.tenant_memory(DEFAULT_TENANT).unwrap().base_dir,
+            PathBuf::from("/srv/1koro/owner")
+        );
+        assert!(cfg.tenant_memory("ken").is_err());
+        assert_eq!(cfg.default_user("aiko"), FALLBACK_USER);
+
+        let named = config(
+            "[[tenants]]\nname = \"aiko\"\nbase_dir = \"/a\"\ndefault_user = \"aiko\"\n[[tenants]]\nname = \"ken\"\nbase_dir = \"/k\"",
+        );
+        assert_eq!(named.default_user("aiko"), "aiko");
+        assert_eq!(named.default_user("ken"), FALLBACK_USER);
+
+        let nested = config("[[tenants]]\nname = \"aiko\"\nbase_dir = \"/srv/1koro/owner/aiko\"");
+        assert!(nested.validate_tenants().is_err());
+        let dup = config(
+            "[[tenants]]\nname = \"a\"\nbase_dir = \"/a\"\n[[tenants]]\nname = \"a\"\nbase_dir = \"/b\"",
+        );
+        assert!(dup.validate_tenants().is_err());
+        let unknown = config("[[api.keys]]\nname = \"k\"\nkey = \"s\"\ntenant = \"ken\"");
+        assert!(unknown.validate_tenants().is_err());
+    }
+
+    #[test]
+    fn test_channel_profiles() {
+        let profiles: ChannelProfiles = toml::from_str(
+            r#"
+            [slack]
+            tools = ["search_logs"]
+            style = "Short."
+            ["slack#random"]
+            model = "small"
+            tools = ["query_facts"]
+            [family]
+            memory = ["identity", "user"]
+            "#,
+        )
+        .unwrap();
+        let cli = profiles.for_channel("cli");
+        assert!(cli.allows_tool("shell"));
+        assert_eq!(cli.memory_tiers(), MemoryTier::ALL);
+
+        let general = profiles.for_channel("slack#general");
+        assert!(general.allows_tool("search_logs") && !general.allows_tool("shell"));
+        assert_eq!(general.style.as_deref(), Some("Short."));
+
+        let random = profiles.for_channel("slack#random");
+        assert_eq!(random.model.as_deref(), Some("small"));
+        assert!(random.allows_tool("query_facts") && !random.allows_tool("search_logs"));
+        assert_eq!(random.style.as_deref(), Some("Short."));
+
+        let family = profiles.for_channel("family");
+        assert_eq!(
+            family.memory_tiers(),
+            [MemoryTier::Identity, MemoryTier::User]
+        );
+
+        for typo in [
+            "toolz = [\"shell\"]",
+            "max_token = 100",
+            "max_tool_iteration = 2",
+        ] {
+            let err = toml::from_str::<ChannelProfiles>(&format!("[slack]\n{typo}")).unwrap_err();
+            assert!(err.to_string().contains("unknown field"), "{typo}: {err}");
+        }
+    }
+}
@@ -0,0 +1,455 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow, bail};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as B64;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+
+use crate::config::{self, MemoryConfig};
+use crate::retention;
+
+/// Header of a whole-file ciphertext (core files, summaries, sessions, facts).
+/// The salt the key was derived with follows it.
+const FILE_MAGIC: &[u8] = b"1KORO-ENC1\n";
+/// Prefix of a single encrypted log entry. Daily logs are appended with
+/// O_APPEND, so each entry is sealed on its own line instead of re-encrypting
+/// the whole file.
+const LINE_PREFIX: &str = "enc:";
+const NONCE_LEN: usize = 24;
+const SALT_LEN: usize = 16;
+/// Random salt of a memory directory, created with its first key.
+const SALT_FILE: &str = "memory.salt";
+const KEY_ENV: &str = "IKORO_MEMORY_KEY";
+
+/// XChaCha20-Poly1305 with a key derived (Argon2id, salted per memory
+/// directory) from a secret in a key file or the `IKORO_MEMORY_KEY` env var.
+/// Every ciphertext starts with its salt. Only the directory's own salt is
+/// accepted, so a planted file can't make us run the slow derivation again.
+pub struct Cipher {
+    salt: [u8; SALT_LEN],
+    aead: XChaCha20Poly1305,
+}
+
+fn derive(secret: &[u8], salt: &[u8]) -> Result<XChaCha20Poly1305> {
+    let mut key = [0u8; 32];
+    argon2::Argon2::default()
+        .hash_password_into(secret, salt, &mut key)
+        .map_err(|e| anyhow!("Key derivation failed: {e}"))?;
+    Ok(XChaCha20Poly1305::new(&key.into()))
+}
+
+/// The salt of `base_dir`, created if there is none yet.
+fn store_salt(base_dir: &Path) -> Result<[u8; SALT_LEN]> {
+    let path = base_dir.join(SALT_FILE);
+    match std::fs::read(&path) {
+        Ok(salt) => salt
+            .try_into()
+            .map_err(|_| anyhow!("Corrupt salt file: {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let mut salt = [0u8; SALT_LEN];
+            OsRng.fill_bytes(&mut salt);
+            std::fs::create_dir_all(base_dir)?;
+            std::fs::write(&path, salt)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            Ok(salt)
+        }
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+impl Cipher {
+    /// Derive the key for `secret` under `salt`. Deliberately slow.
+    pub fn new(secret: &[u8], salt: &[u8]) -> Result<Self> {
+        let salt: [u8; SALT_LEN] = salt
+            .try_into()
+            .map_err(|_| anyhow!("Salt must be {SALT_LEN} bytes"))?;
+        Ok(Self {
+            salt,
+            aead: derive(secret, &salt)?,
+        })
+    }
+
+    /// Env var takes precedence over `encryption_key_file`.
+    /// Returns `None` when encryption is not configured.
+    pub fn from_config(config: &MemoryConfig) -> Result<Option<Self>> {
+        let secret = if let Ok(v) = std::env::var(KEY_ENV) {
+            v
+        } else if let Some(path) = &config.encryption_key_file {
+            let path = config::expand_tilde(&path.to_string_lossy());
+            std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read memory key: {}", path.display()))?
+        } else {
+            return Ok(None);
+        };
+        let secret = secret.trim();
+        if secret.len() < 16 {
+            bail!("Memory encryption key is too short (need at least 16 characters)");
+        }
+        let salt = store_salt(&config.base_dir)?;
+        Ok(Some(Self::new(secret.as_bytes(), &salt)?))
+    }
+
+    fn seal(&self, plain: &[u8]) -> Result<Vec<u8>> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ct = self
+            .aead
+            .encrypt(&nonce, plain)
+            .map_err(|_| anyhow!("Encryption failed"))?;
+        let mut out = Vec::with_capacity(SALT_LEN + NONCE_LEN + ct.len());
+        out.extend_from_slice(&self.salt);
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&ct);
+        Ok(out)
+    }
+
+    fn open(&self, sealed: &[u8]) -> Result<Vec<u8>> {
+        if sealed.len() < SALT_LEN + NONCE_LEN {
+            bail!("Encrypted data is truncated");
+        }
+        let (salt, sealed) = sealed.split_at(SALT_LEN);
+        if salt != self.salt {
+            bail!("Encrypted under another memory directory's salt (restore its {SALT_FILE})");
+        }
+        open_with(&self.aead, sealed)
+    }
+
+    pub fn encrypt_file(&self, plain: &str) -> Result<Vec<u8>> {
+        self.encrypt_bytes(plain.as_bytes())
+    }
+
+    pub fn encrypt_bytes(&self, plain: &[u8]) -> Result<Vec<u8>> {
+        let mut out = FILE_MAGIC.to_vec();
+        out.extend_from_slice(&self.seal(plain)?);
+        Ok(out)
+    }
+
+    pub fn encrypt_line(&self, plain: &str) -> Result<String> {
+        Ok(format!(
+            "{LINE_PREFIX}{}",
+            B64.encode(self.seal(plain.as_bytes())?)
+        ))
+    }
+
+    /// Open one encrypted log line (without its newline), if it is one.
+    fn open_line(&self, line: &str) -> Option<Result<Vec<u8>>> {
+        let b64 = line.strip_prefix(LINE_PREFIX)?;
+        Some(
+            B64.decode(b64.trim())
+                .context("Malformed encrypted log line")
+                .and_then(|sealed| self.open(&sealed)),
+        )
+    }
+}
+
+fn open_with(aead: &XChaCha20Poly1305, sealed: &[u8]) -> Result<Vec<u8>> {
+    if sealed.len() < NONCE_LEN {
+        bail!("Encrypted data is truncated");
+    }
+    let (nonce, ct) = sealed.split_at(NONCE_LEN);
+    aead.decrypt(XNonce::from_slice(nonce), ct)
+        .map_err(|_| anyhow!("Decryption failed (wrong memory key?)"))
+}
+
+/// The plaintext of a whole-file ciphertext; `None` if `data` is not one.
+fn open_file(cipher: Option<&Cipher>, data: &[u8]) -> Result<Option<Vec<u8>>> {
+    let Some(sealed) = data.strip_prefix(FILE_MAGIC) else {
+        return Ok(None);
+    };
+    let cipher = cipher.context("File is encrypted but no memory key is configured")?;
+    Ok(Some(cipher.open(sealed)?))
+}
+
+fn is_encrypted_line(line: &[u8]) -> bool {
+    line.starts_with(LINE_PREFIX.as_bytes())
+}
+
+/// Random 128-bit identifier as lowercase hex.
+pub fn random_id() -> String {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(FILE_MAGIC) || data.split(|&b| b == b'\n').any(is_encrypted_line)
+}
+
+/// Decode file contents that may be plaintext, a whole-file ciphertext or a
+/// log with individually encrypted lines (possibly mixed with plaintext
+/// lines written before encryption was enabled).
+pub fn decode(cipher: Option<&Cipher>, data: &[u8]) -> Result<String> {
+    if let Some(plain) = open_file(cipher, data)? {
+        return String::from_utf8(plain).context("Decrypted data is not UTF-8");
+    }
+    let text = String::from_utf8_lossy(data);
+    if !text.lines().any(|l| is_encrypted_line(l.as_bytes())) {
+        return Ok(text.into_owned());
+    }
+    let cipher = cipher.context("Log is encrypted but no memory key is configured")?;
+    let mut out = String::with_capacity(text.len());
+    for line in text.lines() {
+        match cipher.open_line(line) {
+            Some(plain) => out.push_str(&String::from_utf8_lossy(&plain?)),
+            None => out.push_str(line),
+        }
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+pub fn read_text(cipher: Option<&Cipher>, path: &Path) -> Result<String> {
+    let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    decode(cipher, &data).with_context(|| format!("Failed to decode {}", path.display()))
+}
+
+/// Read a binary file (attachments), decrypting whole-file ciphertext.
+pub fn read_bytes(cipher: Option<&Cipher>, path: &Path) -> Result<Vec<u8>> {
+    let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(open_file(cipher, &data)?.unwrap_or(data))
+}
+
+pub fn write_bytes(cipher: Option<&Cipher>, path: &Path, data: &[u8]) -> Result<()> {
+    let data = match cipher {
+        Some(c) => c.encrypt_bytes(data)?,
+        None => data.to_vec(),
+    };
+    std::fs::write(path, data).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Write a whole file, encrypting when a key is configured.
+pub fn write_text(cipher: Option<&Cipher>, path: &Path, content: &str) -> Result<()> {
+    let data = match cipher {
+        Some(c) => c.encrypt_file(content)?,
+        None => content.as_bytes().to_vec(),
+    };
+    std::fs::write(path, data).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+// --- Migration ---
+
+#[derive(Clone, Copy)]
+enum Mode {
+    /// Whole-file ciphertext of UTF-8 text.
+    File,
+    /// One sealed line per log entry.
+    Lines,
+    /// Whole-file ciphertext of arbitrary bytes (attachments).
+    Binary,
+}
+
+/// Files covered by encryption at rest, relative to the memory directory.
+/// Daily logs are line-encrypted; everything else is whole-file.
+fn migratable_files(base_dir: &Path) -> Vec<(PathBuf, Mode)> {
+    let mut files = Vec::new();
+    let mut scan = |dir: &Path, ext: Option<&str>, mode: Mode| {
+        if let Ok(entries) = std::fs::read_dir(dir) {
+            for e in entries.flatten() {
+                let p = e.path();
+                if p.is_file() && ext.is_none_or(|ext| p.extension().is_some_and(|x| x == ext)) {
+                    files.push((p, mode));
+                }
+            }
+        }
+    };
+    scan(&base_dir.join("core"), Some("md"), Mode::File);
+    if let Ok(files) = std::fs::read_dir(base_dir.join(crate::memory::CORE_HISTORY_DIR)) {
+        for file in files.flatten() {
+            scan(&file.path(), Some("md"), Mode::File);
+        }
+    }
+    scan(&base_dir.join("logs/daily"), Some("md"), Mode::Lines);
+    scan(&base_dir.join("logs/weekly"), Some("md"), Mode::File);
+    scan(&base_dir.join("logs/monthly"), Some("md"), Mode::File);
+    scan(&base_dir.join("sessions"), Some("json"), Mode::File);
+    scan(&base_dir.join("jobs"), Some("json"), Mode::File);
+    if let Ok(days) = std::fs::read_dir(base_dir.join("files")) {
+        for day in days.flatten() {
+            scan(&day.path(), None, Mode::Binary);
+        }
+    }
+    let facts = base_dir.join("facts.json");
+    if facts.exists() {
+        files.push((facts, Mode::File));
+    }
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+    files
+}
+
+fn in_target_form(raw: &[u8], encrypt: bool, mode: Mode) -> bool {
+    match (encrypt, mode) {
+        (false, Mode::Binary) => !raw.starts_with(FILE_MAGIC),
+        (false, _) => !is_encrypted(raw),
+        (true, Mode::File | Mode::Binary) => raw.starts_with(FILE_MAGIC),
+        (true, Mode::Lines) => raw
+            .split(|&b| b == b'\n')
+            .filter(|l| !l.is_empty())
+            .all(|l| l.starts_with(LINE_PREFIX.as_bytes())),
+    }
+}
+
+/// `raw` rewritten in the target form, or `None` if it already is.
+fn convert(cipher: &Cipher, raw: &[u8], encrypt: bool, mode: Mode) -> Result<Option<Vec<u8>>> {
+    if in_target_form(raw, encrypt, mode) {
+        return Ok(None);
+    }
+    if let Mode::Binary = mode {
+        let plain = open_file(Some(cipher), raw)?.unwrap_or_else(|| raw.to_vec());
+        return Ok(Some(if encrypt {
+            cipher.encrypt_bytes(&plain)?
+        } else {
+            plain
+        }));
+    }
+    let plain = decode(Some(cipher), raw)?;
+    Ok(Some(match (encrypt, mode) {
+        (false, _) => plain.into_bytes(),
+        (true, Mode::Lines) => {
+            let mut s = String::new();
+            for line in plain.lines() {
+                s.push_str(&cipher.encrypt_line(line)?);
+                s.push('\n');
+            }
+            s.into_bytes()
+        }
+        (true, _) => cipher.encrypt_file(&plain)?,
+    }))
+}
+
+/// Rewrite every memory file encrypted (`encrypt = true`) or as plaintext.
+/// Files already in the target form are left untouched. Monthly log
+/// archives are repacked with each day converted. Returns the number of
+/// files rewritten.
+pub fn migrate(base_dir: &Path, cipher: &Cipher, encrypt: bool) -> Result<usize> {
+    let mut changed = 0;
+    for (path, mode) in migratable_files(base_dir) {
+        let raw = std::fs::read(&path)?;
+        let Some(out) = convert(cipher, &raw, encrypt, mode)
+            .with_context(|| format!("Failed to decode {}", path.display()))?
+        else {
+            continue;
+        };
+        let tmp = path.with_extension(format!("{}.tmp", std::process::id()));
+        std::fs::write(&tmp, out)?;
+        if let Err(e) = std::fs::rename(&tmp, &path) {
+            let _ = std::fs::remove_file(&tmp);
+            return Err(e.into());
+        }
+        changed += 1;
+    }
+    for month in retention::list_archives(base_dir) {
+        let path = retention::archive_path(base_dir, &month);
+        let mut files = retention::read_archive(&path)?;
+        let mut dirty = false;
+        for (date, data) in files.iter_mut() {
+            if let Some(out) = convert(cipher, data, encrypt, Mode::Lines)
+                .with_context(|| format!("Failed to decode {date} in {}", path.display()))?
+            {
+                *data = out;
+                dirty = true;
+            }
+        }
+        if dirty {
+            retention::write_archive(&path, &files)?;
+            changed += 1;
+        }
+    }
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cipher() -> Cipher {
+        Cipher::new(b"correct horse battery staple", b"0123456789abcdef").unwrap()
+    }
+
+    #[test]
+    fn test_file_roundtrip() {
+        let c = cipher();
+        let sealed = c.encrypt_file("# User\n\nTanaka").unwrap();
+        assert!(is_encrypted(&sealed));
+        assert!(!sealed.windows(6).any(|w| w == b"Tanaka"));
+        assert_eq!(decode(Some(&c), &sealed).unwrap(), "# User\n\nTanaka");
+    }
+
+    #[test]
+    fn test_plaintext_passthrough() {
+        assert_eq!(decode(None, b"- hello\n").unwrap(), "- hello\n");
+        assert_eq!(decode(Some(&cipher()), b"- hello\n").unwrap(), "- hello\n");
+    }
+
+    #[test]
+    fn test_mixed_log_lines() {
+        let c = cipher();
+        let log = format!("- old entry\n{}\n", c.encrypt_line("- new entry").unwrap());
+        assert_eq!(
+            decode(Some(&c), log.as_bytes()).unwrap(),
+            "- old entry\n- new entry\n"
+        );
+    }
+
+    #[test]
+    fn test_wrong_key_or_missing_key_fails() {
+        let sealed = cipher().encrypt_file("secret").unwrap();
+        let other = Cipher::new(b"another secret entirely", b"0123456789abcdef").unwrap();
+        assert!(decode(Some(&other), &sealed).is_err());
+        assert!(decode(None, &sealed).is_err());
+    }
+
+    #[test]
+    fn test_salted_keys() {
+        let dir = std::env::temp_dir().join(format!("1koro-crypto-salt-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("core")).unwrap();
+        let key_file = dir.join("memory.key");
+        std::fs::write(&key_file, "correct horse battery staple\n").unwrap();
+        let config = MemoryConfig {
+            base_dir: dir.clone(),
+            encryption_key_file: Some(key_file),
+            ..Default::default()
+        };
+        let c = Cipher::from_config(&config).unwrap().unwrap();
+        let salt = std::fs::read(dir.join(SALT_FILE)).unwrap();
+        assert_eq!(salt.len(), SALT_LEN);
+        assert_eq!(
+            Cipher::from_config(&config).unwrap().unwrap().salt[..],
+            salt
+        );
+
+        // Same secret, another directory's salt: refused without deriving
+        let sealed = c.encrypt_file("# User").unwrap();
+        assert_eq!(&sealed[FILE_MAGIC.len()..][..SALT_LEN], &salt[..]);
+        let err = decode(Some(&cipher()), &sealed).unwrap_err();
+        assert!(format!("{err:#}").contains(SALT_FILE), "{err:#}");
+        let line = cipher().encrypt_line("- planted").unwrap();
+        assert!(decode(Some(&c), line.as_bytes()).is_err());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_migrate_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("1koro-crypto-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("core")).unwrap();
+        std::fs::create_dir_all(dir.join("logs/daily")).unwrap();
+        std::fs::write(dir.join("core/user.md"), "# User\n").unwrap();
+        std::fs::write(dir.join("logs/daily/2026-02-17.md"), "- a\n- b\n").unwrap();
+
+        let c = cipher();
+        assert_eq!(migrate(&dir, &c, true).unwrap(), 2);
+        assert_eq!(migrate(&dir, &c, true).unwrap(), 0);
+        let log = std::fs::read(dir.join("logs/daily/2026-02-17.md")).unwrap();
+        assert!(is_encrypted(&log));
+        assert_eq!(decode(Some(&c), &log).unwrap(), "- a\n- b\n");
+
+        assert_eq!(migrate(&dir, &c, false).unwrap(), 2);
+        assert_eq!(
+            std::fs::read_to_string(dir.join("core/user.md")).unwrap(),
+            "# User\n"
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,233 @@
+use std::io::Write;
+
+use anyhow::Result;
+use chrono::{NaiveDate, Weekday};
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use crate::facts::{FactQuery, FactStore};
+use crate::log_entry;
+use crate::memory::MemoryManager;
+use crate::session::SessionStore;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ExportFilter {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    /// Only logs and sessions from this channel. Core files, summaries and
+    /// facts are not per-channel and are left out when this is set.
+    pub channel: Option<String>,
+    /// Only logs and sessions of this user; like `channel`, this leaves out
+    /// everything that is not per-user.
+    pub user: Option<String>,
+}
+
+impl ExportFilter {
+    fn date_in_range(&self, d: NaiveDate) -> bool {
+        self.from.is_none_or(|f| d >= f) && self.to.is_none_or(|t| d <= t)
+    }
+
+    /// Inclusive [start, end] overlaps the filter range.
+    fn period_in_range(&self, start: NaiveDate, end: NaiveDate) -> bool {
+        self.from.is_none_or(|f| end >= f) && self.to.is_none_or(|t| start <= t)
+    }
+
+    fn channel_matches(&self, channel: Option<&str>) -> bool {
+        match (&self.channel, channel) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(want), Some(c)) => log_entry::channel_matches(want, c),
+        }
+    }
+
+    fn user_matches(&self, user: Option<&str>) -> bool {
+        self.user.as_deref().is_none_or(|want| user == Some(want))
+    }
+}
+
+fn emit(out: &mut impl Write, mut record: Value, kind: &str) -> Result<()> {
+    record["type"] = json!(kind);
+    serde_json::to_writer(&mut *out, &record)?;
+    out.write_all(b"\n")?;
+    Ok(())
+}
+
+/// Write all memory as JSON Lines, one record per log entry, session,
+/// summary, core file and fact, each tagged with a `type` field.
+/// Returns the number of records written.
+pub async fn export(
+    memory: &MemoryManager,
+    sessions: &SessionStore,
+    facts: &FactStore,
+    filter: &ExportFilter,
+    out: &mut impl Write,
+) -> Result<usize> {
+    let mut n = 0;
+
+    for date in memory.list_daily_logs()? {
+        let Ok(d) = NaiveDate::parse_from_str(&date, "%Y-%m-%d") else {
+            continue;
+        };
+        if !filter.date_in_range(d) {
+            continue;
+        }
+        for entry in memory.read_entries(&date)? {
+            if filter.channel_matches(entry.channel.as_deref())
+                && filter.user_matches(entry.user.as_deref())
+            {
+                emit(out, serde_json::to_value(&entry)?, "log")?;
+                n += 1;
+            }
+        }
+    }
+
+    for s in sessions.list().await {
+        let (channel, user) = s.key.split_once(':').unzip();
+        if !filter.channel_matches(channel)
+            || !filter.user_matches(user)
+            || !filter.date_in_range(s.updated_at.date_naive())
+        {
+            continue;
+        }
+        emit(out, serde_json::to_value(&s)?, "session")?;
+        n += 1;
+    }
+
+    if filter.channel.is_some() || filter.user.is_some() {
+        return Ok(n);
+    }
+
+    for id in memory.list_summaries("weekly")? {
+        let monday = id
+            .split_once("-W")
+            .and_then(|(y, w)| Some((y.parse().ok()?, w.parse().ok()?)))
+            .and_then(|(y, w)| NaiveDate::from_isoywd_opt(y, w, Weekday::Mon));
+        let Some(monday) = monday else { continue };
+        let sunday = monday + chrono::Days::new(6);
+        if !filter.period_in_range(monday, sunday) {
+            continue;
+        }
+        let content = memory.read_weekly_summary(&id)?.unwrap_or_default();
+        emit(
+            out,
+            json!({"period": "weekly", "id": id, "content": content}),
+            "summary",
+        )?;
+        n += 1;
+    }
+    for id in memory.list_summaries("monthly")? {
+        let Ok(first) = NaiveDate::parse_from_str(&format!("{id}-01"), "%Y-%m-%d") else {
+            continue;
+        };
+        let last = first
+            .checked_add_months(chrono::Months::new(1))
+            .and_then(|d| d.pred_opt())
+            .unwrap_or(first);
+        if !filter.period_in_range(first, last) {
+            continue;
+        }
+        let content = memory.read_monthly_summary(&id)?.unwrap_or_default();
+        emit(
+            out,
+            json!({"period": "monthly", "id": id, "content": content}),
+            "summary",
+        )?;
+        n += 1;
+    }
+
+    for file in ["identity.md", "user.md", "state.md"] {
+        if let Ok(content) = memory.read_core(file) {
+            emit(out, json!({"file": file, "content": content}), "core")?;
+            n += 1;
+        }
+    }
+
+    let all = facts.query(&FactQuery {
+        history: true,
+        limit: Some(usize::MAX),
+        ..Default::default()
+    });
+    for f in all {
+        if filter.date_in_range(f.timestamp.date_naive()) {
+            emit(out, serde_json::to_value(&f)?, "fact")?;
+            n += 1;
+        }
+    }
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_entry::{LogEntry, Role};
+
+    #[test]
+    fn test_filter() {
+        let f = ExportFilter {
+            from: NaiveDate::from_ymd_opt(2026, 2, 1),
+            to: NaiveDate::from_ymd_opt(2026, 2, 28),
+            channel: Some("slack".into()),
+            user: Some("U1".into()),
+        };
+        assert!(f.date_in_range(NaiveDate::from_ymd_opt(2026, 2, 17).unwrap()));
+        assert!(!f.date_in_range(NaiveDate::from_ymd_opt(2026, 3, 1).unwrap()));
+        assert!(f.period_in_range(
+            NaiveDate::from_ymd_opt(2026, 1, 26).unwrap(),
+            NaiveDate::from_ymd_opt(2026, 2, 1).unwrap()
+        ));
+        assert!(f.channel_matches(Some("slack")));
+        assert!(f.channel_matches(Some("slack#general")));
+        assert!(!f.channel_matches(Some("slackbot")));
+        assert!(!f.channel_matches(None));
+        assert!(f.user_matches(Some("U1")));
+        assert!(!f.user_matches(Some("U2")) && !f.user_matches(None));
+    }
+
+    #[tokio::test]
+    async fn test_export_jsonl() {
+        let base = std::env::temp_dir().join(format!("1koro-export-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&base);
+        let mem = MemoryManager::new(&crate::config::MemoryConfig {
+            base_dir: base.clone(),
+            ..Default::default()
+        })
+        .unwrap();
+        let mut hi = LogEntry::message(Role::User, "cli", "masaki", "masaki", "hi");
+        hi.date = NaiveDate::from_ymd_opt(2026, 2, 17).unwrap();
+        let mut yo = LogEntry::message(Role::User, "slack", "U1", "tanaka", "yo");
+        yo.date = hi.date;
+        mem.append_log(&hi).unwrap();
+        mem.append_log(&yo).unwrap();
+        let sessions = SessionStore::new(base.clone(), None).unwrap();
+        let facts = FactStore::new(&base, None).unwrap();
+
+        let mut buf = Vec::new();
+        let filter = ExportFilter {
+            channel: Some("cli".into()),
+            ..Default::default()
+        };
+        let n = export(&mem, &sessions, &facts, &filter, &mut buf)
+            .await
+            .unwrap();
+        assert_eq!(n, 1);
+        let rec: Value = serde_json::from_slice(buf.trim_ascii_end()).unwrap();
+        assert_eq!(rec["type"], "log");
+        assert_eq!(rec["date"], "2026-02-17");
+        assert_eq!(rec["text"], "hi");
+        assert_eq!(rec["role"], "user");
+        assert_eq!(rec["channel"], "cli");
+
+        let mut buf = Vec::new();
+        let filter = ExportFilter {
+            user: Some("U1".into()),
+            ..Default::default()
+        };
+        let n = export(&mem, &sessions, &facts, &filter, &mut buf)
+            .await
+            .unwrap();
+        assert_eq!(n, 1);
+        let rec: Value = serde_json::from_slice(buf.trim_ascii_end()).unwrap();
+        assert_eq!(rec["text"], "yo");
+        let _ = std::fs::remove_dir_all(base);
+    }
+}
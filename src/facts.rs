@@ -0,0 +1,416 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{self, Cipher};
+use crate::llm::{LlmClient, Message};
+
+/// A single (subject, predicate, object) triple.
+/// Facts are never deleted: a newer value for the same subject/predicate
+/// marks the old one as superseded so the history stays queryable.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fact {
+    pub id: u64,
+    pub subject: String,
+    pub predicate: String,
+    pub object: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    pub timestamp: DateTime<Local>,
+    pub confidence: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub superseded_by: Option<u64>,
+}
+
+impl Fact {
+    pub fn is_current(&self) -> bool {
+        self.superseded_by.is_none()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NewFact {
+    pub subject: String,
+    pub predicate: String,
+    pub object: String,
+    #[serde(default)]
+    pub source: Option<String>,
+    #[serde(default = "default_confidence")]
+    pub confidence: f32,
+}
+
+fn default_confidence() -> f32 {
+    1.0
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct FactQuery {
+    pub subject: Option<String>,
+    pub predicate: Option<String>,
+    /// Free-text match against subject, predicate and object.
+    pub q: Option<String>,
+    #[serde(default)]
+    pub history: bool,
+    pub limit: Option<usize>,
+}
+
+/// Facts live in a single JSON file (`facts.json`) under the memory directory.
+/// The set is small enough to keep in memory and rewrite atomically on change.
+/// Other processes (the stdio MCP server, the daemon) may write it too, so a
+/// change re-reads the file under `facts.lock` first, and queries pick up
+/// their writes.
+pub struct FactStore {
+    path: PathBuf,
+    lock_path: PathBuf,
+    cipher: Option<Arc<Cipher>>,
+    facts: Mutex<Loaded>,
+}
+
+/// The facts as of the file's last seen modification time.
+struct Loaded {
+    facts: Vec<Fact>,
+    modified: Option<SystemTime>,
+}
+
+impl FactStore {
+    pub fn new(base_dir: &Path, cipher: Option<Arc<Cipher>>) -> Result<Self> {
+        let store = Self {
+            path: base_dir.join("facts.json"),
+            lock_path: base_dir.join("facts.lock"),
+            cipher,
+            facts: Mutex::new(Loaded {
+                facts: Vec::new(),
+                modified: None,
+            }),
+        };
+        store.reload(&mut store.facts.lock().expect("fact store lock poisoned"))?;
+        Ok(store)
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        std::fs::metadata(&self.path)
+            .and_then(|m| m.modified())
+            .ok()
+    }
+
+    /// Read the file into `loaded`.
+    fn reload(&self, loaded: &mut Loaded) -> Result<()> {
+        let path = &self.path;
+        let modified = self.modified();
+        loaded.facts = if modified.is_some() {
+            let content = crypto::read_text(self.cipher.as_deref(), path)
+                .with_context(|| format!("Failed to read facts: {}", path.display()))?;
+            serde_json::from_str(&content)
+                .with_context(|| format!("Failed to parse facts: {}", path.display()))?
+        } else {
+            Vec::new()
+        };
+        loaded.modified = modified;
+        Ok(())
+    }
+
+    /// Hold the cross-process lock on the facts file until dropped.
+    fn lock_file(&self) -> Result<std::fs::File> {
+        if let Some(parent) = self.lock_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = std::fs::File::create(&self.lock_path)
+            .with_context(|| format!("Failed to create {}", self.lock_path.display()))?;
+        file.lock()
+            .with_context(|| format!("Failed to lock {}", self.lock_path.display()))?;
+        Ok(file)
+    }
+
+    /// Record a fact. An identical current fact is refreshed rather than
+    /// duplicated; a different object for the same subject/predicate
+    /// supersedes the previous value.
+    pub fn remember(&self, new: NewFact) -> Result<Fact> {
+        let subject = new.subject.trim();
+        let predicate = new.predicate.trim();
+        let object = new.object.trim();
+        if subject.is_empty() || predicate.is_empty() || object.is_empty() {
+            anyhow::bail!("Fact subject, predicate and object must not be empty");
+        }
+        let confidence = new.confidence.clamp(0.0, 1.0);
+
+        let mut loaded = self.facts.lock().expect("fact store lock poisoned");
+        let _file = self.lock_file()?;
+        self.reload(&mut loaded)?;
+        let facts = &mut loaded.facts;
+        let id = facts.iter().map(|f| f.id).max().unwrap_or(0) + 1;
+        let now = Local::now();
+
+        let mut superseded = Vec::new();
+        for (i, f) in facts.iter_mut().enumerate() {
+            if !f.is_current()
+                || !same_key(&f.subject, subject)
+                || !same_key(&f.predicate, predicate)
+            {
+                continue;
+            }
+            if same_key(&f.object, object) {
+                f.confidence = f.confidence.max(confidence);
+                f.timestamp = now;
+                if new.source.is_some() {
+                    f.source = new.source.clone();
+                }
+                let fact = f.clone();
+                self.save(&mut loaded)?;
+                return Ok(fact);
+            }
+            superseded.push(i);
+        }
+        for i in superseded {
+            facts[i].superseded_by = Some(id);
+        }
+
+        let fact = Fact {
+            id,
+            subject: subject.to_string(),
+            predicate: predicate.to_string(),
+            object: object.to_string(),
+            source: new.source,
+            timestamp: now,
+            confidence,
+            superseded_by: None,
+        };
+        facts.push(fact.clone());
+        self.save(&mut loaded)?;
+        Ok(fact)
+    }
+
+    /// Newest first. Superseded facts are only included with `history`.
+    pub fn query(&self, q: &FactQuery) -> Vec<Fact> {
+        let mut loaded = self.facts.lock().expect("fact store lock poisoned");
+        if self.modified() != loaded.modified
+            && let Err(e) = self.reload(&mut loaded)
+        {
+            tracing::warn!("Keeping cached facts: {e:#}");
+        }
+        let text = q.q.as_deref().map(str::to_lowercase);
+        let mut out: Vec<Fact> = loaded
+            .facts
+            .iter()
+            .filter(|f| q.history || f.is_current())
+            .filter(|f| q.subject.as_deref().is_none_or(|s| same_key(&f.subject, s)))
+            .filter(|f| {
+                q.predicate
+                    .as_deref()
+                    .is_none_or(|p| same_key(&f.predicate, p))
+            })
+            .filter(|f| {
+                text.as_deref().is_none_or(|t| {
+                    f.subject.to_lowercase().contains(t)
+                        || f.predicate.to_lowercase().contains(t)
+                        || f.object.to_lowercase().contains(t)
+                })
+            })
+            .cloned()
+            .collect();
+        out.sort_by(|a, b| b.timestamp.cmp(&a.timestamp).then(b.id.cmp(&a.id)));
+        out.truncate(q.limit.unwrap_or(100));
+        out
+    }
+
+    /// Write `loaded` out; the caller holds the file lock.
+    fn save(&self, loaded: &mut Loaded) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp = self
+            .path
+            .with_extension(format!("{}.json.tmp", std::process::id()));
+        crypto::write_text(
+            self.cipher.as_deref(),
+            &tmp,
+            &serde_json::to_string_pretty(&loaded.facts)?,
+        )?;
+        if let Err(e) = std::fs::rename(&tmp, &self.path) {
+            let _ = std::fs::remove_file(&tmp);
+            return Err(e.into());
+        }
+        loaded.modified = self.modified();
+        Ok(())
+    }
+}
+
+fn same_key(a: &str, b: &str) -> bool {
+    a.trim().eq_ignore_ascii_case(b.trim())
+}
+
+pub fn format_fact(f: &Fact) -> String {
+    let mut s = format!(
+        "#{} {} | {} | {} (confidence {:.2}, {})",
+        f.id,
+        f.subject,
+        f.predicate,
+        f.object,
+        f.confidence,
+        f.timestamp.format("%Y-%m-%d %H:%M")
+    );
+    if let Some(by) = f.superseded_by {
+        s.push_str(&format!(" [superseded by #{by}]"));
+    }
+    s
+}
+
+// --- Extraction ---
+
+const EXTRACT_PROMPT: &str = "Extract durable facts about people, projects, places and dates from the log entries below. \
+Only include facts worth remembering long-term (birthdays, roles, deadlines, preferences, relationships). \
+Reply with a JSON array only, no prose: [{\"subject\":\"...\",\"predicate\":\"...\",\"object\":\"...\",\"confidence\":0.0-1.0}]. \
+Reply with [] if there is nothing to remember.";
+
+/// Ask the LLM for facts contained in `entries` and store them,
+/// with the entries themselves as the fact source. Returns how many facts were stored.
+pub async fn extract(llm: &LlmClient, store: &FactStore, entries: &[String]) -> Result<usize> {
+    if entries.is_empty() {
+        return Ok(0);
+    }
+    let msgs = vec![
+        Message::system(EXTRACT_PROMPT),
+        Message::user(entries.join("\n")),
+    ];
+    let resp = llm.chat(msgs, None).await?;
+    let candidates = parse_extraction(resp.content.as_deref().unwrap_or("[]"))?;
+    let source = entries.join("\n");
+    let mut stored = 0;
+    for mut c in candidates {
+        c.source.get_or_insert_with(|| source.clone());
+        match store.remember(c) {
+            Ok(_) => stored += 1,
+            Err(e) => tracing::debug!("Skipping extracted fact: {e}"),
+        }
+    }
+    Ok(stored)
+}
+
+/// Models like to wrap JSON in code fences or add a sentence around it;
+/// take the outermost array.
+fn parse_extraction(text: &str) -> Result<Vec<NewFact>> {
+    let (Some(start), Some(end)) = (text.find('['), text.rfind(']')) else {
+        return Ok(Vec::new());
+    };
+    if end < start {
+        return Ok(Vec::new());
+    }
+    serde_json::from_str(&text[start..=end]).context("Failed to parse extracted facts")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_store(name: &str) -> (PathBuf, FactStore) {
+        let dir = std::env::temp_dir().join(format!("1koro-facts-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let store = FactStore::new(&dir, None).unwrap();
+        (dir, store)
+    }
+
+    fn fact(s: &str, p: &str, o: &str) -> NewFact {
+        NewFact {
+            subject: s.into(),
+            predicate: p.into(),
+            object: o.into(),
+            source: None,
+            confidence: 0.9,
+        }
+    }
+
+    #[test]
+    fn test_supersede_keeps_history() {
+        let (dir, store) = temp_store("supersede");
+        let old = store.remember(fact("Tanaka", "birthday", "03-14")).unwrap();
+        let new = store.remember(fact("tanaka", "Birthday", "04-14")).unwrap();
+
+        let current = store.query(&FactQuery {
+            subject: Some("Tanaka".into()),
+            ..Default::default()
+        });
+        assert_eq!(current.len(), 1);
+        assert_eq!(current[0].object, "04-14");
+
+        let all = store.query(&FactQuery {
+            subject: Some("Tanaka".into()),
+            history: true,
+            ..Default::default()
+        });
+        assert_eq!(all.len(), 2);
+        let old = all.iter().find(|f| f.id == old.id).unwrap();
+        assert_eq!(old.superseded_by, Some(new.id));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_same_fact_is_not_duplicated() {
+        let (dir, store) = temp_store("dedup");
+        store.remember(fact("1koro", "language", "Rust")).unwrap();
+        store.remember(fact("1koro", "language", "rust")).unwrap();
+        let all = store.query(&FactQuery {
+            history: true,
+            ..Default::default()
+        });
+        assert_eq!(all.len(), 1);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_facts_persist() {
+        let (dir, store) = temp_store("persist");
+        store
+            .remember(fact("Project X", "deadline", "2026-03-01"))
+            .unwrap();
+        drop(store);
+        let reopened = FactStore::new(&dir, None).unwrap();
+        let found = reopened.query(&FactQuery {
+            q: Some("deadline".into()),
+            ..Default::default()
+        });
+        assert_eq!(found.len(), 1);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_two_stores_share_the_file() {
+        // The daemon and the stdio MCP server each open the same facts.json.
+        let (dir, daemon) = temp_store("shared");
+        let stdio = FactStore::new(&dir, None).unwrap();
+        daemon.remember(fact("Tanaka", "team", "infra")).unwrap();
+        stdio.remember(fact("Sato", "team", "web")).unwrap();
+        daemon.remember(fact("Tanaka", "team", "ml")).unwrap();
+
+        let all = FactQuery {
+            history: true,
+            ..Default::default()
+        };
+        assert_eq!(stdio.query(&all).len(), 3, "stdio sees the daemon's facts");
+        let reopened = FactStore::new(&dir, None).unwrap();
+        let ids: Vec<u64> = reopened.query(&all).iter().map(|f| f.id).collect();
+        assert_eq!(ids.len(), 3, "no write was lost: {ids:?}");
+        assert!(ids.contains(&1) && ids.contains(&2) && ids.contains(&3));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_remember_rejects_empty() {
+        let (dir, store) = temp_store("empty");
+        assert!(store.remember(fact("", "is", "x")).is_err());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_parse_extraction() {
+        let text = "Here you go:\n```json\n[{\"subject\":\"Tanaka\",\"predicate\":\"birthday\",\"object\":\"March 14\",\"confidence\":0.8}]\n```";
+        let facts = parse_extraction(text).unwrap();
+        assert_eq!(facts.len(), 1);
+        assert_eq!(facts[0].subject, "Tanaka");
+        assert!(parse_extraction("nothing here").unwrap().is_empty());
+        assert!(parse_extraction("[]").unwrap().is_empty());
+    }
+}
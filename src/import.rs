@@ -0,0 +1,496 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveTime, TimeZone, Weekday};
+use serde_json::Value;
+
+use crate::llm::{LlmClient, Message};
+use crate::log_entry::{LogEntry, Role};
+use crate::memory::MemoryManager;
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum Format {
+    /// Slack workspace export (unzipped directory)
+    Slack,
+    /// ChatGPT data export `conversations.json`
+    Chatgpt,
+    /// Markdown journal files with a YYYY-MM-DD date in the file name
+    Markdown,
+}
+
+/// One message from an export, in the shape of the daily log entries
+/// `Agent::handle_message` writes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportedMessage {
+    pub date: NaiveDate,
+    /// Sort key within the export (epoch seconds or position).
+    pub time: f64,
+    pub time_of_day: Option<NaiveTime>,
+    pub channel: String,
+    pub user: String,
+    pub role: Role,
+    pub name: String,
+    pub text: String,
+}
+
+impl ImportedMessage {
+    fn log_entry(&self) -> LogEntry {
+        LogEntry {
+            date: self.date,
+            time: self.time_of_day,
+            channel: Some(self.channel.clone()),
+            user: Some(self.user.clone()),
+            role: self.role,
+            name: Some(self.name.clone()),
+            text: self.text.clone(),
+            tags: Vec::new(),
+        }
+    }
+}
+
+pub struct ImportStats {
+    pub added: usize,
+    pub skipped: usize,
+    pub dates: BTreeSet<NaiveDate>,
+}
+
+pub fn parse(format: Format, path: &Path, owner: &str) -> Result<Vec<ImportedMessage>> {
+    let mut msgs = match format {
+        Format::Slack => parse_slack(path)?,
+        Format::Chatgpt => parse_chatgpt(path, owner)?,
+        Format::Markdown => parse_markdown(path, owner)?,
+    };
+    msgs.sort_by(|a, b| a.date.cmp(&b.date).then(a.time.total_cmp(&b.time)));
+    Ok(msgs)
+}
+
+fn local_datetime(epoch: f64) -> Option<(NaiveDate, NaiveTime)> {
+    Local
+        .timestamp_opt(epoch as i64, 0)
+        .single()
+        .map(|dt: DateTime<Local>| (dt.date_naive(), dt.time()))
+}
+
+// --- Slack ---
+
+/// Slack exports contain `users.json` plus one directory per channel with a
+/// JSON file of messages per day.
+fn parse_slack(dir: &Path) -> Result<Vec<ImportedMessage>> {
+    let mut names: HashMap<String, String> = HashMap::new();
+    if let Ok(content) = std::fs::read_to_string(dir.join("users.json")) {
+        let users: Vec<Value> = serde_json::from_str(&content).context("Invalid users.json")?;
+        for u in users {
+            let id = u["id"].as_str().unwrap_or_default();
+            let name = [
+                &u["profile"]["display_name"],
+                &u["real_name"],
+                &u["profile"]["real_name"],
+                &u["name"],
+            ]
+            .into_iter()
+            .filter_map(Value::as_str)
+            .find(|s| !s.is_empty());
+            if let Some(name) = name {
+                names.insert(id.to_string(), name.to_string());
+            }
+        }
+    }
+
+    let mut out = Vec::new();
+    for channel_dir in std::fs::read_dir(dir)
+        .with_context(|| format!("Cannot read Slack export: {}", dir.display()))?
+        .flatten()
+    {
+        let channel_path = channel_dir.path();
+        if !channel_path.is_dir() {
+            continue;
+        }
+        let channel = format!("slack#{}", channel_dir.file_name().to_string_lossy());
+        for day in std::fs::read_dir(&channel_path)?.flatten() {
+            let p = day.path();
+            if p.extension().is_none_or(|e| e != "json") {
+                continue;
+            }
+            let content = std::fs::read_to_string(&p)?;
+            let msgs: Vec<Value> = serde_json::from_str(&content)
+                .with_context(|| format!("Invalid Slack day file: {}", p.display()))?;
+            for m in msgs {
+                if m["type"].as_str() != Some("message")
+                    || m["subtype"]
+                        .as_str()
+                        .is_some_and(|s| !matches!(s, "bot_message" | "thread_broadcast"))
+                {
+                    continue;
+                }
+                let text = m["text"].as_str().unwrap_or_default().trim();
+                let Some(ts) = m["ts"].as_str().and_then(|t| t.parse::<f64>().ok()) else {
+                    continue;
+                };
+                let Some((date, time_of_day)) = local_datetime(ts) else {
+                    continue;
+                };
+                if text.is_empty() {
+                    continue;
+                }
+                let user = m["user"]
+                    .as_str()
+                    .or(m["bot_id"].as_str())
+                    .unwrap_or("unknown")
+                    .to_string();
+                let name = m["user_profile"]["display_name"]
+                    .as_str()
+                    .filter(|s| !s.is_empty())
+                    .or(m["user_profile"]["real_name"].as_str())
+                    .or(m["username"].as_str())
+                    .map(String::from)
+                    .or_else(|| names.get(&user).cloned())
+                    .unwrap_or_else(|| user.clone());
+                out.push(ImportedMessage {
+                    date,
+                    time: ts,
+                    time_of_day: Some(time_of_day),
+                    channel: channel.clone(),
+                    user,
+                    role: Role::User,
+                    name,
+                    text: text.to_string(),
+                });
+            }
+        }
+    }
+    Ok(out)
+}
+
+// --- ChatGPT ---
+
+fn parse_chatgpt(path: &Path, owner: &str) -> Result<Vec<ImportedMessage>> {
+    let content =
+        std::fs::read_to_string(path).with_context(|| format!("Cannot read {}", path.display()))?;
+    let conversations: Vec<Value> =
+        serde_json::from_str(&content).context("Invalid ChatGPT conversations.json")?;
+    let mut out = Vec::new();
+    for conv in conversations {
+        let Some(mapping) = conv["mapping"].as_object() else {
+            continue;
+        };
+        for node in mapping.values() {
+            let msg = &node["message"];
+            let role = msg["author"]["role"].as_str().unwrap_or_default();
+            if !matches!(role, "user" | "assistant") {
+                continue;
+            }
+            let text = msg["content"]["parts"]
+                .as_array()
+                .map(|parts| {
+                    parts
+                        .iter()
+                        .filter_map(Value::as_str)
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                })
+                .unwrap_or_default();
+            let Some(time) = msg["create_time"].as_f64().or(conv["create_time"].as_f64()) else {
+                continue;
+            };
+            let Some((date, time_of_day)) = local_datetime(time) else {
+                continue;
+            };
+            if text.trim().is_empty() {
+                continue;
+            }
+            let (role, name) = if role == "user" {
+                (Role::User, owner)
+            } else {
+                (Role::Agent, "ChatGPT")
+            };
+            out.push(ImportedMessage {
+                date,
+                time,
+                time_of_day: Some(time_of_day),
+                channel: "chatgpt".into(),
+                user: owner.into(),
+                role,
+                name: name.into(),
+                text: text.trim().to_string(),
+            });
+        }
+    }
+    Ok(out)
+}
+
+// --- Markdown journals ---
+
+fn date_in_name(path: &Path) -> Option<NaiveDate> {
+    let name = path.file_stem()?.to_string_lossy();
+    let b = name.as_bytes();
+    (0..b.len().saturating_sub(9))
+        .filter(|&i| b[i].is_ascii_digit())
+        .find_map(|i| NaiveDate::parse_from_str(name.get(i..i + 10)?, "%Y-%m-%d").ok())
+}
+
+fn collect_markdown(path: &Path, out: &mut Vec<std::path::PathBuf>) -> Result<()> {
+    if path.is_dir() {
+        for e in std::fs::read_dir(path)?.flatten() {
+            collect_markdown(&e.path(), out)?;
+        }
+    } else if path.extension().is_some_and(|e| e == "md") {
+        out.push(path.to_path_buf());
+    }
+    Ok(())
+}
+
+/// Each list item or paragraph becomes one entry; headings are skipped.
+fn parse_markdown(path: &Path, owner: &str) -> Result<Vec<ImportedMessage>> {
+    let mut files = Vec::new();
+    collect_markdown(path, &mut files)?;
+    let mut out = Vec::new();
+    for file in files {
+        let Some(date) = date_in_name(&file) else {
+            tracing::warn!("Skipping {} (no YYYY-MM-DD in name)", file.display());
+            continue;
+        };
+        let content = std::fs::read_to_string(&file)?;
+        let mut blocks: Vec<String> = Vec::new();
+        let mut current = String::new();
+        for line in content.lines() {
+            let t = line.trim();
+            let item = t.strip_prefix("- ").or_else(|| t.strip_prefix("* "));
+            if t.is_empty() || t.starts_with('#') || item.is_some() {
+                if !current.trim().is_empty() {
+                    blocks.push(std::mem::take(&mut current));
+                }
+                current.clear();
+                if let Some(item) = item {
+                    current.push_str(item);
+                }
+                continue;
+            }
+            if !current.is_empty() {
+                current.push('\n');
+            }
+            current.push_str(t);
+        }
+        if !current.trim().is_empty() {
+            blocks.push(current);
+        }
+        for (i, text) in blocks.into_iter().enumerate() {
+            out.push(ImportedMessage {
+                date,
+                time: i as f64,
+                time_of_day: None,
+                channel: "journal".into(),
+                user: owner.into(),
+                role: Role::User,
+                name: owner.into(),
+                text,
+            });
+        }
+    }
+    Ok(out)
+}
+
+// --- Writing ---
+
+/// Append messages to their daily logs, skipping entries already present
+/// so that re-importing the same export is a no-op.
+pub fn import(memory: &MemoryManager, msgs: &[ImportedMessage]) -> Result<ImportStats> {
+    let mut stats = ImportStats {
+        added: 0,
+        skipped: 0,
+        dates: BTreeSet::new(),
+    };
+    let mut seen: HashMap<NaiveDate, HashSet<String>> = HashMap::new();
+    for m in msgs {
+        let existing = match seen.entry(m.date) {
+            std::collections::hash_map::Entry::Occupied(e) => e.into_mut(),
+            std::collections::hash_map::Entry::Vacant(e) => e.insert(
+                memory
+                    .read_entries(&m.date.format("%Y-%m-%d").to_string())?
+                    .iter()
+                    .map(LogEntry::to_markdown)
+                    .collect(),
+            ),
+        };
+        let entry = m.log_entry();
+        if !existing.insert(entry.to_markdown()) {
+            stats.skipped += 1;
+            continue;
+        }
+        memory.append_log(&entry)?;
+        stats.added += 1;
+        stats.dates.insert(m.date);
+    }
+    Ok(stats)
+}
+
+const SUMMARY_PROMPT: &str = "Summarize this period of conversation logs concisely: key events, decisions, people and open threads. Under 300 words.";
+
+/// Write weekly summaries for the weeks touched by an import (and monthly
+/// summaries from those), without replacing summaries that already exist.
+pub async fn summarize(
+    llm: &LlmClient,
+    memory: &MemoryManager,
+    dates: &BTreeSet<NaiveDate>,
+) -> Result<usize> {
+    let mut weeks: BTreeMap<String, NaiveDate> = BTreeMap::new();
+    for d in dates {
+        let w = d.iso_week();
+        let monday = NaiveDate::from_isoywd_opt(w.year(), w.week(), Weekday::Mon).unwrap_or(*d);
+        weeks.insert(format!("{}-W{:02}", w.year(), w.week()), monday);
+    }
+
+    let mut written = 0;
+    let mut months: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (week_id, monday) in &weeks {
+        let summary = match memory.read_weekly_summary(week_id)? {
+            Some(s) => s,
+            None => {
+                let mut logs = String::new();
+                for day in monday.iter_days().take(7) {
+                    let date = day.format("%Y-%m-%d").to_string();
+                    if let Some(log) = memory.read_daily_log(&date)? {
+                        logs.push_str(&format!("## {date}\n{log}\n"));
+                    }
+                }
+                if logs.is_empty() {
+                    continue;
+                }
+                let s = chat_summary(llm, logs).await?;
+                memory.write_weekly_summary(week_id, &s)?;
+                written += 1;
+                s
+            }
+        };
+        months
+            .entry(monday.format("%Y-%m").to_string())
+            .or_default()
+            .push(format!("## {week_id}\n{summary}"));
+    }
+    for (month_id, weeklies) in months {
+        if memory.read_monthly_summary(&month_id)?.is_none() {
+            let s = chat_summary(llm, weeklies.join("\n\n")).await?;
+            memory.write_monthly_summary(&month_id, &s)?;
+            written += 1;
+        }
+    }
+    Ok(written)
+}
+
+async fn chat_summary(llm: &LlmClient, input: String) -> Result<String> {
+    let resp = llm
+        .chat(
+            vec![Message::system(SUMMARY_PROMPT), Message::user(input)],
+            None,
+        )
+        .await?;
+    Ok(resp.content.unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("1koro-import-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_parse_slack() {
+        let dir = temp_dir("slack");
+        std::fs::write(
+            dir.join("users.json"),
+            r#"[{"id":"U1","name":"tanaka","real_name":"Tanaka Taro","profile":{"display_name":""}}]"#,
+        )
+        .unwrap();
+        std::fs::create_dir_all(dir.join("general")).unwrap();
+        std::fs::write(
+            dir.join("general/2024-02-17.json"),
+            r#"[{"type":"message","user":"U1","text":"deploy done","ts":"1708150000.000100"},
+                {"type":"message","subtype":"channel_join","user":"U1","text":"joined","ts":"1708150001.0"}]"#,
+        )
+        .unwrap();
+        let msgs = parse(Format::Slack, &dir, "masaki").unwrap();
+        assert_eq!(msgs.len(), 1);
+        let entry = msgs[0].log_entry().to_markdown();
+        assert!(
+            entry.ends_with(" [slack#general:U1] user Tanaka Taro: deploy done"),
+            "{entry}"
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_parse_chatgpt() {
+        let dir = temp_dir("chatgpt");
+        let file = dir.join("conversations.json");
+        std::fs::write(
+            &file,
+            r#"[{"title":"t","create_time":1708150000.0,"mapping":{
+                "a":{"message":null},
+                "b":{"message":{"author":{"role":"system"},"content":{"parts":["sys"]},"create_time":1708150000.0}},
+                "c":{"message":{"author":{"role":"user"},"content":{"parts":["hello\nthere"]},"create_time":1708150001.0}},
+                "d":{"message":{"author":{"role":"assistant"},"content":{"parts":["hi"]},"create_time":1708150002.0}}
+            }}]"#,
+        )
+        .unwrap();
+        let msgs = parse(Format::Chatgpt, &file, "masaki").unwrap();
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(msgs[0].text, "hello\nthere");
+        assert_eq!(msgs[0].role, Role::User);
+        assert_eq!(msgs[1].role, Role::Agent);
+        assert_eq!(msgs[1].name, "ChatGPT");
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_parse_markdown_blocks() {
+        let dir = temp_dir("md");
+        std::fs::write(
+            dir.join("journal-2026-02-17.md"),
+            "# Tuesday\n\nWent to the gym.\nFelt good.\n\n- call mom\n- buy milk\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("notes.md"), "no date\n").unwrap();
+        let msgs = parse(Format::Markdown, &dir, "masaki").unwrap();
+        let texts: Vec<_> = msgs.iter().map(|m| m.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            ["Went to the gym.\nFelt good.", "call mom", "buy milk"]
+        );
+        assert_eq!(msgs[0].date.to_string(), "2026-02-17");
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_reimport_is_deduplicated() {
+        let base = temp_dir("dedup");
+        let mem = MemoryManager::new(&crate::config::MemoryConfig {
+            base_dir: base.clone(),
+            ..Default::default()
+        })
+        .unwrap();
+        let msg = ImportedMessage {
+            date: NaiveDate::from_ymd_opt(2026, 2, 17).unwrap(),
+            time: 0.0,
+            time_of_day: None,
+            channel: "journal".into(),
+            user: "masaki".into(),
+            role: Role::User,
+            name: "masaki".into(),
+            text: "hello".into(),
+        };
+        let first = import(&mem, &[msg.clone(), msg.clone()]).unwrap();
+        assert_eq!((first.added, first.skipped), (1, 1));
+        let second = import(&mem, &[msg]).unwrap();
+        assert_eq!((second.added, second.skipped), (0, 1));
+        assert_eq!(
+            mem.read_daily_log("2026-02-17").unwrap().unwrap(),
+            "- --:--:-- [journal:masaki] user masaki: hello\n"
+        );
+        let _ = std::fs::remove_dir_all(base);
+    }
+}
@@ -0,0 +1,375 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Local};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sha2::Sha256;
+
+use crate::agent::Agent;
+use crate::api::AttachmentUpload;
+use crate::attachments::Attachment;
+use crate::crypto::{self, Cipher};
+use crate::ratelimit::Limiter;
+
+/// Finished jobs are forgotten after this long.
+const JOB_RETENTION_DAYS: i64 = 7;
+const CALLBACK_ATTEMPTS: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobRequest {
+    pub text: String,
+    pub channel: String,
+    pub user: String,
+    /// Kept until the job has run so a restart can replay it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<AttachmentUpload>,
+    pub callback_url: Option<String>,
+    #[serde(default)]
+    pub message_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CallbackState {
+    pub delivered: bool,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub id: String,
+    pub status: JobStatus,
+    pub created_at: DateTime<Local>,
+    pub updated_at: DateTime<Local>,
+    pub request: JobRequest,
+    pub text: Option<String>,
+    #[serde(default)]
+    pub actions: Vec<Value>,
+    pub error: Option<String>,
+    pub callback: Option<CallbackState>,
+}
+
+impl Job {
+    fn is_finished(&self) -> bool {
+        matches!(
+            self.status,
+            JobStatus::Done | JobStatus::Failed | JobStatus::Cancelled
+        )
+    }
+
+    /// The job as returned by `GET /jobs/{id}` and sent to callbacks.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "job_id": self.id,
+            "status": self.status,
+            "created_at": self.created_at,
+            "updated_at": self.updated_at,
+            "channel": self.request.channel,
+            "user": self.request.user,
+            "text": self.text,
+            "actions": self.actions,
+            "error": self.error,
+            "callback": self.callback,
+        })
+    }
+}
+
+pub fn validate_callback_url(url: &str) -> Result<()> {
+    if !(url.starts_with("https://") || url.starts_with("http://")) {
+        bail!("callback_url must be an http(s) URL");
+    }
+    Ok(())
+}
+
+/// `sha256=<hex>` HMAC of `<timestamp>.<body>`.
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    let hex: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    format!("sha256={hex}")
+}
+
+/// Background `Agent::handle_message` runs, persisted under `jobs/` so a
+/// restart picks up where it left off.
+pub struct JobQueue {
+    dir: PathBuf,
+    cipher: Option<Arc<Cipher>>,
+    agent: Arc<Agent>,
+    limits: Arc<Limiter>,
+    jobs: Mutex<HashMap<String, Job>>,
+    http: reqwest::Client,
+    secret: Option<String>,
+}
+
+impl JobQueue {
+    pub fn new(
+        base_dir: PathBuf,
+        cipher: Option<Arc<Cipher>>,
+        agent: Arc<Agent>,
+        limits: Arc<Limiter>,
+        secret: Option<String>,
+    ) -> Result<Arc<Self>> {
+        let dir = base_dir.join("jobs");
+        std::fs::create_dir_all(&dir)?;
+        let cutoff = Local::now() - chrono::Duration::days(JOB_RETENTION_DAYS);
+        let mut jobs = HashMap::new();
+        for entry in std::fs::read_dir(&dir)?.flatten() {
+            let path = entry.path();
+            if path.extension().is_none_or(|e| e != "json") {
+                continue;
+            }
+            let Ok(job) = crypto::read_text(cipher.as_deref(), &path)
+                .and_then(|c| Ok(serde_json::from_str::<Job>(&c)?))
+            else {
+                tracing::warn!("Skipping unreadable job file {}", path.display());
+                continue;
+            };
+            if job.is_finished() && job.updated_at < cutoff {
+                let _ = std::fs::remove_file(&path);
+                continue;
+            }
+            jobs.insert(job.id.clone(), job);
+        }
+        Ok(Arc::new(Self {
+            dir,
+            cipher,
+            agent,
+            limits,
+            jobs: Mutex::new(jobs),
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(30))
+                .build()?,
+            secret,
+        }))
+    }
+
+    fn save(&self, job: &Job) -> Result<()> {
+        let path = self.dir.join(format!("{}.json", job.id));
+        let tmp = self
+            .dir
+            .join(format!("{}.{}.json.tmp", job.id, std::process::id()));
+        crypto::write_text(
+            self.cipher.as_deref(),
+            &tmp,
+            &serde_json::to_string_pretty(job)?,
+        )?;
+        if let Err(e) = std::fs::rename(&tmp, &path) {
+            let _ = std::fs::remove_file(&tmp);
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// Apply `f` to a job and persist it. Returns the updated job.
+    fn update(&self, id: &str, f: impl FnOnce(&mut Job)) -> Option<Job> {
+        let job = {
+            let mut jobs = self.jobs.lock().expect("job map lock poisoned");
+            let job = jobs.get_mut(id)?;
+            f(job);
+            job.updated_at = Local::now();
+            job.clone()
+        };
+        if let Err(e) = self.save(&job) {
+            tracing::error!("Failed to save job {id}: {e}");
+        }
+        Some(job)
+    }
+
+    pub fn get(&self, id: &str) -> Option<Job> {
+        self.jobs
+            .lock()
+            .expect("job map lock poisoned")
+            .get(id)
+            .cloned()
+    }
+
+    /// Queue a message and start working on it in the background.
+    pub fn submit(self: &Arc<Self>, request: JobRequest) -> Result<Job> {
+        if let Some(url) = &request.callback_url {
+            validate_callback_url(url)?;
+        }
+        let now = Local::now();
+        let job = Job {
+            id: crypto::random_id(),
+            status: JobStatus::Queued,
+            created_at: now,
+            updated_at: now,
+            callback: request
+                .callback_url
+                .as_ref()
+                .map(|_| CallbackState::default()),
+            request,
+            text: None,
+            actions: Vec::new(),
+            error: None,
+        };
+        self.save(&job).context("Failed to persist job")?;
+        self.jobs
+            .lock()
+            .expect("job map lock poisoned")
+            .insert(job.id.clone(), job.clone());
+        tokio::spawn(self.clone().run(job.id.clone()));
+        Ok(job)
+    }
+
+    /// Restart jobs interrupted by a shutdown and retry undelivered
+    /// callbacks. A job that was mid-run is run again from the start.
+    pub fn resume(self: &Arc<Self>) {
+        let pending: Vec<String> = self
+            .jobs
+            .lock()
+            .expect("job map lock poisoned")
+            .values()
+            .filter(|j| !j.is_finished() || j.callback.as_ref().is_some_and(|c| !c.delivered))
+            .map(|j| j.id.clone())
+            .collect();
+        if !pending.is_empty() {
+            tracing::info!("Resuming {} jobs", pending.len());
+        }
+        for id in pending {
+            tokio::spawn(self.clone().run(id));
+        }
+    }
+
+    async fn run(self: Arc<Self>, id: String) {
+        let Some(job) = self.get(&id) else { return };
+        let job = if job.is_finished() {
+            job
+        } else {
+            self.execute(job).await
+        };
+        if job.callback.as_ref().is_some_and(|c| !c.delivered) {
+            self.deliver(job).await;
+        }
+    }
+
+    async fn execute(&self, job: Job) -> Job {
+        let id = job.id.clone();
+        let _permit = self.limits.acquire_queued().await;
+        self.update(&id, |j| j.status = JobStatus::Running);
+        let req = &job.request;
+        let result = async {
+            let attachments = req
+                .attachments
+                .iter()
+                .map(|a| Attachment::from_base64(&a.filename, a.mime_type.as_deref(), &a.data))
+                .collect::<Result<Vec<_>>>()?;
+            self.agent
+                .handle_message(
+                    &req.text,
+                    &req.channel,
+                    &req.user,
+                    attachments,
+                    req.message_id.as_deref(),
+                    None,
+                )
+                .await
+        }
+        .await;
+        self.update(&id, |j| {
+            j.request.attachments.clear();
+            match result {
+                Ok(resp) if resp.cancelled => j.status = JobStatus::Cancelled,
+                Ok(resp) => {
+                    j.status = JobStatus::Done;
+                    j.text = Some(resp.text.unwrap_or_else(|| "(no response)".into()));
+                    j.actions = resp.actions;
+                }
+                Err(e) => {
+                    tracing::error!("Job {} failed: {e}", j.id);
+                    j.status = JobStatus::Failed;
+                    j.error = Some(e.to_string());
+                }
+            }
+        })
+        .unwrap_or(job)
+    }
+
+    async fn deliver(&self, job: Job) {
+        let Some(url) = job.request.callback_url.clone() else {
+            return;
+        };
+        let body = job.to_json().to_string();
+        let mut attempts = job.callback.as_ref().map_or(0, |c| c.attempts);
+        while attempts < CALLBACK_ATTEMPTS {
+            if attempts > 0 {
+                tokio::time::sleep(Duration::from_secs(2u64.pow(attempts))).await;
+            }
+            attempts += 1;
+            let timestamp = Local::now().timestamp();
+            let mut req = self
+                .http
+                .post(&url)
+                .header("content-type", "application/json")
+                .header("x-1koro-job-id", &job.id)
+                .header("x-1koro-timestamp", timestamp.to_string())
+                .body(body.clone());
+            if let Some(secret) = &self.secret {
+                req = req.header(
+                    "x-1koro-signature",
+                    sign(secret, timestamp, body.as_bytes()),
+                );
+            }
+            let error = match req.send().await {
+                Ok(r) if r.status().is_success() => None,
+                Ok(r) => Some(format!("HTTP {}", r.status())),
+                Err(e) => Some(e.to_string()),
+            };
+            let delivered = error.is_none();
+            self.update(&job.id, |j| {
+                j.callback = Some(CallbackState {
+                    delivered,
+                    attempts,
+                    last_error: error.clone(),
+                });
+            });
+            match error {
+                None => return,
+                Some(e) => tracing::warn!("Callback for job {} failed ({attempts}): {e}", job.id),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign() {
+        // Verified with: printf '1700000000.{"a":1}' | openssl dgst -sha256 -hmac secret
+        assert_eq!(
+            sign("secret", 1_700_000_000, br#"{"a":1}"#),
+            "sha256=49f24e537407743fa4a0242bb63b94b9a47ee99cbbe071ccd8a22550ae411686"
+        );
+    }
+
+    #[test]
+    fn test_callback_url_validation() {
+        assert!(validate_callback_url("https://n8n.example.com/webhook/x").is_ok());
+        assert!(validate_callback_url("file:///etc/passwd").is_err());
+    }
+}
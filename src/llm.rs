@@ -9,11 +9,96 @@ use crate::config::LlmConfig;
 
 // --- Message ---
 
+/// Message body: plain text, or typed parts for multimodal input.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Content {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+/// One part of a multimodal message, in the OpenAI chat format.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text {
+        text: String,
+    },
+    /// `url` is an https URL or a `data:<mime>;base64,...` URL.
+    ImageUrl {
+        image_url: ImageUrl,
+    },
+    InputAudio {
+        input_audio: InputAudio,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageUrl {
+    pub url: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputAudio {
+    /// Base64 audio data.
+    pub data: String,
+    /// `wav` or `mp3`.
+    pub format: String,
+}
+
+impl ContentPart {
+    /// Short placeholder for a non-text part, for summaries and logs.
+    fn describe(&self) -> String {
+        match self {
+            Self::Text { text } => text.clone(),
+            Self::ImageUrl { image_url } if image_url.url.starts_with("data:") => "[image]".into(),
+            Self::ImageUrl { image_url } => format!("[image: {}]", image_url.url),
+            Self::InputAudio { input_audio } => format!("[audio: {}]", input_audio.format),
+        }
+    }
+}
+
+impl Content {
+    /// The text of the message; non-text parts are left out.
+    pub fn text(&self) -> String {
+        match self {
+            Self::Text(t) => t.clone(),
+            Self::Parts(parts) => parts
+                .iter()
+                .filter_map(|p| match p {
+                    ContentPart::Text { text } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+
+    /// The text of the message with placeholders for images and audio.
+    pub fn describe(&self) -> String {
+        match self {
+            Self::Text(t) => t.clone(),
+            Self::Parts(parts) => parts
+                .iter()
+                .map(ContentPart::describe)
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+
+    fn is_text_only(&self) -> bool {
+        match self {
+            Self::Text(_) => true,
+            Self::Parts(parts) => parts.iter().all(|p| matches!(p, ContentPart::Text { .. })),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
     pub role: String,
     #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub content: Option<String>,
+    pub content: Option<Content>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub tool_calls: Option<Vec<ToolCall>>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -23,7 +108,7 @@ pub struct Message {
 impl Message {
     fn new(
         role: &str,
-        content: Option<String>,
+        content: Option<Content>,
         tc: Option<Vec<ToolCall>>,
         tid: Option<String>,
     ) -> Self {
@@ -35,19 +120,57 @@ impl Message {
         }
     }
     pub fn system(c: impl Into<String>) -> Self {
-        Self::new("system", Some(c.into()), None, None)
+        Self::new("system", Some(Content::Text(c.into())), None, None)
     }
     pub fn user(c: impl Into<String>) -> Self {
-        Self::new("user", Some(c.into()), None, None)
+        Self::new("user", Some(Content::Text(c.into())), None, None)
+    }
+    pub fn user_parts(parts: Vec<ContentPart>) -> Self {
+        Self::new("user", Some(Content::Parts(parts)), None, None)
     }
     pub fn assistant(c: impl Into<String>) -> Self {
-        Self::new("assistant", Some(c.into()), None, None)
+        Self::new("assistant", Some(Content::Text(c.into())), None, None)
     }
     pub fn assistant_with_tool_calls(c: Option<String>, tc: Vec<ToolCall>) -> Self {
-        Self::new("assistant", c, Some(tc), None)
+        Self::new("assistant", c.map(Content::Text), Some(tc), None)
     }
     pub fn tool_result(id: impl Into<String>, c: impl Into<String>) -> Self {
-        Self::new("tool", Some(c.into()), None, Some(id.into()))
+        Self::new("tool", Some(Content::Text(c.into())), None, Some(id.into()))
+    }
+
+    /// One-line rendering for session summaries: text, placeholders for
+    /// media, and the names and arguments of any tool calls.
+    pub fn describe(&self) -> String {
+        let mut out = self
+            .content
+            .as_ref()
+            .map(Content::describe)
+            .unwrap_or_default();
+        for tc in self.tool_calls.iter().flatten() {
+            if !out.is_empty() {
+                out.push(' ');
+            }
+            out.push_str(&format!(
+                "[called {}({})]",
+                tc.function.name, tc.function.arguments
+            ));
+        }
+        out
+    }
+
+    /// The message as sent on the wire. Text-only parts are collapsed to a
+    /// plain string, which every OpenAI-compatible provider accepts, and
+    /// only user messages keep media parts (others get placeholders).
+    fn to_wire(&self) -> Message {
+        let content = match &self.content {
+            Some(c @ Content::Parts(_)) if c.is_text_only() => Some(Content::Text(c.text())),
+            Some(c @ Content::Parts(_)) if self.role != "user" => Some(Content::Text(c.describe())),
+            other => other.clone(),
+        };
+        Message {
+            content,
+            ..self.clone()
+        }
     }
 }
 
@@ -76,12 +199,15 @@ pub struct LlmResponse {
 
 // --- Client (concrete, no trait) ---
 
+#[derive(Clone)]
 pub struct LlmClient {
     client: Client,
     base_url: String,
     api_key: String,
     model: String,
     max_tokens: u32,
+    vision: bool,
+    audio: bool,
 }
 
 #[derive(Serialize)]
@@ -91,6 +217,8 @@ struct ChatRequest {
     max_tokens: u32,
     #[serde(skip_serializing_if = "Option::is_none")]
     tools: Option<Vec<serde_json::Value>>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
 }
 
 #[derive(Deserialize)]
@@ -103,27 +231,153 @@ struct Choice {
 }
 #[derive(Deserialize)]
 struct ChoiceMessage {
-    content: Option<String>,
+    content: Option<Content>,
     #[serde(default)]
     tool_calls: Option<Vec<ToolCall>>,
 }
 
+// --- Streaming (server-sent events) ---
+
+#[derive(Deserialize)]
+struct StreamChunk {
+    #[serde(default)]
+    choices: Vec<StreamChoice>,
+}
+#[derive(Deserialize)]
+struct StreamChoice {
+    #[serde(default)]
+    delta: StreamDelta,
+}
+#[derive(Default, Deserialize)]
+struct StreamDelta {
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<ToolCallDelta>,
+}
+#[derive(Deserialize)]
+struct ToolCallDelta {
+    #[serde(default)]
+    index: usize,
+    id: Option<String>,
+    function: Option<FunctionDelta>,
+}
+#[derive(Deserialize)]
+struct FunctionDelta {
+    name: Option<String>,
+    arguments: Option<String>,
+}
+
+/// Reassembles a streamed completion. Tool calls arrive in fragments keyed
+/// by index: the id and name first, then the arguments piece by piece.
+#[derive(Default)]
+struct StreamState {
+    content: String,
+    tool_calls: Vec<ToolCall>,
+    done: bool,
+}
+
+impl StreamState {
+    /// Feed one line of the event stream; returns any new text.
+    fn feed_line(&mut self, line: &str) -> Result<Option<String>> {
+        let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+            return Ok(None);
+        };
+        if data == "[DONE]" {
+            self.done = true;
+            return Ok(None);
+        }
+        let value: serde_json::Value =
+            serde_json::from_str(data).context("Failed to parse LLM stream chunk")?;
+        if let Some(err) = value.get("error") {
+            anyhow::bail!("LLM API error in stream: {err}");
+        }
+        let Ok(chunk) = serde_json::from_value::<StreamChunk>(value) else {
+            return Ok(None);
+        };
+        let mut text = None;
+        for choice in chunk.choices {
+            if let Some(c) = choice.delta.content.filter(|c| !c.is_empty()) {
+                self.content.push_str(&c);
+                text = Some(c);
+            }
+            for tc in choice.delta.tool_calls {
+                while self.tool_calls.len() <= tc.index {
+                    self.tool_calls.push(ToolCall {
+                        id: String::new(),
+                        type_: "function".into(),
+                        function: FunctionCall {
+                            name: String::new(),
+                            arguments: String::new(),
+                        },
+                    });
+                }
+                let call = &mut self.tool_calls[tc.index];
+                if let Some(id) = tc.id {
+                    call.id = id;
+                }
+                if let Some(f) = tc.function {
+                    call.function.name.push_str(&f.name.unwrap_or_default());
+                    call.function
+                        .arguments
+                        .push_str(&f.arguments.unwrap_or_default());
+                }
+            }
+        }
+        Ok(text)
+    }
+
+    fn finish(self) -> LlmResponse {
+        LlmResponse {
+            content: Some(self.content).filter(|c| !c.is_empty()),
+            tool_calls: self
+                .tool_calls
+                .into_iter()
+                .filter(|tc| !tc.function.name.is_empty())
+                .collect(),
+        }
+    }
+}
+
 const MAX_RETRIES: u32 = 2;
 
 impl LlmClient {
-    pub async fn chat(
+    /// Whether the model accepts image content parts.
+    pub fn supports_vision(&self) -> bool {
+        self.vision
+    }
+
+    /// Whether the model accepts `input_audio` content parts.
+    pub fn supports_audio(&self) -> bool {
+        self.audio
+    }
+
+    /// The same endpoint with another model and/or token limit.
+    pub fn with_overrides(&self, model: Option<&str>, max_tokens: Option<u32>) -> Self {
+        Self {
+            model: model.map_or_else(|| self.model.clone(), str::to_string),
+            max_tokens: max_tokens.unwrap_or(self.max_tokens),
+            ..self.clone()
+        }
+    }
+
+    fn request(
         &self,
         messages: Vec<Message>,
         tools: Option<&[serde_json::Value]>,
-    ) -> Result<LlmResponse> {
-        let url = format!("{}/chat/completions", self.base_url);
-        let request = ChatRequest {
+        stream: bool,
+    ) -> ChatRequest {
+        ChatRequest {
             model: self.model.clone(),
-            messages,
+            messages: messages.iter().map(Message::to_wire).collect(),
             max_tokens: self.max_tokens,
             tools: tools.map(|t| t.to_vec()),
-        };
+            stream,
+        }
+    }
 
+    /// POST the request, retrying on connection errors, 429 and 5xx.
+    async fn send(&self, request: &ChatRequest) -> Result<reqwest::Response> {
+        let url = format!("{}/chat/completions", self.base_url);
         let mut last_err = None;
         for attempt in 0..=MAX_RETRIES {
             if attempt > 0 {
@@ -136,7 +390,7 @@ impl LlmClient {
                 .client
                 .post(&url)
                 .bearer_auth(&self.api_key)
-                .json(&request)
+                .json(request)
                 .send()
                 .await
             {
@@ -157,25 +411,59 @@ impl LlmClient {
                 let body = response.text().await.unwrap_or_default();
                 anyhow::bail!("LLM API error ({}): {}", status, body);
             }
-
-            let body: ChatResponse = response
-                .json()
-                .await
-                .context("Failed to parse LLM response")?;
-            let choice = body
-                .choices
-                .into_iter()
-                .next()
-                .context("No choices in LLM response")?;
-
-            return Ok(LlmResponse {
-                content: choice.message.content,
-                tool_calls: choice.message.tool_calls.unwrap_or_default(),
-            });
+            return Ok(response);
         }
 
         Err(last_err.unwrap_or_else(|| anyhow::anyhow!("LLM request failed after retries")))
     }
+
+    pub async fn chat(
+        &self,
+        messages: Vec<Message>,
+        tools: Option<&[serde_json::Value]>,
+    ) -> Result<LlmResponse> {
+        let response = self.send(&self.request(messages, tools, false)).await?;
+        let body: ChatResponse = response
+            .json()
+            .await
+            .context("Failed to parse LLM response")?;
+        let choice = body
+            .choices
+            .into_iter()
+            .next()
+            .context("No choices in LLM response")?;
+
+        Ok(LlmResponse {
+            content: choice.message.content.map(|c| c.text()),
+            tool_calls: choice.message.tool_calls.unwrap_or_default(),
+        })
+    }
+
+    /// Like `chat`, but streams the completion and calls `on_delta` with
+    /// each piece of text as it arrives.
+    pub async fn chat_stream(
+        &self,
+        messages: Vec<Message>,
+        tools: Option<&[serde_json::Value]>,
+        on_delta: impl Fn(&str),
+    ) -> Result<LlmResponse> {
+        let mut response = self.send(&self.request(messages, tools, true)).await?;
+        let mut state = StreamState::default();
+        let mut buf = Vec::new();
+        while !state.done {
+            let Some(chunk) = response.chunk().await.context("LLM stream interrupted")? else {
+                break;
+            };
+            buf.extend_from_slice(&chunk);
+            while let Some(end) = buf.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = buf.drain(..=end).collect();
+                if let Some(text) = state.feed_line(String::from_utf8_lossy(&line).trim_end())? {
+                    on_delta(&text);
+                }
+            }
+        }
+        Ok(state.finish())
+    }
 }
 
 // --- Factory ---
@@ -195,5 +483,113 @@ pub fn create_client(config: &LlmConfig) -> Result<Arc<LlmClient>> {
         api_key: config.api_key.clone(),
         model: config.model.clone(),
         max_tokens: config.max_tokens,
+        vision: config.vision,
+        audio: config.audio,
     }))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_legacy_session_message_deserializes() {
+        let m: Message = serde_json::from_str(r#"{"role":"user","content":"hi"}"#).unwrap();
+        assert_eq!(m.content, Some(Content::Text("hi".into())));
+        let m: Message = serde_json::from_str(r#"{"role":"assistant","tool_calls":[]}"#).unwrap();
+        assert!(m.content.is_none());
+    }
+
+    #[test]
+    fn test_parts_roundtrip() {
+        let json = r#"{"role":"user","content":[
+            {"type":"text","text":"what is this?"},
+            {"type":"image_url","image_url":{"url":"data:image/png;base64,AAAA"}},
+            {"type":"input_audio","input_audio":{"data":"UklG","format":"wav"}}
+        ]}"#;
+        let m: Message = serde_json::from_str(json).unwrap();
+        let Some(Content::Parts(parts)) = &m.content else {
+            panic!("expected parts");
+        };
+        assert_eq!(parts.len(), 3);
+        assert_eq!(m.describe(), "what is this?\n[image]\n[audio: wav]");
+        let back = serde_json::to_value(&m).unwrap();
+        assert_eq!(back["content"][2]["input_audio"]["format"], "wav");
+        assert_eq!(back["content"][1]["type"], "image_url");
+    }
+
+    #[test]
+    fn test_wire_format() {
+        let text_only = Message::user_parts(vec![ContentPart::Text { text: "a".into() }]);
+        assert_eq!(text_only.to_wire().content, Some(Content::Text("a".into())));
+
+        let image = ContentPart::ImageUrl {
+            image_url: ImageUrl {
+                url: "https://example.com/a.png".into(),
+            },
+        };
+        let user = Message::user_parts(vec![image.clone()]);
+        assert_eq!(user.to_wire().content, user.content);
+        let tool = Message {
+            role: "tool".into(),
+            ..user
+        };
+        assert_eq!(
+            tool.to_wire().content,
+            Some(Content::Text("[image: https://example.com/a.png]".into()))
+        );
+    }
+
+    #[test]
+    fn test_describe_tool_calls() {
+        let m = Message::assistant_with_tool_calls(
+            None,
+            vec![ToolCall {
+                id: "1".into(),
+                type_: "function".into(),
+                function: FunctionCall {
+                    name: "search_logs".into(),
+                    arguments: r#"{"query":"deploy"}"#.into(),
+                },
+            }],
+        );
+        assert_eq!(m.describe(), r#"[called search_logs({"query":"deploy"})]"#);
+    }
+
+    #[test]
+    fn test_stream_reassembly() {
+        let mut state = StreamState::default();
+        let lines = [
+            ": keep-alive",
+            r#"data: {"choices":[{"delta":{"role":"assistant","content":"Let me "}}]}"#,
+            r#"data: {"choices":[{"delta":{"content":"check."}}]}"#,
+            r#"data: {"choices":[{"delta":{"tool_calls":[{"index":0,"id":"call_1","type":"function","function":{"name":"search_logs","arguments":""}}]}}]}"#,
+            r#"data: {"choices":[{"delta":{"tool_calls":[{"index":0,"function":{"arguments":"{\"query\":"}}]}}]}"#,
+            r#"data: {"choices":[{"delta":{"tool_calls":[{"index":0,"function":{"arguments":"\"deploy\"}"}}]}}]}"#,
+            r#"data: {"choices":[{"delta":{},"finish_reason":"tool_calls"}]}"#,
+            "",
+            "data: [DONE]",
+        ];
+        let deltas: Vec<String> = lines
+            .iter()
+            .filter_map(|l| state.feed_line(l).unwrap())
+            .collect();
+        assert_eq!(deltas, ["Let me ", "check."]);
+        assert!(state.done);
+        let resp = state.finish();
+        assert_eq!(resp.content.as_deref(), Some("Let me check."));
+        assert_eq!(resp.tool_calls.len(), 1);
+        assert_eq!(resp.tool_calls[0].id, "call_1");
+        assert_eq!(
+            resp.tool_calls[0].function.arguments,
+            r#"{"query":"deploy"}"#
+        );
+
+        let mut failed = StreamState::default();
+        assert!(
+            failed
+                .feed_line(r#"data: {"error":{"message":"overloaded"}}"#)
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,504 @@
+use anyhow::{Context, Result, bail};
+use chrono::{Local, NaiveDate, NaiveTime, Timelike};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+/// Who produced a daily log entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Agent,
+    Note,
+}
+
+impl Role {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::User => "user",
+            Self::Agent => "agent",
+            Self::Note => "note",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "user" => Some(Self::User),
+            "agent" => Some(Self::Agent),
+            "note" => Some(Self::Note),
+            _ => None,
+        }
+    }
+}
+
+/// One entry of a daily log (`logs/daily/YYYY-MM-DD.md`).
+///
+/// Written as a Markdown list item that stays readable by hand:
+///
+/// ```text
+/// - 14:03:22 [slack:U123] user Tanaka #deploy: first line
+///   continuation lines are indented by two spaces
+/// - 14:03:30 [slack:U123] agent 1koro: reply
+/// - 15:00:00 note: free-form note
+/// ```
+///
+/// `--:--:--` stands for an unknown time of day (e.g. imported journals).
+/// Entries written before this format (`- [channel:user] name: text` and
+/// `- text`, with multi-line messages spilling onto unindented lines) are
+/// still parsed.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LogEntry {
+    pub date: NaiveDate,
+    pub time: Option<NaiveTime>,
+    pub channel: Option<String>,
+    pub user: Option<String>,
+    pub role: Role,
+    pub name: Option<String>,
+    pub text: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+const NO_TIME: &str = "--:--:--";
+/// Name the agent's own entries are logged under.
+pub const AGENT_NAME: &str = "1koro";
+
+impl LogEntry {
+    /// A conversation turn, timestamped now.
+    pub fn message(role: Role, channel: &str, user: &str, name: &str, text: &str) -> Self {
+        let (date, time) = now();
+        Self {
+            date,
+            time: Some(time),
+            channel: Some(channel.into()),
+            user: Some(user.into()),
+            role,
+            name: Some(name.into()),
+            text: text.into(),
+            tags: Vec::new(),
+        }
+    }
+
+    /// A free-form note, timestamped now.
+    pub fn note(text: &str) -> Self {
+        let (date, time) = now();
+        Self {
+            date,
+            time: Some(time),
+            channel: None,
+            user: None,
+            role: Role::Note,
+            name: None,
+            text: text.into(),
+            tags: Vec::new(),
+        }
+    }
+
+    /// Markdown list item, including the leading `- ` and continuation
+    /// indentation, without a trailing newline.
+    pub fn to_markdown(&self) -> String {
+        let mut s = String::from("- ");
+        match self.time {
+            Some(t) => s.push_str(&t.format("%H:%M:%S").to_string()),
+            None => s.push_str(NO_TIME),
+        }
+        if let (Some(c), Some(u)) = (&self.channel, &self.user) {
+            s.push_str(&format!(" [{c}:{u}]"));
+        }
+        s.push(' ');
+        s.push_str(self.role.as_str());
+        if let Some(n) = &self.name {
+            s.push(' ');
+            s.push_str(n);
+        }
+        for t in &self.tags {
+            s.push_str(" #");
+            s.push_str(t);
+        }
+        s.push_str(": ");
+        let mut lines = self.text.split('\n');
+        s.push_str(lines.next().unwrap_or_default());
+        for l in lines {
+            s.push_str("\n  ");
+            s.push_str(l);
+        }
+        s
+    }
+}
+
+fn now() -> (NaiveDate, NaiveTime) {
+    let now = Local::now();
+    let time = now.time();
+    (now.date_naive(), time.with_nanosecond(0).unwrap_or(time))
+}
+
+/// Parse a daily log into entries. Lines that do not start a new list item
+/// belong to the previous entry.
+pub fn parse(date: NaiveDate, content: &str) -> Vec<LogEntry> {
+    let mut entries: Vec<LogEntry> = Vec::new();
+    for line in content.lines() {
+        if let Some(item) = line.strip_prefix("- ") {
+            entries.push(parse_item(date, item));
+            continue;
+        }
+        let cont = match line.strip_prefix("  ") {
+            Some(c) => c,
+            None if line.trim().is_empty() => continue,
+            None => line,
+        };
+        match entries.last_mut() {
+            Some(e) => {
+                e.text.push('\n');
+                e.text.push_str(cont);
+            }
+            None => entries.push(legacy_note(date, line.trim())),
+        }
+    }
+    entries
+}
+
+fn legacy_note(date: NaiveDate, text: &str) -> LogEntry {
+    LogEntry {
+        date,
+        time: None,
+        channel: None,
+        user: None,
+        role: Role::Note,
+        name: None,
+        text: text.into(),
+        tags: Vec::new(),
+    }
+}
+
+fn parse_item(date: NaiveDate, item: &str) -> LogEntry {
+    parse_structured(date, item).unwrap_or_else(|| parse_legacy(date, item))
+}
+
+fn parse_structured(date: NaiveDate, item: &str) -> Option<LogEntry> {
+    let (time_str, rest) = item.split_once(' ')?;
+    let time = if time_str == NO_TIME {
+        None
+    } else {
+        Some(NaiveTime::parse_from_str(time_str, "%H:%M:%S").ok()?)
+    };
+    let (header, text) = match rest.split_once(": ") {
+        Some((h, t)) => (h, t),
+        None => (rest.strip_suffix(':')?, ""),
+    };
+
+    let (channel, user, header) = match header.strip_prefix('[') {
+        Some(h) => {
+            let (key, h) = h.split_once("] ")?;
+            let (c, u) = key.split_once(':')?;
+            (Some(c.to_string()), Some(u.to_string()), h)
+        }
+        None => (None, None, header),
+    };
+    let mut words: Vec<&str> = header.split(' ').collect();
+    let role = Role::parse(words.first()?)?;
+    words.remove(0);
+    let mut tags = Vec::new();
+    while let Some(tag) = words.last().and_then(|w| w.strip_prefix('#')) {
+        if tag.is_empty() {
+            break;
+        }
+        tags.insert(0, tag.to_string());
+        words.pop();
+    }
+    let name = (!words.is_empty()).then(|| words.join(" "));
+
+    Some(LogEntry {
+        date,
+        time,
+        channel,
+        user,
+        role,
+        name,
+        text: text.to_string(),
+        tags,
+    })
+}
+
+fn parse_legacy(date: NaiveDate, item: &str) -> LogEntry {
+    if let Some(rest) = item.strip_prefix('[')
+        && let Some((key, rest)) = rest.split_once("] ")
+        && let Some((channel, user)) = key.split_once(':')
+        && let Some((name, text)) = rest.split_once(": ")
+    {
+        return LogEntry {
+            date,
+            time: None,
+            channel: Some(channel.into()),
+            user: Some(user.into()),
+            role: if name == AGENT_NAME {
+                Role::Agent
+            } else {
+                Role::User
+            },
+            name: Some(name.into()),
+            text: text.into(),
+            tags: Vec::new(),
+        };
+    }
+    legacy_note(date, item)
+}
+
+/// `channel` equals `want`, or is a sub-channel of it (`slack#general`
+/// matches `slack`).
+pub fn channel_matches(want: &str, channel: &str) -> bool {
+    channel == want
+        || channel
+            .split_once('#')
+            .is_some_and(|(base, _)| base == want)
+}
+
+// --- Search ---
+
+#[derive(Debug, Default, Deserialize)]
+pub struct LogQuery {
+    /// Text to look for (substring, or a regex with `regex`). May be empty
+    /// when at least one filter is given.
+    #[serde(default)]
+    pub query: String,
+    pub channel: Option<String>,
+    /// Matches the entry's user id or display name.
+    pub user: Option<String>,
+    pub role: Option<Role>,
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    #[serde(default)]
+    pub regex: bool,
+    /// Entries of surrounding context to include before and after each hit.
+    #[serde(default)]
+    pub context: usize,
+    pub limit: Option<usize>,
+}
+
+pub struct SearchHit {
+    pub entry: LogEntry,
+    pub before: Vec<LogEntry>,
+    pub after: Vec<LogEntry>,
+}
+
+const MAX_CONTEXT: usize = 10;
+
+impl LogQuery {
+    pub fn has_filters(&self) -> bool {
+        self.channel.is_some()
+            || self.user.is_some()
+            || self.role.is_some()
+            || self.from.is_some()
+            || self.to.is_some()
+    }
+
+    pub fn includes_date(&self, date: NaiveDate) -> bool {
+        self.from.is_none_or(|f| date >= f) && self.to.is_none_or(|t| date <= t)
+    }
+
+    pub fn context(&self) -> usize {
+        self.context.min(MAX_CONTEXT)
+    }
+
+    /// Compile the query into a case-insensitive matcher.
+    pub fn matcher(&self) -> Result<Regex> {
+        if self.query.is_empty() && !self.has_filters() {
+            bail!("Provide a query or at least one filter");
+        }
+        let pattern = if self.regex {
+            self.query.clone()
+        } else {
+            regex::escape(&self.query)
+        };
+        RegexBuilder::new(&pattern)
+            .case_insensitive(true)
+            .size_limit(1 << 20)
+            .build()
+            .with_context(|| format!("Invalid regex: {}", self.query))
+    }
+
+    pub fn matches(&self, entry: &LogEntry, matcher: &Regex) -> bool {
+        if !self.includes_date(entry.date) {
+            return false;
+        }
+        if let Some(want) = &self.channel
+            && !entry
+                .channel
+                .as_deref()
+                .is_some_and(|c| channel_matches(want, c))
+        {
+            return false;
+        }
+        if let Some(want) = &self.user {
+            let hit = [&entry.user, &entry.name]
+                .into_iter()
+                .flatten()
+                .any(|u| u.eq_ignore_ascii_case(want));
+            if !hit {
+                return false;
+            }
+        }
+        if self.role.is_some_and(|r| r != entry.role) {
+            return false;
+        }
+        self.query.is_empty() || matcher.is_match(&entry.text)
+    }
+}
+
+/// Render hits for the LLM / CLI. Matches are marked with `>`, context
+/// entries are indented.
+pub fn format_hits(hits: &[SearchHit]) -> String {
+    let mut out = Vec::new();
+    for h in hits {
+        let line = |e: &LogEntry, mark: &str| {
+            let md = e.to_markdown();
+            let body = md.strip_prefix("- ").unwrap_or(&md).to_string();
+            format!("{mark}[{}] {body}", e.date)
+        };
+        if h.before.is_empty() && h.after.is_empty() {
+            out.push(line(&h.entry, ""));
+            continue;
+        }
+        let mut block: Vec<String> = h.before.iter().map(|e| line(e, "  ")).collect();
+        block.push(line(&h.entry, "> "));
+        block.extend(h.after.iter().map(|e| line(e, "  ")));
+        out.push(block.join("\n"));
+    }
+    out.join(
+        if hits
+            .iter()
+            .any(|h| !h.before.is_empty() || !h.after.is_empty())
+        {
+            "\n---\n"
+        } else {
+            "\n"
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn d() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, 2, 17).unwrap()
+    }
+
+    #[test]
+    fn test_roundtrip_multiline_with_tags() {
+        let e = LogEntry {
+            date: d(),
+            time: NaiveTime::from_hms_opt(14, 3, 22),
+            channel: Some("slack".into()),
+            user: Some("U1".into()),
+            role: Role::User,
+            name: Some("Tanaka Taro".into()),
+            text: "deploy plan:\n- build\n\n- ship: today".into(),
+            tags: vec!["deploy".into(), "infra".into()],
+        };
+        let md = e.to_markdown();
+        assert_eq!(
+            md,
+            "- 14:03:22 [slack:U1] user Tanaka Taro #deploy #infra: deploy plan:\n  - build\n  \n  - ship: today"
+        );
+        assert_eq!(parse(d(), &md), vec![e]);
+    }
+
+    #[test]
+    fn test_note_and_unknown_time() {
+        let mut n = LogEntry::note("bought milk");
+        n.date = d();
+        n.time = None;
+        assert_eq!(n.to_markdown(), "- --:--:-- note: bought milk");
+        assert_eq!(parse(d(), &n.to_markdown()), vec![n]);
+    }
+
+    #[test]
+    fn test_legacy_format() {
+        let log = "- [cli:masaki] masaki: first\nsecond line\n- [cli:masaki] 1koro: reply\n- plain note\n";
+        let entries = parse(d(), log);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].role, Role::User);
+        assert_eq!(entries[0].text, "first\nsecond line");
+        assert_eq!(entries[0].channel.as_deref(), Some("cli"));
+        assert_eq!(entries[1].role, Role::Agent);
+        assert_eq!(entries[2].role, Role::Note);
+        assert_eq!(entries[2].text, "plain note");
+        assert!(entries.iter().all(|e| e.time.is_none()));
+    }
+
+    #[test]
+    fn test_mixed_old_and_new() {
+        let log = "- [cli:masaki] masaki: old\n- 09:00:00 [web:masaki] user masaki: new\n";
+        let entries = parse(d(), log);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].time, NaiveTime::from_hms_opt(9, 0, 0));
+        assert_eq!(entries[1].channel.as_deref(), Some("web"));
+    }
+
+    #[test]
+    fn test_legacy_line_that_looks_timed() {
+        // Not a valid structured header: falls back to a plain note.
+        let entries = parse(d(), "- 10:00 meeting with Tanaka: agenda\n");
+        assert_eq!(entries[0].role, Role::Note);
+        assert_eq!(entries[0].text, "10:00 meeting with Tanaka: agenda");
+    }
+
+    #[test]
+    fn test_query_filters() {
+        let entry = |role, channel: &str, user: &str, text: &str| LogEntry {
+            date: d(),
+            time: None,
+            channel: Some(channel.into()),
+            user: Some(user.into()),
+            role,
+            name: Some(user.into()),
+            text: text.into(),
+            tags: Vec::new(),
+        };
+        let slack = entry(Role::User, "slack#general", "masaki", "Deploy at 3pm");
+        let reply = entry(Role::Agent, "slack", "masaki", "deploy noted");
+        let cli = entry(Role::User, "cli", "masaki", "deploy?");
+
+        let q = LogQuery {
+            query: "deploy".into(),
+            channel: Some("slack".into()),
+            role: Some(Role::User),
+            ..Default::default()
+        };
+        let m = q.matcher().unwrap();
+        assert!(q.matches(&slack, &m));
+        assert!(!q.matches(&reply, &m));
+        assert!(!q.matches(&cli, &m));
+
+        let q = LogQuery {
+            query: r"\d+pm".into(),
+            regex: true,
+            to: NaiveDate::from_ymd_opt(2026, 2, 16),
+            ..Default::default()
+        };
+        assert!(!q.matches(&slack, &q.matcher().unwrap()), "date filter");
+
+        let q = LogQuery {
+            query: r"\d+pm".into(),
+            regex: true,
+            ..Default::default()
+        };
+        assert!(q.matches(&slack, &q.matcher().unwrap()));
+    }
+
+    #[test]
+    fn test_query_requires_text_or_filter() {
+        assert!(LogQuery::default().matcher().is_err());
+        let q = LogQuery {
+            user: Some("masaki".into()),
+            ..Default::default()
+        };
+        assert!(q.matcher().is_ok());
+        let bad = LogQuery {
+            query: "(".into(),
+            regex: true,
+            ..Default::default()
+        };
+        assert!(bad.matcher().is_err());
+    }
+}
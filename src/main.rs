@@ -1,11 +1,27 @@
 mod agent;
 mod api;
+mod attachments;
+mod auth;
+mod backup;
 mod config;
+mod crypto;
+mod export;
+mod facts;
+mod import;
+mod jobs;
 mod llm;
+mod log_entry;
 mod mcp;
 mod memory;
+mod memory_api;
+mod openai;
+mod ratelimit;
+mod retention;
 mod session;
+mod tenant;
 mod tools;
+mod trust;
+mod ws;
 
 use std::sync::Arc;
 use std::time::Duration;
@@ -27,6 +43,9 @@ use tools::ToolKind;
 struct Cli {
     #[arg(short, long, default_value = "~/.1koro/config.toml")]
     config: String,
+    /// Work on this tenant's memory instead of `[memory]`
+    #[arg(long, global = true)]
+    tenant: Option<String>,
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -52,6 +71,115 @@ enum Commands {
     },
     /// Start MCP server on stdio (for Claude Code integration)
     Mcp,
+    /// Write a compressed snapshot of the agent state (secrets stripped)
+    ///
+    /// If a server is running on the memory directory, it takes the snapshot
+    /// so that session writes can be paused for it.
+    Backup {
+        /// Output archive path (default: ./1koro-<timestamp>.tar.gz)
+        file: Option<String>,
+        /// API server URL (default: from `[api] bind`)
+        #[arg(long)]
+        url: Option<String>,
+        /// Admin key for the running server (default: `[api] api_key`)
+        #[arg(long, env = "IKORO_AUTH_TOKEN")]
+        token: Option<String>,
+    },
+    /// Restore agent state from a backup archive
+    Restore {
+        /// Backup archive to restore from
+        file: String,
+        /// Show what would change without writing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Overwrite existing files that differ from the backup
+        #[arg(long)]
+        force: bool,
+    },
+    /// Import past conversations into the daily logs
+    Import {
+        #[arg(long, value_enum)]
+        format: import::Format,
+        /// Export file or directory
+        path: String,
+        /// Your user name in the imported logs (ChatGPT and Markdown;
+        /// default: the tenant's `default_user`)
+        #[arg(long)]
+        user: Option<String>,
+        /// Generate weekly/monthly summaries for imported periods
+        #[arg(long)]
+        summarize: bool,
+    },
+    /// Export logs, sessions, summaries, core files and facts as JSON Lines
+    Export {
+        /// Output file (default: stdout)
+        #[arg(short, long)]
+        output: Option<String>,
+        /// First date to include (YYYY-MM-DD)
+        #[arg(long)]
+        from: Option<chrono::NaiveDate>,
+        /// Last date to include (YYYY-MM-DD)
+        #[arg(long)]
+        to: Option<chrono::NaiveDate>,
+        /// Only logs and sessions from this channel
+        #[arg(long)]
+        channel: Option<String>,
+        /// Only logs and sessions of this user
+        #[arg(long)]
+        user: Option<String>,
+    },
+    /// Manage the memory directory
+    Memory {
+        #[command(subcommand)]
+        action: MemoryAction,
+    },
+    /// Work with daily logs
+    Logs {
+        #[command(subcommand)]
+        action: LogsAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum MemoryAction {
+    /// Encrypt all memory files with the configured key
+    Encrypt,
+    /// Decrypt all memory files back to plaintext
+    Decrypt,
+}
+
+#[derive(Subcommand)]
+enum LogsAction {
+    /// Search daily logs
+    Search {
+        /// Text to find (may be omitted when a filter is given)
+        #[arg(default_value = "")]
+        query: String,
+        /// Only this channel (a base channel also matches e.g. slack#general)
+        #[arg(long)]
+        channel: Option<String>,
+        /// Only entries from this user id or display name
+        #[arg(long)]
+        user: Option<String>,
+        #[arg(long, value_enum)]
+        role: Option<log_entry::Role>,
+        /// First date to include (YYYY-MM-DD)
+        #[arg(long)]
+        from: Option<chrono::NaiveDate>,
+        /// Last date to include (YYYY-MM-DD)
+        #[arg(long)]
+        to: Option<chrono::NaiveDate>,
+        /// Treat the query as a regular expression
+        #[arg(long)]
+        regex: bool,
+        /// Entries of context to show around each hit
+        #[arg(short = 'C', long, default_value_t = 0)]
+        context: usize,
+        #[arg(long, default_value_t = 100)]
+        limit: usize,
+    },
+    /// Apply the [retention] policy now (the daemon also runs it daily)
+    Compact,
 }
 
 #[tokio::main]
@@ -63,15 +191,15 @@ async fn main() -> Result<()> {
         )
         .init();
 
-    let cli = Cli::parse();
-    match cli.command.unwrap_or(Commands::Run) {
+    let mut cli = Cli::parse();
+    match cli.command.take().unwrap_or(Commands::Run) {
         Commands::Init => {
             config::init_config_dir().await?;
             tracing::info!("Initialized ~/.1koro/");
         }
         Commands::Run => run(&cli.config).await?,
         Commands::Status => {
-            let cfg = config::load(&cli.config)?;
+            let cfg = load_config(&cli)?;
             println!(
                 "{}",
                 memory::MemoryManager::new(&cfg.memory)?.read_core("state.md")?
@@ -83,55 +211,269 @@ async fn main() -> Result<()> {
             token,
             channel,
         } => chat(&url, token.as_deref(), &channel, message.as_deref()).await?,
-        Commands::Mcp => mcp_stdio(&cli.config).await?,
+        Commands::Mcp => mcp_stdio(load_config(&cli)?).await?,
+        Commands::Backup { file, url, token } => {
+            let cfg = load_config(&cli)?;
+            let out = file.unwrap_or_else(backup::timestamped_name);
+            if memory::daemon_running(&cfg.memory.base_dir) {
+                let url = url.unwrap_or_else(|| local_url(&cfg.api.bind));
+                let token = token.or_else(|| cfg.api.api_key.clone());
+                let tenant = cli.tenant.as_deref().unwrap_or(config::DEFAULT_TENANT);
+                let files = backup_via_server(&url, token.as_deref(), tenant, &out).await?;
+                println!("Wrote {out} ({files} files, from the running server)");
+            } else {
+                let manifest = backup::create(
+                    &cfg.memory.base_dir,
+                    Some(&config::expand_tilde(&cli.config)),
+                    std::path::Path::new(&out),
+                    None,
+                )?;
+                println!("Wrote {out} ({} files)", manifest.files.len());
+            }
+        }
+        Commands::Restore {
+            file,
+            dry_run,
+            force,
+        } => {
+            let cfg = load_config(&cli)?;
+            let plan = backup::restore(
+                std::path::Path::new(&file),
+                &cfg.memory.base_dir,
+                &config::expand_tilde(&cli.config),
+                dry_run,
+                force,
+            )?;
+            println!(
+                "Backup from {} (1koro {})",
+                plan.manifest.created_at.format("%Y-%m-%d %H:%M:%S"),
+                plan.manifest.agent_version
+            );
+            let mut conflicts = 0;
+            for (path, action) in &plan.actions {
+                let label = match action {
+                    backup::Action::Create => "create",
+                    backup::Action::Overwrite => "overwrite",
+                    backup::Action::Unchanged => continue,
+                    backup::Action::Conflict => {
+                        conflicts += 1;
+                        "conflict"
+                    }
+                };
+                println!("  {label:<9} {path}");
+            }
+            if dry_run {
+                println!("Dry run: nothing written.");
+            }
+            if conflicts > 0 {
+                println!("{conflicts} conflicting files kept as-is (use --force to overwrite).");
+            }
+        }
+        Commands::Import {
+            format,
+            path,
+            user,
+            summarize,
+        } => {
+            let cfg = load_config(&cli)?;
+            let tenant = cli.tenant.as_deref().unwrap_or(config::DEFAULT_TENANT);
+            let owner = user.unwrap_or_else(|| cfg.default_user(tenant).to_string());
+            let mem = memory::MemoryManager::new(&cfg.memory)?;
+            let msgs = import::parse(format, std::path::Path::new(&path), &owner)?;
+            let stats = import::import(&mem, &msgs)?;
+            println!(
+                "Imported {} entries across {} days ({} duplicates skipped)",
+                stats.added,
+                stats.dates.len(),
+                stats.skipped
+            );
+            if summarize && !stats.dates.is_empty() {
+                let llm = llm::create_client(&cfg.llm)?;
+                let n = import::summarize(&llm, &mem, &stats.dates).await?;
+                println!("Wrote {n} summaries");
+            }
+        }
+        Commands::Export {
+            output,
+            from,
+            to,
+            channel,
+            user,
+        } => {
+            let cfg = load_config(&cli)?;
+            let mem = memory::MemoryManager::new(&cfg.memory)?;
+            let sessions = session::SessionStore::new(cfg.memory.base_dir.clone(), mem.cipher())?;
+            let facts = facts::FactStore::new(&cfg.memory.base_dir, mem.cipher())?;
+            let filter = export::ExportFilter {
+                from,
+                to,
+                channel,
+                user,
+            };
+            let n = match output {
+                Some(path) => {
+                    let mut w = std::io::BufWriter::new(std::fs::File::create(&path)?);
+                    let n = export::export(&mem, &sessions, &facts, &filter, &mut w).await?;
+                    std::io::Write::flush(&mut w)?;
+                    n
+                }
+                None => {
+                    let mut w = std::io::stdout().lock();
+                    export::export(&mem, &sessions, &facts, &filter, &mut w).await?
+                }
+            };
+            eprintln!("Exported {n} records");
+        }
+        Commands::Memory { action } => {
+            let cfg = load_config(&cli)?;
+            let cipher = crypto::Cipher::from_config(&cfg.memory)?.ok_or_else(|| {
+                anyhow::anyhow!(
+                    "No memory key configured. Set [memory] encryption_key_file or IKORO_MEMORY_KEY."
+                )
+            })?;
+            let encrypt = matches!(action, MemoryAction::Encrypt);
+            let n = crypto::migrate(&cfg.memory.base_dir, &cipher, encrypt)?;
+            println!(
+                "{} {n} files",
+                if encrypt { "Encrypted" } else { "Decrypted" }
+            );
+        }
+        Commands::Logs {
+            action:
+                LogsAction::Search {
+                    query,
+                    channel,
+                    user,
+                    role,
+                    from,
+                    to,
+                    regex,
+                    context,
+                    limit,
+                },
+        } => {
+            let cfg = load_config(&cli)?;
+            let mem = memory::MemoryManager::new(&cfg.memory)?;
+            let hits = mem.search_logs(&log_entry::LogQuery {
+                query,
+                channel,
+                user,
+                role,
+                from,
+                to,
+                regex,
+                context,
+                limit: Some(limit),
+            })?;
+            if hits.is_empty() {
+                eprintln!("No results found.");
+            } else {
+                println!("{}", log_entry::format_hits(&hits));
+            }
+        }
+        Commands::Logs {
+            action: LogsAction::Compact,
+        } => {
+            let cfg = load_config(&cli)?;
+            if !cfg.retention.is_enabled() {
+                anyhow::bail!("No retention policy configured. Set [retention] in config.toml.");
+            }
+            // The server's sessions are in memory and would overwrite pruned
+            // files; it compacts on its own once a day.
+            if memory::daemon_running(&cfg.memory.base_dir) {
+                anyhow::bail!(
+                    "A 1koro server is running on {}; it compacts logs daily. Stop it to compact now.",
+                    cfg.memory.base_dir.display()
+                );
+            }
+            let mem = memory::MemoryManager::new(&cfg.memory)?;
+            let sessions = session::SessionStore::new(cfg.memory.base_dir.clone(), mem.cipher())?;
+            let s = retention::compact(&cfg.memory.base_dir, &cfg.retention, &sessions).await?;
+            println!(
+                "Archived {} days in {} months, pruned tool output in {} sessions, deleted {} archives",
+                s.days_archived, s.months_archived, s.sessions_pruned, s.archives_deleted
+            );
+        }
     }
     Ok(())
 }
 
+/// The config file, with `[memory]` pointing at `--tenant`'s directory.
+fn load_config(cli: &Cli) -> Result<config::Config> {
+    let mut cfg = config::load(&cli.config)?;
+    if let Some(tenant) = &cli.tenant {
+        cfg.validate_tenants()?;
+        cfg.memory = cfg.tenant_memory(tenant)?;
+        // The shell is not confined to a directory; only the default
+        // tenant gets it.
+        cfg.tools.shell_enabled &= tenant == config::DEFAULT_TENANT;
+    }
+    Ok(cfg)
+}
+
 async fn run(config_path: &str) -> Result<()> {
     let cfg = config::load(config_path)?;
-    let mem = Arc::new(memory::MemoryManager::new(&cfg.memory)?);
+    cfg.validate_tenants()?;
     let llm = llm::create_client(&cfg.llm)?;
+    let limits = Arc::new(ratelimit::Limiter::new(&cfg.api));
 
-    let tool_ctx = tools::ToolContext {
-        memory: mem.clone(),
-        base_dir: cfg.memory.base_dir.clone(),
-    };
-    let mut reg = tools::ToolRegistry::new(tool_ctx);
-    if cfg.tools.shell_enabled {
-        reg.add(ToolKind::Shell(std::time::Duration::from_secs(
-            cfg.tools.shell_timeout,
-        )));
-        tracing::warn!(
-            "Shell tool enabled ({}s timeout) — arbitrary command execution is possible",
-            cfg.tools.shell_timeout
-        );
+    let mut tenants = Vec::new();
+    let mut locks = Vec::new();
+    let names =
+        std::iter::once(config::DEFAULT_TENANT).chain(cfg.tenants.iter().map(|t| t.name.as_str()));
+    for name in names {
+        let memory = cfg.tenant_memory(name)?;
+        if name != config::DEFAULT_TENANT {
+            config::init_memory_dir(&memory.base_dir).await?;
+        }
+        locks.push(memory::lock_for_daemon(&memory.base_dir)?);
+        let tenant = tenant::Tenant::open(name, &cfg, &memory, llm.clone(), limits.clone())?;
+        if cfg.backup.interval_hours > 0 {
+            let dir = match &cfg.backup.dir {
+                Some(d) if name == config::DEFAULT_TENANT => {
+                    config::expand_tilde(&d.to_string_lossy())
+                }
+                Some(d) => config::expand_tilde(&d.to_string_lossy()).join(name),
+                None => memory.base_dir.join("backups"),
+            };
+            backup::spawn_rotation(
+                memory.base_dir.clone(),
+                config::expand_tilde(config_path),
+                dir,
+                Duration::from_secs(cfg.backup.interval_hours * 3600),
+                cfg.backup.keep,
+                tenant.sessions.clone(),
+            );
+        }
+        if cfg.retention.is_enabled() {
+            retention::spawn(
+                memory.base_dir.clone(),
+                cfg.retention.clone(),
+                tenant.sessions.clone(),
+            );
+        }
+        tenant.jobs.resume();
+        tenants.push(tenant);
     }
-    reg.add(ToolKind::SearchLogs);
-    reg.add(ToolKind::ReadCoreMemory);
-    reg.add(ToolKind::UpdateCoreMemory);
-    reg.add(ToolKind::AppendLog);
-    reg.add(ToolKind::ReadDailyLog);
-    reg.add(ToolKind::WriteSummary);
-    reg.add(ToolKind::ReadFile);
-
-    let skills = agent::load_skills(&cfg.memory.base_dir)?;
-    if !skills.is_empty() {
-        tracing::info!("Loaded {} skills", skills.len());
+    if !cfg.tenants.is_empty() {
+        tracing::info!("Serving {} tenants", tenants.len());
     }
+    let tenants = Arc::new(tenant::Tenants::new(tenants));
+    let owner = tenants
+        .get(None)
+        .expect("the default tenant is always opened");
 
-    let sessions = session::SessionStore::new(cfg.memory.base_dir.clone())?;
-    let agent = agent::Agent::new(llm, mem.clone(), sessions, reg, skills);
-
+    let keys = Arc::new(auth::KeyStore::new(&cfg.api));
     if cfg.mcp.enabled {
-        if cfg.mcp.api_key.is_none() && !is_localhost(&cfg.mcp.bind) {
+        if cfg.mcp.api_key.is_none() && keys.is_empty() && !is_localhost(&cfg.mcp.bind) {
             anyhow::bail!(
-                "MCP authentication required for non-localhost binding '{}'. Set [mcp] api_key.",
+                "MCP authentication required for non-localhost binding '{}'. Set [mcp] api_key or an [[api.keys]] entry with the mcp scope.",
                 cfg.mcp.bind
             );
         }
         let mcp_ctx = tools::ToolContext {
-            memory: mem.clone(),
+            memory: owner.memory.clone(),
+            facts: owner.facts.clone(),
             base_dir: cfg.memory.base_dir.clone(),
         };
         let mut mcp_reg = tools::ToolRegistry::new(mcp_ctx);
@@ -139,33 +481,39 @@ async fn run(config_path: &str) -> Result<()> {
         mcp_reg.add(ToolKind::ReadCoreMemory);
         mcp_reg.add(ToolKind::UpdateCoreMemory);
         mcp_reg.add(ToolKind::ReadDailyLog);
+        mcp_reg.add(ToolKind::QueryFacts);
         mcp::start(
             &cfg.mcp.bind,
             Arc::new(mcp_reg),
             &cfg.agent.name,
             cfg.mcp.api_key.clone(),
+            keys.clone(),
         )
         .await?;
-        if cfg.mcp.api_key.is_none() {
+        if cfg.mcp.api_key.is_none() && keys.is_empty() {
             tracing::warn!("MCP authentication disabled (localhost-only)");
         }
     }
 
-    if cfg.api.api_key.is_none() {
-        if is_localhost(&cfg.api.bind) {
+    let localhost = is_localhost(&cfg.api.bind);
+    if keys.is_empty() {
+        if localhost {
             tracing::warn!("API authentication disabled (localhost-only)");
         } else {
             anyhow::bail!(
-                "API authentication required for non-localhost binding '{}'. Set [api] api_key.",
+                "API authentication required for non-localhost binding '{}'. Set [api] api_key or add [[api.keys]].",
                 cfg.api.bind
             );
         }
     }
+    spawn_key_reload(keys.clone(), config::expand_tilde(config_path), localhost);
 
     let state = api::AppState {
-        agent: Arc::new(agent),
+        tenants,
+        limits,
         name: cfg.agent.name.clone(),
-        api_key: cfg.api.api_key.clone(),
+        keys,
+        config_path: config::expand_tilde(config_path),
     };
     let listener = tokio::net::TcpListener::bind(&cfg.api.bind).await?;
     tracing::info!("{} listening on {}", cfg.agent.name, cfg.api.bind);
@@ -175,14 +523,40 @@ async fn run(config_path: &str) -> Result<()> {
             signal::ctrl_c().await.ok();
         })
         .await?;
+    drop(locks);
     Ok(())
 }
 
-async fn mcp_stdio(config_path: &str) -> Result<()> {
-    let cfg = config::load(config_path)?;
+/// Re-read `[api]` keys on SIGHUP, so a key can be revoked without a restart.
+#[cfg(unix)]
+fn spawn_key_reload(keys: Arc<auth::KeyStore>, config_path: std::path::PathBuf, localhost: bool) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(e) => {
+            tracing::warn!("Cannot listen for SIGHUP, API keys will not reload: {e}");
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            match keys.reload(&config_path, localhost) {
+                Ok(n) => tracing::info!("Reloaded {n} API keys"),
+                Err(e) => tracing::error!("API key reload failed, keeping current keys: {e}"),
+            }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+fn spawn_key_reload(_: Arc<auth::KeyStore>, _: std::path::PathBuf, _: bool) {}
+
+async fn mcp_stdio(cfg: config::Config) -> Result<()> {
     let mem = Arc::new(memory::MemoryManager::new(&cfg.memory)?);
     let ctx = tools::ToolContext {
         memory: mem.clone(),
+        facts: Arc::new(facts::FactStore::new(&cfg.memory.base_dir, mem.cipher())?),
         base_dir: cfg.memory.base_dir.clone(),
     };
     let mut reg = tools::ToolRegistry::new(ctx);
@@ -193,6 +567,8 @@ async fn mcp_stdio(config_path: &str) -> Result<()> {
     reg.add(ToolKind::ReadDailyLog);
     reg.add(ToolKind::WriteSummary);
     reg.add(ToolKind::ReadFile);
+    reg.add(ToolKind::RememberFact);
+    reg.add(ToolKind::QueryFacts);
     if cfg.tools.shell_enabled {
         reg.add(ToolKind::Shell(Duration::from_secs(cfg.tools.shell_timeout)));
     }
@@ -283,6 +659,48 @@ async fn send_message(
         .to_string())
 }
 
+/// Fetch a backup of `tenant` from the running server into `out`. Returns
+/// the number of files in it.
+async fn backup_via_server(
+    url: &str,
+    token: Option<&str>,
+    tenant: &str,
+    out: &str,
+) -> Result<usize> {
+    let mut req = reqwest::Client::new().post(format!("{url}/backup"));
+    if let Some(t) = token {
+        req = req.header("Authorization", format!("Bearer {t}"));
+    }
+    let resp = req.send().await?;
+    if !resp.status().is_success() {
+        anyhow::bail!("API error: {} {}", resp.status(), resp.text().await?);
+    }
+    let header = |name| {
+        resp.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string()
+    };
+    let (got, files) = (header("x-backup-tenant"), header("x-backup-files"));
+    if got != tenant {
+        anyhow::bail!("The key belongs to tenant '{got}', not '{tenant}'");
+    }
+    let data = resp.bytes().await?;
+    let tmp = format!("{out}.{}.tmp", std::process::id());
+    std::fs::write(&tmp, &data)?;
+    std::fs::rename(&tmp, out)?;
+    Ok(files.parse().unwrap_or_default())
+}
+
+/// Where to reach the server bound to `bind` from this machine.
+fn local_url(bind: &str) -> String {
+    let local = bind
+        .replace("0.0.0.0:", "127.0.0.1:")
+        .replace("[::]:", "[::1]:");
+    format!("http://{local}")
+}
+
 fn is_localhost(bind: &str) -> bool {
     use std::net::IpAddr;
 
@@ -1,6 +1,7 @@
 use std::sync::Arc;
 
 use anyhow::Result;
+use axum::Extension;
 use axum::extract::{Request, State};
 use axum::http::StatusCode;
 use axum::middleware::{self, Next};
@@ -9,6 +10,7 @@ use axum::routing::post;
 use axum::{Json, Router};
 use serde_json::{Value, json};
 
+use crate::auth::{self, KeyStore, Principal, Scope};
 use crate::tools::ToolRegistry;
 
 #[derive(Clone)]
@@ -16,6 +18,7 @@ struct McpState {
     registry: Arc<ToolRegistry>,
     name: String,
     api_key: Option<String>,
+    keys: Arc<KeyStore>,
 }
 
 pub fn rpc_ok(id: Value, result: Value) -> Value {
@@ -63,11 +66,13 @@ pub async fn start(
     registry: Arc<ToolRegistry>,
     name: &str,
     api_key: Option<String>,
+    keys: Arc<KeyStore>,
 ) -> Result<()> {
     let state = McpState {
         registry,
         name: name.to_string(),
         api_key,
+        keys,
     };
     let app = Router::new()
         .route("/mcp", post(handle_rpc))
@@ -83,26 +88,59 @@ pub async fn start(
     Ok(())
 }
 
-async fn auth_layer(State(state): State<McpState>, req: Request, next: Next) -> impl IntoResponse {
-    if let Some(ref expected) = state.api_key {
-        let auth_ok = req
-            .headers()
-            .get("authorization")
-            .and_then(|v| v.to_str().ok())
-            .and_then(|v| v.strip_prefix("Bearer "))
-            .is_some_and(|t| t == expected);
-        if !auth_ok {
-            return (
-                StatusCode::UNAUTHORIZED,
-                Json(rpc_err(Value::Null, -32000, "Unauthorized")),
-            )
-                .into_response();
+/// Accepts `[mcp] api_key` (full access) or an API key with the `mcp`
+/// scope. With neither configured the server is open (localhost only).
+/// MCP serves the default tenant, so keys of other tenants are refused.
+async fn auth_layer(
+    State(state): State<McpState>,
+    mut req: Request,
+    next: Next,
+) -> impl IntoResponse {
+    let token = auth::bearer_token(req.headers());
+    let principal = if state.api_key.is_none() && state.keys.is_empty() {
+        Some(Principal::anonymous())
+    } else if let Some(token) = token {
+        if state
+            .api_key
+            .as_deref()
+            .is_some_and(|k| auth::ct_eq(k, token))
+        {
+            Some(Principal::anonymous())
+        } else {
+            state
+                .keys
+                .authenticate(token)
+                .filter(|p| p.allows(Scope::Mcp) && p.tenant.is_none())
         }
-    }
+    } else {
+        None
+    };
+    let Some(principal) = principal else {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(rpc_err(Value::Null, -32000, "Unauthorized")),
+        )
+            .into_response();
+    };
+    tracing::info!("MCP request with key '{}'", principal.name);
+    req.extensions_mut().insert(principal);
     next.run(req).await.into_response()
 }
 
-async fn handle_rpc(State(state): State<McpState>, Json(req): Json<Value>) -> impl IntoResponse {
+async fn handle_rpc(
+    State(state): State<McpState>,
+    Extension(principal): Extension<Principal>,
+    Json(req): Json<Value>,
+) -> impl IntoResponse {
+    if req["method"] == "tools/call"
+        && let Some(tool) = req["params"]["name"].as_str()
+        && state.registry.writes_memory(tool)
+        && !principal.allows(Scope::MemoryWrite)
+    {
+        let id = req.get("id").cloned().unwrap_or(Value::Null);
+        let msg = format!("Tool '{tool}' requires the memory:write scope");
+        return (StatusCode::OK, Json(rpc_err(id, -32000, &msg)));
+    }
     let resp = handle_request(&state.registry, &state.name, &req).await;
     (StatusCode::OK, Json(resp))
 }
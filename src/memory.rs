@@ -1,23 +1,68 @@
 use anyhow::{Context, Result, bail};
-use chrono::Local;
-use std::path::PathBuf;
+use chrono::NaiveDate;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 
 use crate::config::MemoryConfig;
+use crate::crypto::{self, Cipher};
+use crate::log_entry::{self, LogEntry, LogQuery, SearchHit};
+use crate::retention::{self, MonthArchive};
 
 const CORE_FILES: &[&str] = &["identity.md", "user.md", "state.md"];
 const WRITABLE_CORE_FILES: &[&str] = &["user.md", "state.md"];
+/// Previous versions of core files, one directory per file.
+pub const CORE_HISTORY_DIR: &str = "core/history";
+const MAX_CORE_VERSIONS: usize = 50;
+/// Locked by a running server for every memory directory it serves.
+const DAEMON_LOCK: &str = "daemon.lock";
 
 pub struct MemoryManager {
     base_dir: PathBuf,
+    cipher: Option<Arc<Cipher>>,
+    /// Last monthly log archive read, keyed by month and mtime, so a search
+    /// walking day by day decompresses each archive once.
+    archive_cache: Mutex<Option<CachedArchive>>,
+    /// Dates in each monthly archive, by month, with the archive's mtime, so
+    /// listing logs does not decompress every archive every time.
+    archive_dates: Mutex<HashMap<String, (SystemTime, Vec<String>)>>,
+}
+
+struct CachedArchive {
+    month: String,
+    modified: SystemTime,
+    files: Arc<MonthArchive>,
 }
 
 impl MemoryManager {
     pub fn new(config: &MemoryConfig) -> Result<Self> {
         Ok(Self {
             base_dir: config.base_dir.clone(),
+            cipher: Cipher::from_config(config)?.map(Arc::new),
+            archive_cache: Mutex::new(None),
+            archive_dates: Mutex::new(HashMap::new()),
         })
     }
 
+    pub fn base_dir(&self) -> &Path {
+        &self.base_dir
+    }
+
+    /// Encryption-at-rest key, shared with `SessionStore` and `FactStore`.
+    pub fn cipher(&self) -> Option<Arc<Cipher>> {
+        self.cipher.clone()
+    }
+
+    /// Read any file under the memory directory, decrypting if needed.
+    pub fn read_text(&self, path: &Path) -> Result<String> {
+        crypto::read_text(self.cipher.as_deref(), path)
+    }
+
+    fn write_text(&self, path: &Path, content: &str) -> Result<()> {
+        crypto::write_text(self.cipher.as_deref(), path, content)
+    }
+
     // --- Validation ---
 
     fn validate_core_read(filename: &str) -> Result<()> {
@@ -93,26 +138,113 @@ impl MemoryManager {
     pub fn read_core(&self, filename: &str) -> Result<String> {
         Self::validate_core_read(filename)?;
         let path = self.base_dir.join("core").join(filename);
-        std::fs::read_to_string(&path)
+        self.read_text(&path)
             .with_context(|| format!("Failed to read core memory: {}", path.display()))
     }
 
     pub fn write_core(&self, filename: &str, content: &str) -> Result<()> {
         Self::validate_core_write(filename)?;
+        self.replace_core(filename, content)
+    }
+
+    /// Like `write_core` but also allows `identity.md`, which the LLM may
+    /// only read. For edits made by an admin.
+    pub fn write_core_as_admin(&self, filename: &str, content: &str) -> Result<()> {
+        Self::validate_core_read(filename)?;
+        self.replace_core(filename, content)
+    }
+
+    /// Write a core file, keeping the previous content as a version.
+    fn replace_core(&self, filename: &str, content: &str) -> Result<()> {
         let path = self.base_dir.join("core").join(filename);
-        std::fs::write(&path, content)
+        if path.exists() {
+            let previous = self.read_text(&path)?;
+            if previous == content {
+                return Ok(());
+            }
+            self.save_core_version(filename, &previous)?;
+        }
+        self.write_text(&path, content)
             .with_context(|| format!("Failed to write core memory: {}", path.display()))
     }
 
+    fn core_history_dir(&self, filename: &str) -> PathBuf {
+        self.base_dir.join(CORE_HISTORY_DIR).join(filename)
+    }
+
+    fn save_core_version(&self, filename: &str, content: &str) -> Result<()> {
+        let dir = self.core_history_dir(filename);
+        std::fs::create_dir_all(&dir)?;
+        let stamp = chrono::Local::now().format("%Y%m%dT%H%M%S").to_string();
+        let mut version = stamp.clone();
+        let mut n = 1;
+        while dir.join(format!("{version}.md")).exists() {
+            n += 1;
+            version = format!("{stamp}-{n}");
+        }
+        self.write_text(&dir.join(format!("{version}.md")), content)?;
+        let versions = self.core_history(filename)?;
+        for old in versions.iter().skip(MAX_CORE_VERSIONS) {
+            let _ = std::fs::remove_file(dir.join(format!("{old}.md")));
+        }
+        Ok(())
+    }
+
+    /// Saved versions of a core file (`YYYYMMDDTHHMMSS`), newest first. Each
+    /// holds the content as it was before the change made at that time.
+    pub fn core_history(&self, filename: &str) -> Result<Vec<String>> {
+        Self::validate_core_read(filename)?;
+        let Ok(entries) = std::fs::read_dir(self.core_history_dir(filename)) else {
+            return Ok(Vec::new());
+        };
+        let mut versions: Vec<String> = entries
+            .flatten()
+            .filter_map(|e| {
+                let name = e.file_name().to_string_lossy().to_string();
+                name.strip_suffix(".md").map(str::to_string)
+            })
+            .filter(|v| Self::validate_version(v).is_ok())
+            .collect();
+        versions.sort_by(|a, b| b.cmp(a));
+        Ok(versions)
+    }
+
+    pub fn read_core_version(&self, filename: &str, version: &str) -> Result<String> {
+        Self::validate_core_read(filename)?;
+        Self::validate_version(version)?;
+        let path = self
+            .core_history_dir(filename)
+            .join(format!("{version}.md"));
+        self.read_text(&path)
+            .with_context(|| format!("No version {version} of {filename}"))
+    }
+
+    fn validate_version(version: &str) -> Result<()> {
+        let (stamp, n) = version.split_once('-').unwrap_or((version, "1"));
+        let b = stamp.as_bytes();
+        if b.len() != 15
+            || b[8] != b'T'
+            || !b[..8].iter().all(u8::is_ascii_digit)
+            || !b[9..].iter().all(u8::is_ascii_digit)
+            || n.is_empty()
+            || !n.bytes().all(|c| c.is_ascii_digit())
+        {
+            bail!("Invalid version id: {version}");
+        }
+        Ok(())
+    }
+
     // --- Daily Logs ---
 
-    /// Append a log entry using O_APPEND for atomic writes.
-    /// Concurrent appends are safe — both writes will be preserved.
-    pub fn append_log(&self, entry: &str) -> Result<()> {
+    /// Append a log entry to the daily log of its date using O_APPEND for
+    /// atomic writes. Concurrent appends are safe — both writes will be
+    /// preserved. With encryption enabled each entry (including any
+    /// continuation lines) is sealed on its own line.
+    pub fn append_log(&self, entry: &LogEntry) -> Result<()> {
         use std::io::Write;
 
-        let today = Local::now().format("%Y-%m-%d").to_string();
-        let path = self.base_dir.join(format!("logs/daily/{today}.md"));
+        let date = entry.date.format("%Y-%m-%d");
+        let path = self.base_dir.join(format!("logs/daily/{date}.md"));
         if let Some(parent) = path.parent() {
             std::fs::create_dir_all(parent)?;
         }
@@ -123,48 +255,150 @@ impl MemoryManager {
             .open(&path)
             .with_context(|| format!("Failed to open log: {}", path.display()))?;
 
-        writeln!(file, "- {entry}")
+        let text = entry.to_markdown();
+        let line = match &self.cipher {
+            Some(c) => c.encrypt_line(&text)?,
+            None => text,
+        };
+        writeln!(file, "{line}")
             .with_context(|| format!("Failed to append to log: {}", path.display()))
     }
 
-    pub fn search_logs(&self, query: &str, max_results: usize) -> Result<Vec<String>> {
-        let logs_dir = self.base_dir.join("logs/daily");
+    /// Parsed entries of one daily log (empty if there is none).
+    pub fn read_entries(&self, date: &str) -> Result<Vec<LogEntry>> {
+        let Some(content) = self.read_daily_log(date)? else {
+            return Ok(Vec::new());
+        };
+        let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .with_context(|| format!("Invalid date value: {date}"))?;
+        Ok(log_entry::parse(date, &content))
+    }
+
+    pub fn search_logs(&self, query: &LogQuery) -> Result<Vec<SearchHit>> {
+        let matcher = query.matcher()?;
+        let limit = query.limit.unwrap_or(100);
+        let ctx = query.context();
         let mut results = Vec::new();
-        if !logs_dir.exists() {
-            return Ok(results);
-        }
-        let mut entries: Vec<_> = std::fs::read_dir(&logs_dir)?
-            .filter_map(|e| e.ok())
-            .collect();
-        // Sort descending (newest first) so limit cuts old results, not new
-        entries.sort_by_key(|e| std::cmp::Reverse(e.file_name()));
-        let query_lower = query.to_lowercase();
-        for entry in entries {
-            let path = entry.path();
-            if path.extension().is_some_and(|ext| ext == "md") {
-                let content = std::fs::read_to_string(&path)?;
-                for line in content.lines() {
-                    if line.to_lowercase().contains(&query_lower) {
-                        let date = path.file_stem().unwrap_or_default().to_string_lossy();
-                        results.push(format!("[{date}] {line}"));
-                        if results.len() >= max_results {
-                            return Ok(results);
-                        }
-                    }
+        // Newest first so limit cuts old results, not new
+        for date in self.list_daily_logs()?.into_iter().rev() {
+            let Ok(d) = NaiveDate::parse_from_str(&date, "%Y-%m-%d") else {
+                continue;
+            };
+            if !query.includes_date(d) {
+                continue;
+            }
+            let entries = self.read_entries(&date)?;
+            for (i, entry) in entries.iter().enumerate() {
+                if !query.matches(entry, &matcher) {
+                    continue;
+                }
+                results.push(SearchHit {
+                    entry: entry.clone(),
+                    before: entries[i.saturating_sub(ctx)..i].to_vec(),
+                    after: entries[i + 1..(i + 1 + ctx).min(entries.len())].to_vec(),
+                });
+                if results.len() >= limit {
+                    return Ok(results);
                 }
             }
         }
         Ok(results)
     }
 
+    /// Dates (YYYY-MM-DD) that have a daily log, loose or archived, oldest
+    /// first.
+    pub fn list_daily_logs(&self) -> Result<Vec<String>> {
+        let mut dates = self.list_ids("logs/daily", Self::validate_date)?;
+        for month in retention::list_archives(&self.base_dir) {
+            dates.extend(
+                self.archived_dates(&month)?
+                    .into_iter()
+                    .filter(|d| Self::validate_date(d).is_ok()),
+            );
+        }
+        dates.sort();
+        dates.dedup();
+        Ok(dates)
+    }
+
+    fn archived_dates(&self, month: &str) -> Result<Vec<String>> {
+        let path = retention::archive_path(&self.base_dir, month);
+        let Ok(modified) = std::fs::metadata(&path).and_then(|m| m.modified()) else {
+            return Ok(Vec::new());
+        };
+        let mut index = self.archive_dates.lock().expect("archive index poisoned");
+        if let Some((at, dates)) = index.get(month)
+            && *at == modified
+        {
+            return Ok(dates.clone());
+        }
+        let dates = retention::archive_dates(&path)?;
+        index.insert(month.to_string(), (modified, dates.clone()));
+        Ok(dates)
+    }
+
+    fn archived_month(&self, month: &str) -> Result<Option<Arc<MonthArchive>>> {
+        let path = retention::archive_path(&self.base_dir, month);
+        let Ok(modified) = std::fs::metadata(&path).and_then(|m| m.modified()) else {
+            return Ok(None);
+        };
+        let mut cache = self.archive_cache.lock().expect("archive cache poisoned");
+        if let Some(c) = cache.as_ref()
+            && c.month == month
+            && c.modified == modified
+        {
+            return Ok(Some(c.files.clone()));
+        }
+        let files = Arc::new(retention::read_archive(&path)?);
+        *cache = Some(CachedArchive {
+            month: month.to_string(),
+            modified,
+            files: files.clone(),
+        });
+        Ok(Some(files))
+    }
+
+    /// Summary ids for `period` ("weekly" or "monthly"), oldest first.
+    pub fn list_summaries(&self, period: &str) -> Result<Vec<String>> {
+        match period {
+            "weekly" => self.list_ids("logs/weekly", Self::validate_week_id),
+            "monthly" => self.list_ids("logs/monthly", Self::validate_month_id),
+            _ => bail!("Unknown period: {period}"),
+        }
+    }
+
+    fn list_ids(&self, dir: &str, validate: fn(&str) -> Result<()>) -> Result<Vec<String>> {
+        let dir = self.base_dir.join(dir);
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut ids: Vec<String> = std::fs::read_dir(&dir)?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.extension().is_some_and(|ext| ext == "md"))
+            .filter_map(|p| Some(p.file_stem()?.to_string_lossy().to_string()))
+            .filter(|id| validate(id).is_ok())
+            .collect();
+        ids.sort();
+        Ok(ids)
+    }
+
+    /// A day's log, read from its monthly archive and/or the loose file.
     pub fn read_daily_log(&self, date: &str) -> Result<Option<String>> {
         Self::validate_date(date)?;
+        let archived = self
+            .archived_month(&date[..7])?
+            .and_then(|files| files.get(date).cloned());
         let path = self.base_dir.join(format!("logs/daily/{date}.md"));
-        if path.exists() {
-            Ok(Some(std::fs::read_to_string(path)?))
-        } else {
-            Ok(None)
-        }
+        let raw = match (archived, path.exists()) {
+            (None, false) => return Ok(None),
+            (None, true) => return Ok(Some(self.read_text(&path)?)),
+            (Some(a), false) => a,
+            (Some(a), true) => retention::merge(&a, &std::fs::read(&path)?),
+        };
+        crypto::decode(self.cipher.as_deref(), &raw)
+            .map(Some)
+            .with_context(|| format!("Failed to decode archived log {date}"))
     }
 
     // --- Summaries ---
@@ -173,7 +407,7 @@ impl MemoryManager {
         Self::validate_week_id(week_id)?;
         let path = self.base_dir.join(format!("logs/weekly/{week_id}.md"));
         if path.exists() {
-            Ok(Some(std::fs::read_to_string(path)?))
+            Ok(Some(self.read_text(&path)?))
         } else {
             Ok(None)
         }
@@ -183,7 +417,7 @@ impl MemoryManager {
         Self::validate_month_id(month_id)?;
         let path = self.base_dir.join(format!("logs/monthly/{month_id}.md"));
         if path.exists() {
-            Ok(Some(std::fs::read_to_string(path)?))
+            Ok(Some(self.read_text(&path)?))
         } else {
             Ok(None)
         }
@@ -195,7 +429,7 @@ impl MemoryManager {
         if let Some(p) = path.parent() {
             std::fs::create_dir_all(p)?;
         }
-        Ok(std::fs::write(path, content)?)
+        self.write_text(&path, content)
     }
 
     pub fn write_monthly_summary(&self, month_id: &str, content: &str) -> Result<()> {
@@ -204,10 +438,41 @@ impl MemoryManager {
         if let Some(p) = path.parent() {
             std::fs::create_dir_all(p)?;
         }
-        Ok(std::fs::write(path, content)?)
+        self.write_text(&path, content)
     }
 }
 
+/// Unified line diff from `old` to `new`.
+pub fn diff(old: &str, new: &str) -> String {
+    similar::TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(3)
+        .to_string()
+}
+
+/// Claim `base_dir` for a server; held until the returned file is dropped.
+/// CLI commands that would race the server's in-memory state check it with
+/// `daemon_running`.
+pub fn lock_for_daemon(base_dir: &Path) -> Result<std::fs::File> {
+    std::fs::create_dir_all(base_dir)?;
+    let path = base_dir.join(DAEMON_LOCK);
+    let file = std::fs::File::create(&path)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    if file.try_lock().is_err() {
+        bail!(
+            "Another 1koro server is already running on {}",
+            base_dir.display()
+        );
+    }
+    Ok(file)
+}
+
+/// Whether a server holds the lock on `base_dir`.
+pub fn daemon_running(base_dir: &Path) -> bool {
+    std::fs::File::open(base_dir.join(DAEMON_LOCK))
+        .is_ok_and(|f| matches!(f.try_lock(), Err(std::fs::TryLockError::WouldBlock)))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -270,4 +535,167 @@ mod tests {
         assert!(MemoryManager::validate_month_id("2026-00").is_err());
         assert!(MemoryManager::validate_month_id("2026-13").is_err());
     }
+
+    #[test]
+    fn test_core_history() {
+        let base_dir = std::env::temp_dir().join(format!("1koro-mem-hist-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&base_dir);
+        std::fs::create_dir_all(base_dir.join("core")).unwrap();
+        let mem = MemoryManager {
+            base_dir: base_dir.clone(),
+            cipher: None,
+            archive_cache: Mutex::new(None),
+            archive_dates: Mutex::new(HashMap::new()),
+        };
+        mem.write_core("user.md", "# User\n\nLikes tea\n").unwrap();
+        assert!(mem.core_history("user.md").unwrap().is_empty());
+        mem.write_core("user.md", "# User\n\nLikes coffee\n")
+            .unwrap();
+        mem.write_core("user.md", "# User\n\nLikes coffee\n")
+            .unwrap();
+        let versions = mem.core_history("user.md").unwrap();
+        assert_eq!(versions.len(), 1, "unchanged writes are not versioned");
+        let old = mem.read_core_version("user.md", &versions[0]).unwrap();
+        assert!(old.contains("tea"));
+        let d = diff(&old, &mem.read_core("user.md").unwrap());
+        assert!(d.contains("-Likes tea") && d.contains("+Likes coffee"));
+
+        assert!(mem.write_core("identity.md", "# Me").is_err());
+        mem.write_core_as_admin("identity.md", "# Me").unwrap();
+        assert!(mem.read_core_version("user.md", "../user").is_err());
+        let _ = std::fs::remove_dir_all(base_dir);
+    }
+
+    #[test]
+    fn test_encrypted_logs_are_searchable() {
+        let base_dir = std::env::temp_dir().join(format!("1koro-mem-enc-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&base_dir);
+        let mem = MemoryManager {
+            base_dir: base_dir.clone(),
+            cipher: Some(Arc::new(
+                Cipher::new(b"test key for memory", b"0123456789abcdef").unwrap(),
+            )),
+            archive_cache: Mutex::new(None),
+            archive_dates: Mutex::new(HashMap::new()),
+        };
+        let entry = LogEntry::message(
+            log_entry::Role::User,
+            "cli",
+            "masaki",
+            "masaki",
+            "deploy the\nstaging server",
+        );
+        mem.append_log(&entry).unwrap();
+
+        let today = entry.date.format("%Y-%m-%d").to_string();
+        let raw = std::fs::read_to_string(base_dir.join(format!("logs/daily/{today}.md"))).unwrap();
+        assert!(!raw.contains("staging"));
+
+        let query = LogQuery {
+            query: "STAGING".into(),
+            ..Default::default()
+        };
+        let hits = log_entry::format_hits(&mem.search_logs(&query).unwrap());
+        assert!(hits.contains("user masaki: deploy the\n  staging server"));
+        assert_eq!(mem.read_entries(&today).unwrap(), vec![entry]);
+        let _ = std::fs::remove_dir_all(base_dir);
+    }
+
+    #[test]
+    fn test_search_logs_with_context() {
+        let base_dir = std::env::temp_dir().join(format!("1koro-mem-ctx-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&base_dir);
+        let mem = MemoryManager {
+            base_dir: base_dir.clone(),
+            cipher: None,
+            archive_cache: Mutex::new(None),
+            archive_dates: Mutex::new(HashMap::new()),
+        };
+        for (role, text) in [
+            (log_entry::Role::User, "morning"),
+            (log_entry::Role::User, "when is the deploy?"),
+            (log_entry::Role::Agent, "deploy is at 3pm"),
+            (log_entry::Role::User, "thanks"),
+        ] {
+            mem.append_log(&LogEntry::message(role, "slack#ops", "U1", "masaki", text))
+                .unwrap();
+        }
+
+        let query = LogQuery {
+            query: "deploy".into(),
+            channel: Some("slack".into()),
+            role: Some(log_entry::Role::Agent),
+            context: 1,
+            ..Default::default()
+        };
+        let hits = mem.search_logs(&query).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].entry.text, "deploy is at 3pm");
+        assert_eq!(hits[0].before[0].text, "when is the deploy?");
+        assert_eq!(hits[0].after[0].text, "thanks");
+
+        let query = LogQuery {
+            channel: Some("cli".into()),
+            ..Default::default()
+        };
+        assert!(mem.search_logs(&query).unwrap().is_empty());
+        let _ = std::fs::remove_dir_all(base_dir);
+    }
+
+    #[test]
+    fn test_archived_logs_are_read_transparently() {
+        let base_dir = std::env::temp_dir().join(format!("1koro-mem-arch-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&base_dir);
+        let mem = MemoryManager {
+            base_dir: base_dir.clone(),
+            cipher: Some(Arc::new(
+                Cipher::new(b"test key for memory", b"0123456789abcdef").unwrap(),
+            )),
+            archive_cache: Mutex::new(None),
+            archive_dates: Mutex::new(HashMap::new()),
+        };
+        let mut entry = LogEntry::note("old staging notes");
+        entry.date = NaiveDate::from_ymd_opt(2025, 3, 4).unwrap();
+        mem.append_log(&entry).unwrap();
+        let mut files = MonthArchive::new();
+        let loose = base_dir.join("logs/daily/2025-03-04.md");
+        files.insert("2025-03-04".into(), std::fs::read(&loose).unwrap());
+        retention::write_archive(&retention::archive_path(&base_dir, "2025-03"), &files).unwrap();
+        std::fs::remove_file(&loose).unwrap();
+
+        // A later append to the archived day is read together with it
+        let mut late = LogEntry::note("late import");
+        late.date = entry.date;
+        mem.append_log(&late).unwrap();
+
+        assert_eq!(mem.list_daily_logs().unwrap(), vec!["2025-03-04"]);
+        assert!(
+            mem.archive_dates.lock().unwrap().contains_key("2025-03"),
+            "archived dates are indexed"
+        );
+        assert_eq!(mem.read_entries("2025-03-04").unwrap(), vec![entry, late]);
+        let query = LogQuery {
+            query: "staging".into(),
+            ..Default::default()
+        };
+        assert_eq!(mem.search_logs(&query).unwrap().len(), 1);
+        let _ = std::fs::remove_dir_all(base_dir);
+    }
+
+    #[test]
+    fn test_daemon_lock() {
+        let base_dir = std::env::temp_dir().join(format!("1koro-mem-lock-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&base_dir);
+        std::fs::create_dir_all(&base_dir).unwrap();
+        assert!(!daemon_running(&base_dir));
+        let lock = lock_for_daemon(&base_dir).unwrap();
+        assert!(daemon_running(&base_dir));
+        assert!(
+            lock_for_daemon(&base_dir).is_err(),
+            "one server per directory"
+        );
+        drop(lock);
+        assert!(!daemon_running(&base_dir));
+        let _ = std::fs::remove_dir_all(base_dir);
+    }
 }
@@ -0,0 +1,358 @@
+use std::sync::Arc;
+
+use axum::extract::{Path, Query};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Extension, Json, Router};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::api::AppState;
+use crate::auth::{Principal, Scope};
+use crate::log_entry::{LogEntry, LogQuery};
+use crate::memory;
+use crate::tenant::Tenant;
+
+/// Core files shown in the browser, with whether the LLM may write them.
+const CORE_FILES: &[(&str, bool)] = &[
+    ("identity.md", false),
+    ("user.md", true),
+    ("state.md", true),
+];
+
+/// JSON endpoints behind the web UI's memory browser. Reading needs
+/// `sessions:read`; editing core files needs `memory:write`, and
+/// `identity.md` needs `admin`. Memory is not per-channel, so keys bound to
+/// a user or channel are refused. Everything is the caller's tenant's.
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/memory/core", get(list_core))
+        .route("/memory/core/:file", get(read_core).put(write_core))
+        .route("/memory/core/:file/history/:version", get(read_version))
+        .route("/memory/logs", get(list_logs))
+        .route("/memory/logs/:date", get(read_log))
+        .route("/memory/summaries/:period", get(list_summaries))
+        .route("/memory/summaries/:period/:id", get(read_summary))
+        .route("/memory/search", get(search))
+}
+
+fn error(status: StatusCode, message: impl std::fmt::Display) -> Response {
+    (status, Json(json!({ "error": message.to_string() }))).into_response()
+}
+
+fn not_found() -> Response {
+    error(StatusCode::NOT_FOUND, "Not found")
+}
+
+/// Runs `f` on the blocking pool for a principal not bound to a user or
+/// channel, turning errors into 400s. Reading logs can mean decompressing
+/// monthly archives, which must not hold up the runtime.
+async fn respond(
+    principal: &Principal,
+    f: impl FnOnce() -> anyhow::Result<Response> + Send + 'static,
+) -> Response {
+    if principal.user.is_some() || principal.channel.is_some() {
+        return error(
+            StatusCode::FORBIDDEN,
+            "Keys bound to a user or channel cannot browse memory",
+        );
+    }
+    match tokio::task::spawn_blocking(f).await {
+        Ok(result) => result.unwrap_or_else(|e| error(StatusCode::BAD_REQUEST, e)),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, e),
+    }
+}
+
+async fn list_core(Extension(principal): Extension<Principal>) -> Response {
+    respond(&principal, move || {
+        let files: Vec<_> = CORE_FILES
+            .iter()
+            .map(|(file, llm_writable)| json!({ "file": file, "llm_writable": llm_writable }))
+            .collect();
+        Ok(Json(json!({ "files": files })).into_response())
+    })
+    .await
+}
+
+async fn read_core(
+    Extension(principal): Extension<Principal>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    Path(file): Path<String>,
+) -> Response {
+    respond(&principal, move || {
+        let content = tenant.memory.read_core(&file).unwrap_or_default();
+        let history = tenant.memory.core_history(&file)?;
+        Ok(Json(json!({ "file": file, "content": content, "history": history })).into_response())
+    })
+    .await
+}
+
+#[derive(Deserialize)]
+struct CoreUpdate {
+    content: String,
+}
+
+async fn write_core(
+    Extension(principal): Extension<Principal>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    Path(file): Path<String>,
+    Json(update): Json<CoreUpdate>,
+) -> Response {
+    let needed = if file == "identity.md" {
+        Scope::Admin
+    } else {
+        Scope::MemoryWrite
+    };
+    if !principal.allows(needed) {
+        return error(
+            StatusCode::FORBIDDEN,
+            format!("Editing {file} requires the {needed} scope"),
+        );
+    }
+    let name = principal.name.clone();
+    respond(&principal, move || {
+        tenant.memory.write_core_as_admin(&file, &update.content)?;
+        tracing::info!("{file} edited by '{name}'");
+        let history = tenant.memory.core_history(&file)?;
+        Ok(Json(json!({ "file": file, "history": history })).into_response())
+    })
+    .await
+}
+
+/// A saved version and the diff from it to the next one (or the current
+/// content), i.e. the change made when it was replaced.
+async fn read_version(
+    Extension(principal): Extension<Principal>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    Path((file, version)): Path<(String, String)>,
+) -> Response {
+    respond(&principal, move || {
+        let history = tenant.memory.core_history(&file)?;
+        let Some(i) = history.iter().position(|v| *v == version) else {
+            return Ok(not_found());
+        };
+        let content = tenant.memory.read_core_version(&file, &version)?;
+        let next = match i {
+            0 => tenant.memory.read_core(&file).unwrap_or_default(),
+            _ => tenant.memory.read_core_version(&file, &history[i - 1])?,
+        };
+        Ok(Json(json!({
+            "file": file,
+            "version": version,
+            "content": content,
+            "diff": memory::diff(&content, &next),
+        }))
+        .into_response())
+    })
+    .await
+}
+
+async fn list_logs(
+    Extension(principal): Extension<Principal>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+) -> Response {
+    respond(&principal, move || {
+        let dates = tenant.memory.list_daily_logs()?;
+        Ok(Json(json!({ "dates": dates })).into_response())
+    })
+    .await
+}
+
+async fn read_log(
+    Extension(principal): Extension<Principal>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    Path(date): Path<String>,
+) -> Response {
+    respond(&principal, move || {
+        Ok(match tenant.memory.read_daily_log(&date)? {
+            Some(content) => Json(json!({ "date": date, "content": content })).into_response(),
+            None => not_found(),
+        })
+    })
+    .await
+}
+
+async fn list_summaries(
+    Extension(principal): Extension<Principal>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    Path(period): Path<String>,
+) -> Response {
+    respond(&principal, move || {
+        let ids = tenant.memory.list_summaries(&period)?;
+        Ok(Json(json!({ "period": period, "ids": ids })).into_response())
+    })
+    .await
+}
+
+async fn read_summary(
+    Extension(principal): Extension<Principal>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    Path((period, id)): Path<(String, String)>,
+) -> Response {
+    respond(&principal, move || {
+        let content = match period.as_str() {
+            "weekly" => tenant.memory.read_weekly_summary(&id)?,
+            "monthly" => tenant.memory.read_monthly_summary(&id)?,
+            _ => anyhow::bail!("Unknown period: {period}"),
+        };
+        Ok(match content {
+            Some(content) => {
+                Json(json!({ "period": period, "id": id, "content": content })).into_response()
+            }
+            None => not_found(),
+        })
+    })
+    .await
+}
+
+async fn search(
+    Extension(principal): Extension<Principal>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    Query(query): Query<LogQuery>,
+) -> Response {
+    respond(&principal, move || {
+        let line = |e: &LogEntry| json!({ "date": e.date, "text": e.to_markdown() });
+        let hits: Vec<_> = tenant
+            .memory
+            .search_logs(&query)?
+            .iter()
+            .map(|h| {
+                json!({
+                    "entry": line(&h.entry),
+                    "before": h.before.iter().map(line).collect::<Vec<_>>(),
+                    "after": h.after.iter().map(line).collect::<Vec<_>>(),
+                })
+            })
+            .collect();
+        Ok(Json(json!({ "hits": hits })).into_response())
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Method;
+    use serde_json::Value;
+
+    use crate::api::tests::{call, state};
+
+    const KEYS: &str = r#"
+        [[api.keys]]
+        name = "writer"
+        key = "writer-key"
+        scopes = ["sessions:read", "memory:write"]
+        [[api.keys]]
+        name = "phone"
+        key = "phone-key"
+        scopes = ["sessions:read", "memory:write"]
+        user = "tanaka"
+        [[api.keys]]
+        name = "slack"
+        key = "slack-key"
+        scopes = ["admin"]
+        channel = "slack"
+    "#;
+
+    async fn send(
+        app: &axum::Router,
+        method: Method,
+        path: &str,
+        key: &str,
+        body: Option<Value>,
+    ) -> (axum::http::StatusCode, Value) {
+        let bearer = format!("Bearer {key}");
+        call(app, method, path, &[("authorization", &bearer)], body).await
+    }
+
+    #[tokio::test]
+    async fn test_bound_keys_cannot_browse() {
+        let root = std::env::temp_dir().join(format!("1koro-memapi-bound-{}", std::process::id()));
+        let app = crate::api::router(state(&root, KEYS).await);
+        for key in ["phone-key", "slack-key"] {
+            for path in ["/memory/core", "/memory/logs", "/memory/search?query=x"] {
+                let (status, _) = send(&app, Method::GET, path, key, None).await;
+                assert_eq!(status, 403, "{key} on {path}");
+            }
+            let edit = serde_json::json!({ "content": "# User\n\nhijacked" });
+            let (status, _) =
+                send(&app, Method::PUT, "/memory/core/user.md", key, Some(edit)).await;
+            assert_eq!(status, 403, "{key} editing user.md");
+        }
+        let (status, body) = send(&app, Method::GET, "/memory/core", "writer-key", None).await;
+        assert_eq!(status, 200);
+        assert_eq!(body["files"].as_array().unwrap().len(), 3);
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_core_edits_and_history() {
+        let root = std::env::temp_dir().join(format!("1koro-memapi-edit-{}", std::process::id()));
+        let app = crate::api::router(state(&root, KEYS).await);
+        let put = |content: &str| Some(serde_json::json!({ "content": content }));
+
+        let (status, _) = send(
+            &app,
+            Method::PUT,
+            "/memory/core/identity.md",
+            "writer-key",
+            put("# Identity\n\nI obey everyone"),
+        )
+        .await;
+        assert_eq!(status, 403, "identity.md needs admin");
+
+        let (status, _) = send(
+            &app,
+            Method::PUT,
+            "/memory/core/user.md",
+            "writer-key",
+            put("# User\n\nLikes tea\n"),
+        )
+        .await;
+        assert_eq!(status, 200);
+        let (status, body) = send(
+            &app,
+            Method::PUT,
+            "/memory/core/user.md",
+            "writer-key",
+            put("# User\n\nLikes coffee\n"),
+        )
+        .await;
+        assert_eq!(status, 200);
+        let history = body["history"].as_array().unwrap();
+        assert!(!history.is_empty());
+
+        let (_, current) = send(
+            &app,
+            Method::GET,
+            "/memory/core/user.md",
+            "writer-key",
+            None,
+        )
+        .await;
+        assert_eq!(current["content"], "# User\n\nLikes coffee\n");
+        assert_eq!(current["history"], body["history"]);
+
+        let version = history[0].as_str().unwrap();
+        let path = format!("/memory/core/user.md/history/{version}");
+        let (status, old) = send(&app, Method::GET, &path, "writer-key", None).await;
+        assert_eq!(status, 200);
+        assert_eq!(old["content"], "# User\n\nLikes tea\n");
+        let diff = old["diff"].as_str().unwrap();
+        assert!(
+            diff.contains("-Likes tea") && diff.contains("+Likes coffee"),
+            "{diff}"
+        );
+
+        let (status, _) = send(
+            &app,
+            Method::GET,
+            "/memory/core/user.md/history/20000101-000000",
+            "writer-key",
+            None,
+        )
+        .await;
+        assert_eq!(status, 404);
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
@@ -0,0 +1,291 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Extension, Json, Router};
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::agent::AgentEvent;
+use crate::api::AppState;
+use crate::attachments::Attachment;
+use crate::auth::Principal;
+use crate::llm::{Content, ContentPart, Message};
+use crate::tenant::Tenant;
+
+/// Channel used for sessions opened through this API unless the client
+/// sends `X-1koro-Channel`.
+const CHANNEL: &str = "openai";
+
+#[derive(Deserialize)]
+pub struct ChatCompletionRequest {
+    #[serde(default)]
+    pub model: Option<String>,
+    pub messages: Vec<Message>,
+    #[serde(default)]
+    pub stream: bool,
+    /// OpenAI's end-user id; used as the 1koro user of the session.
+    #[serde(default)]
+    pub user: Option<String>,
+}
+
+/// `POST /v1/chat/completions` and `GET /v1/models`. Clients resend the
+/// whole conversation each turn, but the agent keeps its own session, so
+/// only the last user message is forwarded.
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/v1/chat/completions", post(chat_completions))
+        .route("/v1/models", get(models))
+}
+
+fn error(status: StatusCode, message: impl Into<String>) -> Response {
+    let kind = if status.is_client_error() {
+        "invalid_request_error"
+    } else {
+        "server_error"
+    };
+    (
+        status,
+        Json(json!({"error": {"message": message.into(), "type": kind}})),
+    )
+        .into_response()
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty())
+}
+
+/// Text and inline (`data:`) images of the last user message.
+fn last_user_turn(messages: &[Message]) -> Option<(String, Vec<Attachment>)> {
+    let msg = messages.iter().rev().find(|m| m.role == "user")?;
+    let content = msg.content.as_ref()?;
+    let mut attachments = Vec::new();
+    if let Content::Parts(parts) = content {
+        for part in parts {
+            if let ContentPart::ImageUrl { image_url } = part
+                && let Some(meta) = image_url.url.strip_prefix("data:")
+                && let Some((mime, _)) = meta.split_once(";base64,")
+            {
+                let ext = mime.rsplit('/').next().unwrap_or("bin");
+                let name = format!("image-{}.{ext}", attachments.len() + 1);
+                if let Ok(a) = Attachment::from_base64(&name, Some(mime), &image_url.url) {
+                    attachments.push(a);
+                }
+            }
+        }
+    }
+    Some((content.text(), attachments))
+}
+
+fn completion_id() -> String {
+    format!(
+        "chatcmpl-{:x}",
+        Utc::now().timestamp_nanos_opt().unwrap_or_default()
+    )
+}
+
+async fn chat_completions(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    headers: HeaderMap,
+    Json(req): Json<ChatCompletionRequest>,
+) -> Response {
+    let Some((text, attachments)) = last_user_turn(&req.messages) else {
+        return error(StatusCode::BAD_REQUEST, "No user message in 'messages'");
+    };
+    if text.trim().is_empty() && attachments.is_empty() {
+        return error(StatusCode::BAD_REQUEST, "The last user message is empty");
+    }
+    let mut channel = header(&headers, "x-1koro-channel")
+        .unwrap_or(CHANNEL)
+        .to_string();
+    let mut user = req
+        .user
+        .as_deref()
+        .or_else(|| header(&headers, "x-1koro-user"))
+        .unwrap_or(&tenant.default_user)
+        .to_string();
+    principal.bind(&mut user, &mut channel);
+    let admitted = match state.limits.check(
+        &principal.name,
+        &tenant.scoped(&format!("{channel}:{user}")),
+    ) {
+        Ok(()) => state.limits.acquire().await,
+        Err(e) => Err(e),
+    };
+    let permit = match admitted {
+        Ok(p) => p,
+        Err(e) => {
+            let retry_after = HeaderValue::from(e.retry_after_secs());
+            let mut resp = error(StatusCode::TOO_MANY_REQUESTS, e.message);
+            resp.headers_mut().insert("retry-after", retry_after);
+            return resp;
+        }
+    };
+    let message_id = header(&headers, "idempotency-key").map(str::to_string);
+    let model = req.model.unwrap_or_else(|| state.name.clone());
+    let id = completion_id();
+    let created = Utc::now().timestamp();
+
+    if !req.stream {
+        let _permit = permit;
+        return match tenant
+            .agent
+            .handle_message(
+                &text,
+                &channel,
+                &user,
+                attachments,
+                message_id.as_deref(),
+                None,
+            )
+            .await
+        {
+            Ok(resp) => Json(json!({
+                "id": id,
+                "object": "chat.completion",
+                "created": created,
+                "model": model,
+                "choices": [{
+                    "index": 0,
+                    "message": {"role": "assistant", "content": resp.text.unwrap_or_default()},
+                    "finish_reason": "stop",
+                }],
+                "usage": {"prompt_tokens": 0, "completion_tokens": 0, "total_tokens": 0},
+            }))
+            .into_response(),
+            Err(e) => {
+                tracing::error!("Agent error: {e}");
+                error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+            }
+        };
+    }
+
+    let (tx, rx) = tokio::sync::mpsc::channel::<Result<Event, Infallible>>(16);
+    tokio::spawn(async move {
+        let _permit = permit;
+        let chunk = |delta: Value, finish: Option<&str>| {
+            Event::default().data(
+                json!({
+                    "id": id,
+                    "object": "chat.completion.chunk",
+                    "created": created,
+                    "model": model,
+                    "choices": [{"index": 0, "delta": delta, "finish_reason": finish}],
+                })
+                .to_string(),
+            )
+        };
+        let _ = tx.send(Ok(chunk(json!({"role": "assistant"}), None))).await;
+        let (events, mut deltas) = tokio::sync::mpsc::unbounded_channel();
+        let (agent, session) = (tenant.agent.clone(), format!("{channel}:{user}"));
+        // The run owns the only sender, so `forward` ends when it does.
+        let run = async move {
+            tenant
+                .agent
+                .handle_message(
+                    &text,
+                    &channel,
+                    &user,
+                    attachments,
+                    message_id.as_deref(),
+                    Some(&events),
+                )
+                .await
+        };
+        let forward = async {
+            let (mut streamed, mut hung_up) = (false, false);
+            loop {
+                tokio::select! {
+                    event = deltas.recv() => match event {
+                        Some(AgentEvent::Delta { text }) => {
+                            streamed = true;
+                            let _ = tx.send(Ok(chunk(json!({"content": text}), None))).await;
+                        }
+                        Some(_) => {}
+                        None => break,
+                    },
+                    // The client hung up ("stop generating"): stop the run.
+                    () = tx.closed(), if !hung_up => {
+                        hung_up = true;
+                        agent.cancel(&session);
+                    }
+                }
+            }
+            streamed
+        };
+        let (result, streamed) = tokio::join!(run, forward);
+        let rest = match result {
+            // A stored reply (idempotent retry) arrives without deltas.
+            Ok(resp) if !streamed => resp.text.unwrap_or_default(),
+            Ok(_) => String::new(),
+            Err(e) => {
+                tracing::error!("Agent error: {e}");
+                format!("Error: {e}")
+            }
+        };
+        if !rest.is_empty() {
+            let _ = tx.send(Ok(chunk(json!({"content": rest}), None))).await;
+        }
+        let _ = tx.send(Ok(chunk(json!({}), Some("stop")))).await;
+        let _ = tx.send(Ok(Event::default().data("[DONE]"))).await;
+    });
+    Sse::new(ReceiverStream::new(rx))
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+async fn models(State(state): State<AppState>) -> impl IntoResponse {
+    Json(json!({
+        "object": "list",
+        "data": [{
+            "id": state.name,
+            "object": "model",
+            "created": 0,
+            "owned_by": "1koro",
+        }],
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_last_user_turn() {
+        let req: ChatCompletionRequest = serde_json::from_value(json!({
+            "model": "1koro",
+            "messages": [
+                {"role": "system", "content": "be nice"},
+                {"role": "user", "content": "old question"},
+                {"role": "assistant", "content": "old answer"},
+                {"role": "user", "content": [
+                    {"type": "text", "text": "what is this?"},
+                    {"type": "image_url", "image_url": {"url": "data:image/png;base64,iVBORw=="}},
+                    {"type": "image_url", "image_url": {"url": "https://example.com/a.png"}}
+                ]}
+            ],
+            "stream": true
+        }))
+        .unwrap();
+        assert!(req.stream);
+        let (text, attachments) = last_user_turn(&req.messages).unwrap();
+        assert_eq!(text, "what is this?");
+        assert_eq!(attachments.len(), 1);
+        assert_eq!(attachments[0].filename, "image-1.png");
+        assert_eq!(attachments[0].mime_type, "image/png");
+
+        let none = vec![Message::system("x")];
+        assert!(last_user_turn(&none).is_none());
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use axum::Json;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use serde_json::{Value, json};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::config::ApiConfig;
+
+/// Idle buckets are dropped once a map grows past this.
+const MAX_BUCKETS: usize = 1024;
+/// Suggested wait when every agent slot is busy and the queue is full.
+const BUSY_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+struct TokenBucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn full(capacity: f64, now: Instant) -> Self {
+        Self {
+            tokens: capacity,
+            last: now,
+        }
+    }
+
+    /// Take one token, or say how long until one is available.
+    fn take(&mut self, per_minute: u32, now: Instant) -> Result<(), Duration> {
+        let capacity = f64::from(per_minute);
+        let per_sec = capacity / 60.0;
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * per_sec).min(capacity);
+        self.last = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / per_sec))
+        }
+    }
+
+    fn is_full(&self, per_minute: u32, now: Instant) -> bool {
+        let refilled =
+            now.saturating_duration_since(self.last).as_secs_f64() * f64::from(per_minute) / 60.0;
+        self.tokens + refilled >= f64::from(per_minute)
+    }
+}
+
+/// A request turned away with 429.
+#[derive(Debug)]
+pub struct Rejection {
+    pub message: String,
+    pub retry_after: Duration,
+}
+
+impl Rejection {
+    /// Whole seconds for the `Retry-After` header, rounded up.
+    pub fn retry_after_secs(&self) -> u64 {
+        self.retry_after.as_secs() + u64::from(self.retry_after.subsec_nanos() > 0)
+    }
+}
+
+impl IntoResponse for Rejection {
+    fn into_response(self) -> Response {
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            [("retry-after", self.retry_after_secs().to_string())],
+            Json(json!({ "error": self.message })),
+        )
+            .into_response()
+    }
+}
+
+/// Per-key and per-session token buckets plus a global cap on concurrent
+/// agent runs, with a bounded queue in front of it.
+pub struct Limiter {
+    key_rate: u32,
+    session_rate: u32,
+    max_concurrent: usize,
+    max_queued: usize,
+    keys: Mutex<HashMap<String, TokenBucket>>,
+    sessions: Mutex<HashMap<String, TokenBucket>>,
+    slots: Arc<Semaphore>,
+    queued: AtomicUsize,
+    rate_limited: AtomicU64,
+    busy: AtomicU64,
+}
+
+impl Limiter {
+    pub fn new(api: &ApiConfig) -> Self {
+        let max_concurrent = api.max_concurrent.max(1);
+        Self {
+            key_rate: api.key_rate_per_minute,
+            session_rate: api.session_rate_per_minute,
+            max_concurrent,
+            max_queued: api.max_queued,
+            keys: Mutex::new(HashMap::new()),
+            sessions: Mutex::new(HashMap::new()),
+            slots: Arc::new(Semaphore::new(max_concurrent)),
+            queued: AtomicUsize::new(0),
+            rate_limited: AtomicU64::new(0),
+            busy: AtomicU64::new(0),
+        }
+    }
+
+    /// Charge one message to the API key and to the session.
+    pub fn check(&self, key: &str, session: &str) -> Result<(), Rejection> {
+        let now = Instant::now();
+        take(&self.keys, key, self.key_rate, now)
+            .map_err(|d| ("API key", d))
+            .and_then(|_| {
+                take(&self.sessions, session, self.session_rate, now).map_err(|d| ("session", d))
+            })
+            .map_err(|(what, retry_after)| {
+                self.rate_limited.fetch_add(1, Ordering::Relaxed);
+                tracing::warn!("Rate limited {what} (key '{key}', session {session})");
+                Rejection {
+                    message: format!("Rate limit exceeded for this {what}"),
+                    retry_after,
+                }
+            })
+    }
+
+    /// Wait for an agent slot, or fail at once if the queue is full.
+    pub async fn acquire(&self) -> Result<OwnedSemaphorePermit, Rejection> {
+        if let Ok(permit) = self.slots.clone().try_acquire_owned() {
+            return Ok(permit);
+        }
+        if self.queued.fetch_add(1, Ordering::SeqCst) >= self.max_queued {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            self.busy.fetch_add(1, Ordering::Relaxed);
+            return Err(Rejection {
+                message: "Too many messages in progress".into(),
+                retry_after: BUSY_RETRY_AFTER,
+            });
+        }
+        Ok(self.wait().await)
+    }
+
+    /// Wait for an agent slot however long the queue is (background jobs).
+    pub async fn acquire_queued(&self) -> OwnedSemaphorePermit {
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        self.wait().await
+    }
+
+    async fn wait(&self) -> OwnedSemaphorePermit {
+        let permit = self
+            .slots
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("agent semaphore is never closed");
+        self.queued.fetch_sub(1, Ordering::SeqCst);
+        permit
+    }
+
+    /// Counters for `/health`.
+    pub fn stats(&self) -> Value {
+        json!({
+            "running": self.max_concurrent - self.slots.available_permits(),
+            "queued": self.queued.load(Ordering::SeqCst),
+            "max_concurrent": self.max_concurrent,
+            "rejected": {
+                "rate_limited": self.rate_limited.load(Ordering::Relaxed),
+                "busy": self.busy.load(Ordering::Relaxed),
+            },
+        })
+    }
+}
+
+fn take(
+    buckets: &Mutex<HashMap<String, TokenBucket>>,
+    id: &str,
+    per_minute: u32,
+    now: Instant,
+) -> Result<(), Duration> {
+    if per_minute == 0 {
+        return Ok(());
+    }
+    let mut buckets = buckets.lock().expect("rate limiter lock poisoned");
+    if buckets.len() > MAX_BUCKETS {
+        buckets.retain(|_, b| !b.is_full(per_minute, now));
+    }
+    buckets
+        .entry(id.to_string())
+        .or_insert_with(|| TokenBucket::full(f64::from(per_minute), now))
+        .take(per_minute, now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(key_rate: u32, session_rate: u32, max_queued: usize) -> Limiter {
+        Limiter::new(&ApiConfig {
+            key_rate_per_minute: key_rate,
+            session_rate_per_minute: session_rate,
+            max_concurrent: 1,
+            max_queued,
+            ..ApiConfig::default()
+        })
+    }
+
+    #[test]
+    fn test_token_bucket_refills() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::full(2.0, start);
+        assert!(bucket.take(2, start).is_ok());
+        assert!(bucket.take(2, start).is_ok());
+        let wait = bucket.take(2, start).unwrap_err();
+        assert!((wait.as_secs_f64() - 30.0).abs() < 0.01);
+        assert!(bucket.take(2, start + Duration::from_secs(31)).is_ok());
+    }
+
+    #[test]
+    fn test_key_and_session_limits() {
+        let limits = limiter(3, 2, 0);
+        assert!(limits.check("n8n", "slack:a").is_ok());
+        assert!(limits.check("n8n", "slack:a").is_ok());
+        let err = limits.check("n8n", "slack:a").unwrap_err();
+        assert!(err.message.contains("session"));
+        assert!(err.retry_after_secs() >= 29);
+        // The rejected call still used the key's third token.
+        assert!(
+            limits
+                .check("n8n", "slack:b")
+                .unwrap_err()
+                .message
+                .contains("API key")
+        );
+        assert!(limits.check("other", "slack:b").is_ok());
+        assert!(limiter(0, 0, 0).check("k", "s").is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_queue_is_bounded() {
+        let limits = Arc::new(limiter(0, 0, 1));
+        let first = limits.acquire().await.unwrap();
+        let waiter = tokio::spawn({
+            let limits = limits.clone();
+            async move { limits.acquire().await.is_ok() }
+        });
+        while limits.queued.load(Ordering::SeqCst) == 0 {
+            tokio::task::yield_now().await;
+        }
+        assert!(limits.acquire().await.is_err());
+        assert_eq!(limits.stats()["rejected"]["busy"], 1);
+        drop(first);
+        assert!(waiter.await.unwrap());
+    }
+}
@@ -0,0 +1,300 @@
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::{Local, Months, NaiveDate};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+
+use crate::config::RetentionConfig;
+use crate::session::SessionStore;
+
+/// Monthly archives live here as `YYYY-MM.tar.gz`, holding the raw
+/// `YYYY-MM-DD.md` files (still line-encrypted if encryption is on).
+pub const ARCHIVE_DIR: &str = "logs/archive";
+
+/// Raw daily log bytes keyed by date (YYYY-MM-DD).
+pub type MonthArchive = BTreeMap<String, Vec<u8>>;
+
+pub fn archive_path(base_dir: &Path, month: &str) -> PathBuf {
+    base_dir.join(ARCHIVE_DIR).join(format!("{month}.tar.gz"))
+}
+
+/// Months (YYYY-MM) that have an archive, oldest first.
+pub fn list_archives(base_dir: &Path) -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(base_dir.join(ARCHIVE_DIR)) else {
+        return Vec::new();
+    };
+    let mut months: Vec<String> = entries
+        .flatten()
+        .filter_map(|e| {
+            let name = e.file_name().to_string_lossy().to_string();
+            let month = name.strip_suffix(".tar.gz")?;
+            NaiveDate::parse_from_str(&format!("{month}-01"), "%Y-%m-%d").ok()?;
+            Some(month.to_string())
+        })
+        .collect();
+    months.sort();
+    months
+}
+
+pub fn read_archive(path: &Path) -> Result<MonthArchive> {
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open log archive: {}", path.display()))?;
+    let mut tar = tar::Archive::new(GzDecoder::new(file));
+    let mut out = MonthArchive::new();
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().to_string();
+        let Some(date) = name.strip_suffix(".md") else {
+            continue;
+        };
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        out.insert(date.to_string(), data);
+    }
+    Ok(out)
+}
+
+/// Dates in an archive, without keeping their contents.
+pub fn archive_dates(path: &Path) -> Result<Vec<String>> {
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open log archive: {}", path.display()))?;
+    let mut tar = tar::Archive::new(GzDecoder::new(file));
+    let mut dates = Vec::new();
+    for entry in tar.entries()? {
+        let name = entry?.path()?.to_string_lossy().to_string();
+        if let Some(date) = name.strip_suffix(".md") {
+            dates.push(date.to_string());
+        }
+    }
+    Ok(dates)
+}
+
+pub fn write_archive(path: &Path, files: &MonthArchive) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension(format!("{}.tmp", std::process::id()));
+    let file = std::fs::File::create(&tmp)
+        .with_context(|| format!("Failed to create log archive: {}", tmp.display()))?;
+    let mut tar = tar::Builder::new(GzEncoder::new(file, Compression::best()));
+    for (date, data) in files {
+        crate::backup::append(&mut tar, &format!("{date}.md"), data)?;
+    }
+    tar.into_inner()?.finish()?.flush()?;
+    if let Err(e) = std::fs::rename(&tmp, path) {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+/// Join an archived day with a loose daily file written after the month was
+/// archived (e.g. by an import). A loose file that is already contained at
+/// the end of the archived copy — a compaction interrupted before cleanup —
+/// is not repeated.
+pub fn merge(archived: &[u8], loose: &[u8]) -> Vec<u8> {
+    if loose.is_empty() || archived.ends_with(loose) {
+        return archived.to_vec();
+    }
+    let mut out = archived.to_vec();
+    if !out.is_empty() && !out.ends_with(b"\n") {
+        out.push(b'\n');
+    }
+    out.extend_from_slice(loose);
+    out
+}
+
+#[derive(Debug, Default)]
+pub struct CompactStats {
+    pub months_archived: usize,
+    pub days_archived: usize,
+    pub sessions_pruned: usize,
+    pub archives_deleted: usize,
+}
+
+/// `true` if the whole of `month` (YYYY-MM) lies more than `months` months
+/// before `today`.
+fn older_than(month: &str, months: u32, today: NaiveDate) -> bool {
+    let Ok(first) = NaiveDate::parse_from_str(&format!("{month}-01"), "%Y-%m-%d") else {
+        return false;
+    };
+    let (Some(next), Some(cutoff)) = (
+        first.checked_add_months(Months::new(1)),
+        today.checked_sub_months(Months::new(months)),
+    ) else {
+        return false;
+    };
+    next <= cutoff
+}
+
+/// Move daily logs of months older than `after_months` into their monthly
+/// archive, merging with any archive that already exists.
+fn archive_logs(base_dir: &Path, after_months: u32, today: NaiveDate) -> Result<(usize, usize)> {
+    let daily = base_dir.join("logs/daily");
+    let Ok(entries) = std::fs::read_dir(&daily) else {
+        return Ok((0, 0));
+    };
+    let mut by_month: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for e in entries.flatten() {
+        let path = e.path();
+        let Some(stem) = path
+            .extension()
+            .filter(|x| *x == "md")
+            .and_then(|_| path.file_stem())
+            .map(|s| s.to_string_lossy().to_string())
+        else {
+            continue;
+        };
+        if NaiveDate::parse_from_str(&stem, "%Y-%m-%d").is_err() {
+            continue;
+        }
+        let month = stem[..7].to_string();
+        if older_than(&month, after_months, today) {
+            by_month.entry(month).or_default().push(path);
+        }
+    }
+
+    let (mut months, mut days) = (0, 0);
+    for (month, paths) in by_month {
+        let archive = archive_path(base_dir, &month);
+        let mut files = if archive.exists() {
+            read_archive(&archive)?
+        } else {
+            MonthArchive::new()
+        };
+        for path in &paths {
+            let date = path.file_stem().unwrap_or_default().to_string_lossy();
+            let loose = std::fs::read(path)?;
+            let merged = merge(files.get(date.as_ref()).map_or(&[][..], |v| v), &loose);
+            files.insert(date.to_string(), merged);
+        }
+        write_archive(&archive, &files)?;
+        for path in &paths {
+            std::fs::remove_file(path)?;
+        }
+        months += 1;
+        days += paths.len();
+    }
+    Ok((months, days))
+}
+
+/// Delete archives older than `after_months` whose monthly summary exists.
+fn delete_archives(base_dir: &Path, after_months: u32, today: NaiveDate) -> Result<usize> {
+    let mut deleted = 0;
+    for month in list_archives(base_dir) {
+        let summary = base_dir.join(format!("logs/monthly/{month}.md"));
+        if older_than(&month, after_months, today) && summary.exists() {
+            std::fs::remove_file(archive_path(base_dir, &month))?;
+            tracing::info!("Deleted log archive {month} (monthly summary kept)");
+            deleted += 1;
+        }
+    }
+    Ok(deleted)
+}
+
+/// Apply the retention policy once. Archive IO runs on the blocking pool.
+pub async fn compact(
+    base_dir: &Path,
+    config: &RetentionConfig,
+    sessions: &SessionStore,
+) -> Result<CompactStats> {
+    let today = Local::now().date_naive();
+    let mut stats = CompactStats::default();
+    if config.compress_after_months > 0 {
+        let (base_dir, after) = (base_dir.to_path_buf(), config.compress_after_months);
+        (stats.months_archived, stats.days_archived) =
+            tokio::task::spawn_blocking(move || archive_logs(&base_dir, after, today)).await??;
+    }
+    if config.prune_tool_output_after_months > 0
+        && let Some(cutoff) =
+            Local::now().checked_sub_months(Months::new(config.prune_tool_output_after_months))
+    {
+        stats.sessions_pruned = sessions.prune_tool_output(cutoff).await?;
+    }
+    if config.delete_after_months > 0 {
+        let (base_dir, after) = (base_dir.to_path_buf(), config.delete_after_months);
+        stats.archives_deleted =
+            tokio::task::spawn_blocking(move || delete_archives(&base_dir, after, today)).await??;
+    }
+    Ok(stats)
+}
+
+/// Run `compact` once a day in the background.
+pub fn spawn(base_dir: PathBuf, config: RetentionConfig, sessions: std::sync::Arc<SessionStore>) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(24 * 3600));
+        loop {
+            ticker.tick().await;
+            match compact(&base_dir, &config, &sessions).await {
+                Ok(s) => tracing::info!(
+                    "Log retention: archived {} days in {} months, pruned {} sessions, deleted {} archives",
+                    s.days_archived,
+                    s.months_archived,
+                    s.sessions_pruned,
+                    s.archives_deleted
+                ),
+                Err(e) => tracing::error!("Log retention failed: {e}"),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_older_than() {
+        let today = NaiveDate::from_ymd_opt(2026, 10, 18).unwrap();
+        assert!(older_than("2026-06", 3, today));
+        assert!(!older_than("2026-07", 3, today));
+        assert!(!older_than("2026-10", 0, today));
+        assert!(older_than("2025-12", 1, today));
+    }
+
+    #[test]
+    fn test_merge() {
+        assert_eq!(merge(b"- a\n", b""), b"- a\n");
+        assert_eq!(merge(b"- a\n", b"- b\n"), b"- a\n- b\n");
+        assert_eq!(merge(b"- a", b"- b\n"), b"- a\n- b\n");
+        assert_eq!(merge(b"- a\n- b\n", b"- b\n"), b"- a\n- b\n");
+        assert_eq!(merge(b"", b"- b\n"), b"- b\n");
+    }
+
+    #[test]
+    fn test_archive_logs() {
+        let base = std::env::temp_dir().join(format!("1koro-retention-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&base);
+        let daily = base.join("logs/daily");
+        std::fs::create_dir_all(&daily).unwrap();
+        std::fs::write(daily.join("2026-01-05.md"), "- jan 5\n").unwrap();
+        std::fs::write(daily.join("2026-01-06.md"), "- jan 6\n").unwrap();
+        std::fs::write(daily.join("2026-10-01.md"), "- oct 1\n").unwrap();
+        let today = NaiveDate::from_ymd_opt(2026, 10, 18).unwrap();
+
+        assert_eq!(archive_logs(&base, 3, today).unwrap(), (1, 2));
+        assert!(!daily.join("2026-01-05.md").exists());
+        assert!(daily.join("2026-10-01.md").exists());
+        assert_eq!(list_archives(&base), vec!["2026-01"]);
+
+        // A late write to an archived month is merged on the next run
+        std::fs::write(daily.join("2026-01-05.md"), "- late\n").unwrap();
+        assert_eq!(archive_logs(&base, 3, today).unwrap(), (1, 1));
+        let files = read_archive(&archive_path(&base, "2026-01")).unwrap();
+        assert_eq!(files["2026-01-05"], b"- jan 5\n- late\n");
+        assert_eq!(files["2026-01-06"], b"- jan 6\n");
+
+        // Archives are only deleted once their monthly summary exists
+        assert_eq!(delete_archives(&base, 6, today).unwrap(), 0);
+        std::fs::create_dir_all(base.join("logs/monthly")).unwrap();
+        std::fs::write(base.join("logs/monthly/2026-01.md"), "summary").unwrap();
+        assert_eq!(delete_archives(&base, 6, today).unwrap(), 1);
+        assert!(base.join("logs/monthly/2026-01.md").exists());
+        let _ = std::fs::remove_dir_all(base);
+    }
+}
@@ -5,8 +5,15 @@ use std::sync::{Arc, Mutex};
 use anyhow::Result;
 use chrono::{DateTime, Local};
 use serde::{Deserialize, Serialize};
+use tokio::sync::{RwLock, RwLockWriteGuard};
 
-use crate::llm::Message;
+use crate::crypto::{self, Cipher};
+use crate::llm::{Content, Message};
+
+const PRUNED_TOOL_OUTPUT: &str = "[tool output pruned by retention policy]";
+
+/// Replies remembered per session for idempotent retries.
+const MAX_STORED_REPLIES: usize = 100;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Session {
@@ -15,17 +22,48 @@ pub struct Session {
     #[serde(default)]
     pub summary: Option<String>,
     pub updated_at: DateTime<Local>,
+    /// Responses to recent messages that carried an idempotency key.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub replies: Vec<StoredReply>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredReply {
+    pub key: String,
+    pub text: Option<String>,
+    #[serde(default)]
+    pub actions: Vec<serde_json::Value>,
+    pub at: DateTime<Local>,
+}
+
+impl Session {
+    pub fn reply_for(&self, key: &str) -> Option<&StoredReply> {
+        self.replies.iter().find(|r| r.key == key)
+    }
+
+    /// Remember the reply to `key`, dropping the oldest beyond the limit.
+    pub fn remember_reply(&mut self, reply: StoredReply) {
+        self.replies.retain(|r| r.key != reply.key);
+        self.replies.push(reply);
+        if self.replies.len() > MAX_STORED_REPLIES {
+            let excess = self.replies.len() - MAX_STORED_REPLIES;
+            self.replies.drain(..excess);
+        }
+    }
 }
 
 /// Per-session locking: concurrent requests for different sessions run in parallel,
 /// same-session requests are serialized to prevent message loss.
 pub struct SessionStore {
     base_dir: PathBuf,
+    cipher: Option<Arc<Cipher>>,
     sessions: Mutex<HashMap<String, Arc<tokio::sync::Mutex<Session>>>>,
+    /// Saves hold a read guard; `quiesce` takes the write side to pause them.
+    write_gate: RwLock<()>,
 }
 
 impl SessionStore {
-    pub fn new(base_dir: PathBuf) -> Result<Self> {
+    pub fn new(base_dir: PathBuf, cipher: Option<Arc<Cipher>>) -> Result<Self> {
         let sessions_dir = base_dir.join("sessions");
         std::fs::create_dir_all(&sessions_dir)?;
 
@@ -33,7 +71,7 @@ impl SessionStore {
         for entry in std::fs::read_dir(&sessions_dir)?.flatten() {
             let path = entry.path();
             if path.extension().is_some_and(|e| e == "json") {
-                let content = std::fs::read_to_string(&path)?;
+                let content = crypto::read_text(cipher.as_deref(), &path)?;
                 if let Ok(session) = serde_json::from_str::<Session>(&content) {
                     let key = session.key.clone();
                     loaded
@@ -55,7 +93,9 @@ impl SessionStore {
 
         Ok(Self {
             base_dir,
+            cipher,
             sessions: Mutex::new(sessions),
+            write_gate: RwLock::new(()),
         })
     }
 
@@ -70,13 +110,45 @@ impl SessionStore {
                     messages: Vec::new(),
                     summary: None,
                     updated_at: Local::now(),
+                    replies: Vec::new(),
                 }))
             })
             .clone()
     }
 
-    /// Save session to disk. Does not acquire the session map lock.
-    pub fn save_to_disk(&self, key: &str, session: &Session) -> Result<()> {
+    /// Snapshot of all sessions, waiting for any in-flight request on each.
+    pub async fn list(&self) -> Vec<Session> {
+        let locks: Vec<_> = self
+            .sessions
+            .lock()
+            .expect("session map lock poisoned")
+            .values()
+            .cloned()
+            .collect();
+        let mut out = Vec::with_capacity(locks.len());
+        for l in locks {
+            out.push(l.lock().await.clone());
+        }
+        out.sort_by(|a, b| a.key.cmp(&b.key));
+        out
+    }
+
+    /// Snapshot of one session, waiting for any in-flight request on it.
+    pub async fn get(&self, key: &str) -> Option<Session> {
+        let lock = self
+            .sessions
+            .lock()
+            .expect("session map lock poisoned")
+            .get(key)
+            .cloned()?;
+        let session = lock.lock().await.clone();
+        Some(session)
+    }
+
+    /// Save session to disk. Does not acquire the session map lock; waits
+    /// while a backup has writes paused.
+    pub async fn save_to_disk(&self, key: &str, session: &Session) -> Result<()> {
+        let _gate = self.write_gate.read().await;
         let dir = self.base_dir.join("sessions");
         std::fs::create_dir_all(&dir)?;
 
@@ -85,7 +157,7 @@ impl SessionStore {
         let tmp = dir.join(format!("{filename}.{}.json.tmp", std::process::id()));
 
         let json = serde_json::to_string_pretty(session)?;
-        std::fs::write(&tmp, &json)?;
+        crypto::write_text(self.cipher.as_deref(), &tmp, &json)?;
         if let Err(e) = std::fs::rename(&tmp, &path) {
             let _ = std::fs::remove_file(&tmp);
             return Err(e.into());
@@ -93,6 +165,47 @@ impl SessionStore {
         Ok(())
     }
 
+    /// Replace the content of tool results in sessions last updated before
+    /// `cutoff` with a placeholder. The messages themselves are kept so tool
+    /// calls stay paired with their results. Returns the sessions changed.
+    pub async fn prune_tool_output(&self, cutoff: DateTime<Local>) -> Result<usize> {
+        let locks: Vec<_> = self
+            .sessions
+            .lock()
+            .expect("session map lock poisoned")
+            .values()
+            .cloned()
+            .collect();
+        let mut pruned = 0;
+        for l in locks {
+            let mut s = l.lock().await;
+            if s.updated_at >= cutoff {
+                continue;
+            }
+            let mut changed = false;
+            for m in s.messages.iter_mut().filter(|m| m.role == "tool") {
+                let placeholder = Content::Text(PRUNED_TOOL_OUTPUT.into());
+                if m.content.as_ref() != Some(&placeholder) {
+                    m.content = Some(placeholder);
+                    changed = true;
+                }
+            }
+            if changed {
+                self.save_to_disk(&s.key, &s).await?;
+                pruned += 1;
+            }
+        }
+        Ok(pruned)
+    }
+
+    /// Block session writes until the returned guard is dropped, so a
+    /// snapshot of the sessions directory sees a consistent set of files.
+    /// Blocks the thread, so call it from `spawn_blocking`, never from async
+    /// code.
+    pub fn quiesce(&self) -> RwLockWriteGuard<'_, ()> {
+        self.write_gate.blocking_write()
+    }
+
     /// FNV-1a hash: deterministic across Rust versions (unlike DefaultHasher).
     fn session_filename(key: &str) -> String {
         let mut hash: u64 = 0xcbf29ce484222325;
@@ -121,4 +234,35 @@ mod tests {
         let b = SessionStore::session_filename("test:key");
         assert_eq!(a, b);
     }
+
+    #[test]
+    fn test_stored_replies_are_capped() {
+        let mut s = Session {
+            key: "cli:masaki".into(),
+            messages: Vec::new(),
+            summary: None,
+            updated_at: Local::now(),
+            replies: Vec::new(),
+        };
+        for i in 0..MAX_STORED_REPLIES + 5 {
+            s.remember_reply(StoredReply {
+                key: format!("k{i}"),
+                text: Some(format!("reply {i}")),
+                actions: Vec::new(),
+                at: Local::now(),
+            });
+        }
+        assert_eq!(s.replies.len(), MAX_STORED_REPLIES);
+        assert!(s.reply_for("k0").is_none());
+        assert_eq!(
+            s.reply_for("k104").unwrap().text.as_deref(),
+            Some("reply 104")
+        );
+
+        let legacy: Session = serde_json::from_str(
+            r#"{"key":"cli:masaki","messages":[],"updated_at":"2026-02-17T10:00:00+09:00"}"#,
+        )
+        .unwrap();
+        assert!(legacy.replies.is_empty());
+    }
 }
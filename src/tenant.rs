@@ -0,0 +1,327 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::agent::{self, Agent};
+use crate::config::{Config, DEFAULT_TENANT, MemoryConfig};
+use crate::facts::FactStore;
+use crate::jobs::JobQueue;
+use crate::llm::LlmClient;
+use crate::memory::MemoryManager;
+use crate::ratelimit::Limiter;
+use crate::session::SessionStore;
+use crate::tools::{ToolContext, ToolKind, ToolRegistry};
+use crate::ws::Notifier;
+
+/// Everything that belongs to one owner of memory. Nothing here is shared
+/// with other tenants: each has its own base dir, and its tools can only
+/// reach that.
+pub struct Tenant {
+    pub name: String,
+    /// Who a message is from when it doesn't say.
+    pub default_user: String,
+    pub memory: Arc<MemoryManager>,
+    pub sessions: Arc<SessionStore>,
+    pub facts: Arc<FactStore>,
+    pub agent: Arc<Agent>,
+    pub jobs: Arc<JobQueue>,
+    pub notifier: Arc<Notifier>,
+}
+
+impl Tenant {
+    /// Build a tenant over an existing memory dir.
+    pub fn open(
+        name: &str,
+        cfg: &Config,
+        memory: &MemoryConfig,
+        llm: Arc<LlmClient>,
+        limits: Arc<Limiter>,
+    ) -> Result<Self> {
+        let base_dir = memory.base_dir.clone();
+        let mem = Arc::new(MemoryManager::new(memory)?);
+        let facts = Arc::new(FactStore::new(&base_dir, mem.cipher())?);
+        let notifier = Arc::new(Notifier::new());
+
+        let ctx = ToolContext {
+            memory: mem.clone(),
+            facts: facts.clone(),
+            base_dir: base_dir.clone(),
+        };
+        let reg = tools(name, cfg, ctx, &notifier);
+
+        let skills = agent::load_skills(&base_dir)?;
+        if !skills.is_empty() {
+            tracing::info!("Loaded {} skills for tenant '{name}'", skills.len());
+        }
+        let sessions = Arc::new(SessionStore::new(base_dir.clone(), mem.cipher())?);
+        let mut agent = Agent::new(llm, mem.clone(), sessions.clone(), reg, skills)
+            .with_parallel_tools(cfg.tools.max_parallel)
+            .with_limits(cfg.agent.limits)
+            .with_profiles(cfg.channels.clone())
+            .with_untrusted_policy(cfg.tools.untrusted_policy);
+        if memory.extract_facts {
+            agent = agent.with_fact_extraction(facts.clone());
+        }
+        let agent = Arc::new(agent);
+        let jobs = JobQueue::new(
+            base_dir,
+            mem.cipher(),
+            agent.clone(),
+            limits,
+            cfg.api
+                .callback_secret
+                .clone()
+                .or_else(|| cfg.api.api_key.clone()),
+        )?;
+        Ok(Self {
+            name: name.to_string(),
+            default_user: cfg.default_user(name).to_string(),
+            memory: mem,
+            sessions,
+            facts,
+            agent,
+            jobs,
+            notifier,
+        })
+    }
+
+    /// A session key that cannot collide with another tenant's, for rate
+    /// limits shared by all tenants.
+    pub fn scoped(&self, session: &str) -> String {
+        format!("{}/{session}", self.name)
+    }
+}
+
+/// The agent's tools over one tenant's memory. Only the default tenant gets
+/// the shell, which is not confined to a directory.
+fn tools(name: &str, cfg: &Config, ctx: ToolContext, notifier: &Arc<Notifier>) -> ToolRegistry {
+    let mut reg = ToolRegistry::new(ctx);
+    if cfg.tools.shell_enabled && name == DEFAULT_TENANT {
+        reg.add(ToolKind::Shell(Duration::from_secs(
+            cfg.tools.shell_timeout,
+        )));
+        tracing::warn!(
+            "Shell tool enabled ({}s timeout) — arbitrary command execution is possible",
+            cfg.tools.shell_timeout
+        );
+    }
+    reg.add(ToolKind::SearchLogs);
+    reg.add(ToolKind::ReadCoreMemory);
+    reg.add(ToolKind::UpdateCoreMemory);
+    reg.add(ToolKind::AppendLog);
+    reg.add(ToolKind::ReadDailyLog);
+    reg.add(ToolKind::WriteSummary);
+    reg.add(ToolKind::ReadFile);
+    reg.add(ToolKind::RememberFact);
+    reg.add(ToolKind::QueryFacts);
+    reg.add(ToolKind::Notify(notifier.clone()));
+    if name == DEFAULT_TENANT {
+        for (channel, profile) in &cfg.channels.0 {
+            for tool in profile.tools.iter().flatten() {
+                if !reg.has(tool) {
+                    tracing::warn!("[channels.{channel}] lists unknown or disabled tool '{tool}'");
+                }
+            }
+        }
+    }
+    reg
+}
+
+/// The tenants of a server, by name; there is always a `default` one.
+pub struct Tenants(HashMap<String, Arc<Tenant>>);
+
+impl Tenants {
+    pub fn new(tenants: impl IntoIterator<Item = Tenant>) -> Self {
+        Self(
+            tenants
+                .into_iter()
+                .map(|t| (t.name.clone(), Arc::new(t)))
+                .collect(),
+        )
+    }
+
+    /// The tenant a key works on: its own, or `default` when it has none.
+    pub fn get(&self, name: Option<&str>) -> Option<Arc<Tenant>> {
+        self.0.get(name.unwrap_or(DEFAULT_TENANT)).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ApiConfig, LlmConfig};
+    use crate::log_entry::{LogEntry, Role};
+
+    fn config(root: &std::path::Path) -> Config {
+        toml::from_str(&format!(
+            r#"
+            [agent]
+            name = "1koro"
+            [llm]
+            model = "m"
+            api_key = "k"
+            [memory]
+            base_dir = "{owner}"
+            [tools]
+            shell_enabled = true
+            [[tenants]]
+            name = "aiko"
+            base_dir = "{aiko}"
+            "#,
+            owner = root.join("owner").display(),
+            aiko = root.join("aiko").display(),
+        ))
+        .unwrap()
+    }
+
+    async fn open_all(cfg: &Config) -> Tenants {
+        let llm = crate::llm::create_client(&LlmConfig {
+            base_url: Some("http://127.0.0.1:9".into()),
+            model: "m".into(),
+            api_key: "k".into(),
+            max_tokens: 16,
+            vision: false,
+            audio: false,
+        })
+        .unwrap();
+        let limits = Arc::new(Limiter::new(&ApiConfig::default()));
+        let mut tenants = Vec::new();
+        for name in [DEFAULT_TENANT, "aiko"] {
+            let memory = cfg.tenant_memory(name).unwrap();
+            crate::config::init_memory_dir(&memory.base_dir)
+                .await
+                .unwrap();
+            tenants.push(Tenant::open(name, cfg, &memory, llm.clone(), limits.clone()).unwrap());
+        }
+        Tenants::new(tenants)
+    }
+
+    fn temp_root(tag: &str) -> std::path::PathBuf {
+        let root = std::env::temp_dir().join(format!("1koro-tenant-{tag}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        root
+    }
+
+    #[tokio::test]
+    async fn test_tenant_lookup() {
+        let root = temp_root("lookup");
+        let cfg = config(&root);
+        let tenants = open_all(&cfg).await;
+        assert_eq!(tenants.get(None).unwrap().name, DEFAULT_TENANT);
+        assert_eq!(tenants.get(Some("aiko")).unwrap().name, "aiko");
+        assert!(tenants.get(Some("ken")).is_none());
+        let aiko = tenants.get(Some("aiko")).unwrap();
+        assert_ne!(
+            aiko.scoped("cli:masaki"),
+            tenants.get(None).unwrap().scoped("cli:masaki")
+        );
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    /// Adversarial calls from one tenant's agent aimed at another's files.
+    #[tokio::test]
+    async fn test_tenants_cannot_read_each_other() {
+        let root = temp_root("isolation");
+        let cfg = config(&root);
+        let tenants = open_all(&cfg).await;
+        let (owner, aiko) = (
+            tenants.get(None).unwrap(),
+            tenants.get(Some("aiko")).unwrap(),
+        );
+        owner
+            .memory
+            .append_log(&LogEntry::message(
+                Role::User,
+                "cli",
+                "masaki",
+                "masaki",
+                "owner-secret: the vault code is 4711",
+            ))
+            .unwrap();
+        owner
+            .memory
+            .write_core("user.md", "# User\n\nowner-secret")
+            .unwrap();
+        aiko.memory
+            .append_log(&LogEntry::message(
+                Role::User,
+                "cli",
+                "aiko",
+                "aiko",
+                "aiko's own note",
+            ))
+            .unwrap();
+
+        let aiko_tools = tools(
+            "aiko",
+            &cfg,
+            ToolContext {
+                memory: aiko.memory.clone(),
+                facts: aiko.facts.clone(),
+                base_dir: aiko.memory.base_dir().to_path_buf(),
+            },
+            &aiko.notifier,
+        );
+        let found = aiko_tools
+            .execute("search_logs", r#"{"query":"owner-secret"}"#)
+            .await
+            .unwrap()
+            .for_llm;
+        assert!(!found.contains("4711"), "search_logs leaked: {found}");
+        let own = aiko_tools
+            .execute("search_logs", r#"{"query":"own note"}"#)
+            .await
+            .unwrap()
+            .for_llm;
+        assert!(own.contains("aiko's own note"));
+
+        let owner_dir = cfg.tenant_memory(DEFAULT_TENANT).unwrap().base_dir;
+        let today = chrono::Local::now().format("%Y-%m-%d");
+        for path in [
+            owner_dir.join("core/user.md").display().to_string(),
+            owner_dir
+                .join(format!("logs/daily/{today}.md"))
+                .display()
+                .to_string(),
+            "../owner/core/user.md".to_string(),
+            format!("../owner/logs/daily/{today}.md"),
+        ] {
+            let args = serde_json::json!({ "path": path }).to_string();
+            let out = aiko_tools
+                .execute("read_file", &args)
+                .await
+                .unwrap()
+                .for_llm;
+            assert!(
+                !out.contains("owner-secret") && out.starts_with("Error"),
+                "read_file leaked {path}: {out}"
+            );
+        }
+        #[cfg(unix)]
+        {
+            let link = cfg.tenant_memory("aiko").unwrap().base_dir.join("link");
+            std::os::unix::fs::symlink(owner_dir.join("core/user.md"), &link).unwrap();
+            let out = aiko_tools
+                .execute("read_file", r#"{"path":"link"}"#)
+                .await
+                .unwrap()
+                .for_llm;
+            assert!(!out.contains("owner-secret"), "symlink leaked: {out}");
+        }
+
+        let core = aiko_tools
+            .execute("read_core_memory", r#"{"file":"user.md"}"#)
+            .await
+            .unwrap()
+            .for_llm;
+        assert!(!core.contains("owner-secret"));
+        assert!(
+            !aiko_tools.has("shell"),
+            "only the default tenant gets a shell"
+        );
+        assert!(aiko.sessions.list().await.is_empty());
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
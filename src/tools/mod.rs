@@ -6,7 +6,11 @@ use std::sync::Arc;
 use anyhow::Result;
 use serde_json::{Value, json};
 
+use crate::facts::{FactQuery, FactStore, NewFact, format_fact};
+use crate::log_entry::{self, LogEntry, LogQuery};
 use crate::memory::MemoryManager;
+use crate::trust::Trust;
+use crate::ws::Notifier;
 
 #[derive(Debug)]
 pub struct ToolResult {
@@ -15,6 +19,7 @@ pub struct ToolResult {
 
 pub struct ToolContext {
     pub memory: Arc<MemoryManager>,
+    pub facts: Arc<FactStore>,
     pub base_dir: PathBuf,
 }
 
@@ -38,7 +43,10 @@ pub enum ToolKind {
     WriteSummary,
     AppendLog,
     ReadFile,
+    RememberFact,
+    QueryFacts,
     Shell(std::time::Duration),
+    Notify(Arc<Notifier>),
 }
 
 impl ToolKind {
@@ -51,7 +59,44 @@ impl ToolKind {
             Self::WriteSummary => "write_summary",
             Self::AppendLog => "append_note",
             Self::ReadFile => "read_file",
+            Self::RememberFact => "remember_fact",
+            Self::QueryFacts => "query_facts",
             Self::Shell(_) => "shell",
+            Self::Notify(_) => "notify",
+        }
+    }
+
+    /// Tools that change memory; over MCP they need the `memory:write` scope.
+    fn writes_memory(&self) -> bool {
+        matches!(
+            self,
+            Self::UpdateCoreMemory | Self::WriteSummary | Self::AppendLog | Self::RememberFact
+        )
+    }
+
+    /// Tools that only read; a turn's read-only calls may run concurrently,
+    /// everything else runs one at a time.
+    fn is_read_only(&self) -> bool {
+        matches!(
+            self,
+            Self::SearchLogs
+                | Self::ReadCoreMemory
+                | Self::ReadDailyLog
+                | Self::ReadFile
+                | Self::QueryFacts
+        )
+    }
+
+    /// Tools whose output can carry text written by someone else: files,
+    /// logged messages, extracted facts and command output.
+    fn trust(&self) -> Trust {
+        match self {
+            Self::SearchLogs
+            | Self::ReadDailyLog
+            | Self::ReadFile
+            | Self::QueryFacts
+            | Self::Shell(_) => Trust::Untrusted,
+            _ => Trust::Trusted,
         }
     }
 
@@ -59,11 +104,18 @@ impl ToolKind {
         match self {
             Self::SearchLogs => (
                 "search_logs",
-                "Search past conversation logs and daily notes for a keyword",
+                "Search past conversation logs and daily notes. Narrow results by channel, user, role or date range; query may be empty when a filter is given",
                 json!({"type":"object","properties":{
-                    "query":{"type":"string"},
+                    "query":{"type":"string","description":"Case-insensitive text to find"},
+                    "regex":{"type":"boolean","description":"Treat query as a regular expression"},
+                    "channel":{"type":"string","description":"e.g. slack, cli, slack#general"},
+                    "user":{"type":"string","description":"User id or display name"},
+                    "role":{"type":"string","enum":["user","agent","note"]},
+                    "from":{"type":"string","description":"Start date YYYY-MM-DD (inclusive)"},
+                    "to":{"type":"string","description":"End date YYYY-MM-DD (inclusive)"},
+                    "context":{"type":"integer","description":"Entries of context before and after each hit (max 10)"},
                     "limit":{"type":"integer","description":"Max results (default 100)"}
-                },"required":["query"]}),
+                }}),
             ),
             Self::ReadCoreMemory => (
                 "read_core_memory",
@@ -110,6 +162,27 @@ impl ToolKind {
                     "path":{"type":"string","description":"File path relative to memory directory (~/.1koro)"}
                 },"required":["path"]}),
             ),
+            Self::RememberFact => (
+                "remember_fact",
+                "Store a structured fact (subject, predicate, object), e.g. ('Tanaka', 'birthday', 'March 14'). A new object for the same subject and predicate supersedes the old one",
+                json!({"type":"object","properties":{
+                    "subject":{"type":"string","description":"Person, project, place or thing"},
+                    "predicate":{"type":"string","description":"Attribute or relation, e.g. 'birthday', 'works_on'"},
+                    "object":{"type":"string"},
+                    "confidence":{"type":"number","description":"0.0-1.0 (default 1.0)"}
+                },"required":["subject","predicate","object"]}),
+            ),
+            Self::QueryFacts => (
+                "query_facts",
+                "Look up structured facts by subject, predicate or free text",
+                json!({"type":"object","properties":{
+                    "subject":{"type":"string"},
+                    "predicate":{"type":"string"},
+                    "query":{"type":"string","description":"Free-text match on any field"},
+                    "history":{"type":"boolean","description":"Include superseded facts (default false)"},
+                    "limit":{"type":"integer","description":"Max results (default 100)"}
+                }}),
+            ),
             Self::Shell(_) => (
                 "shell",
                 "Execute a shell command (runs in memory directory)",
@@ -117,22 +190,31 @@ impl ToolKind {
                     "command":{"type":"string","description":"Shell command to execute"}
                 },"required":["command"]}),
             ),
+            Self::Notify(_) => (
+                "notify",
+                "Push a message to the user's open web chat right away, e.g. a reminder or the result of background work",
+                json!({"type":"object","properties":{
+                    "text":{"type":"string"}
+                },"required":["text"]}),
+            ),
         }
     }
 
     async fn execute(&self, args: Value, ctx: &ToolContext) -> Result<ToolResult> {
         match self {
             Self::SearchLogs => {
-                let query = require_str(&args, "query")?;
-                if query.is_empty() {
-                    return ok("Error: 'query' must not be empty");
-                }
-                let limit = args["limit"].as_u64().unwrap_or(100) as usize;
-                let results = ctx.memory.search_logs(query, limit)?;
-                ok(if results.is_empty() {
+                let query: LogQuery = match serde_json::from_value(args) {
+                    Ok(q) => q,
+                    Err(e) => return ok(format!("Error: invalid arguments: {e}")),
+                };
+                let hits = match ctx.memory.search_logs(&query) {
+                    Ok(h) => h,
+                    Err(e) => return ok(format!("Error: {e:#}")),
+                };
+                ok(if hits.is_empty() {
                     "No results found.".into()
                 } else {
-                    results.join("\n")
+                    log_entry::format_hits(&hits)
                 })
             }
             Self::ReadCoreMemory => ok(ctx.memory.read_core(require_str(&args, "file")?)?),
@@ -171,7 +253,7 @@ impl ToolKind {
                 if text.is_empty() {
                     return ok("Error: 'text' must not be empty");
                 }
-                ctx.memory.append_log(text)?;
+                ctx.memory.append_log(&LogEntry::note(text))?;
                 ok("Note appended.")
             }
             Self::ReadFile => {
@@ -195,12 +277,49 @@ impl ToolKind {
                         path.display()
                     ));
                 }
-                match std::fs::read_to_string(&canonical) {
+                match ctx.memory.read_text(&canonical) {
                     Ok(c) => ok(c),
                     Err(e) => ok(format!("Error reading {}: {e}", path.display())),
                 }
             }
+            Self::RememberFact => {
+                let fact = ctx.facts.remember(NewFact {
+                    subject: require_str(&args, "subject")?.into(),
+                    predicate: require_str(&args, "predicate")?.into(),
+                    object: require_str(&args, "object")?.into(),
+                    source: None,
+                    confidence: args["confidence"].as_f64().unwrap_or(1.0) as f32,
+                })?;
+                ok(format!("Remembered {}", format_fact(&fact)))
+            }
+            Self::QueryFacts => {
+                let q = FactQuery {
+                    subject: args["subject"].as_str().map(Into::into),
+                    predicate: args["predicate"].as_str().map(Into::into),
+                    q: args["query"].as_str().map(Into::into),
+                    history: args["history"].as_bool().unwrap_or(false),
+                    limit: args["limit"].as_u64().map(|n| n as usize),
+                };
+                let facts = ctx.facts.query(&q);
+                ok(if facts.is_empty() {
+                    "No facts found.".into()
+                } else {
+                    facts.iter().map(format_fact).collect::<Vec<_>>().join("\n")
+                })
+            }
             Self::Shell(timeout) => shell::execute(&args, ctx, *timeout).await,
+            Self::Notify(notifier) => {
+                let text = require_str(&args, "text")?;
+                if text.is_empty() {
+                    return ok("Error: 'text' must not be empty");
+                }
+                ctx.memory
+                    .append_log(&LogEntry::note(&format!("Notified: {text}")))?;
+                ok(match notifier.send(text) {
+                    0 => "No client is connected; the notification was logged only.".into(),
+                    n => format!("Notification sent to {n} connected clients."),
+                })
+            }
         }
     }
 }
@@ -253,6 +372,31 @@ impl ToolRegistry {
             .collect()
     }
 
+    pub fn has(&self, name: &str) -> bool {
+        self.tools.iter().any(|t| t.name() == name)
+    }
+
+    pub fn writes_memory(&self, name: &str) -> bool {
+        self.tools
+            .iter()
+            .any(|t| t.name() == name && t.writes_memory())
+    }
+
+    /// Unknown tools count as mutating.
+    pub fn is_read_only(&self, name: &str) -> bool {
+        self.tools
+            .iter()
+            .any(|t| t.name() == name && t.is_read_only())
+    }
+
+    /// Unknown tools are untrusted.
+    pub fn trust(&self, name: &str) -> Trust {
+        self.tools
+            .iter()
+            .find(|t| t.name() == name)
+            .map_or(Trust::Untrusted, ToolKind::trust)
+    }
+
     pub async fn execute(&self, name: &str, args_json: &str) -> Result<ToolResult> {
         let tool = self
             .tools
@@ -279,7 +423,10 @@ mod tests {
             ToolKind::WriteSummary,
             ToolKind::AppendLog,
             ToolKind::ReadFile,
+            ToolKind::RememberFact,
+            ToolKind::QueryFacts,
             ToolKind::Shell(std::time::Duration::from_secs(30)),
+            ToolKind::Notify(Arc::new(Notifier::new())),
         ];
         let mut names: Vec<&str> = all.iter().map(|t| t.name()).collect();
         let len_before = names.len();
@@ -299,7 +446,10 @@ mod tests {
             ToolKind::WriteSummary,
             ToolKind::AppendLog,
             ToolKind::ReadFile,
+            ToolKind::RememberFact,
+            ToolKind::QueryFacts,
             ToolKind::Shell(std::time::Duration::from_secs(30)),
+            ToolKind::Notify(Arc::new(Notifier::new())),
         ];
         for t in &all {
             assert_eq!(t.name(), t.spec().0, "name() and spec().0 must match");
@@ -312,6 +462,7 @@ mod tests {
             memory: Arc::new(
                 crate::memory::MemoryManager::new(&crate::config::MemoryConfig::default()).unwrap(),
             ),
+            facts: Arc::new(crate::facts::FactStore::new(&std::env::temp_dir(), None).unwrap()),
             base_dir: std::env::temp_dir(),
         };
         let mut reg = ToolRegistry::new(ctx);
@@ -333,6 +484,7 @@ mod tests {
             memory: Arc::new(
                 crate::memory::MemoryManager::new(&crate::config::MemoryConfig::default()).unwrap(),
             ),
+            facts: Arc::new(crate::facts::FactStore::new(&std::env::temp_dir(), None).unwrap()),
             base_dir: std::env::temp_dir(),
         };
         let reg = ToolRegistry::new(ctx);
@@ -6,6 +6,32 @@ use tokio::process::Command;
 
 use super::{ToolContext, ToolResult, ok, require_str};
 
+/// Kills the command's whole process group when dropped, so neither a
+/// timeout nor a cancelled run leaves it running. This runs on an async
+/// worker, so it only signals: tokio reaps the dropped `sh`, and init the
+/// rest of the group.
+struct ProcessGroup(i32);
+
+impl ProcessGroup {
+    /// The command exited on its own; leave the group alone.
+    fn release(mut self) {
+        self.0 = 0;
+    }
+}
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        let pgid = self.0;
+        if pgid <= 0 {
+            return;
+        }
+        let ret = unsafe { libc::killpg(pgid, libc::SIGKILL) };
+        if ret != 0 {
+            tracing::warn!("killpg({pgid}) failed: {}", std::io::Error::last_os_error());
+        }
+    }
+}
+
 pub async fn execute(args: &Value, ctx: &ToolContext, timeout: Duration) -> Result<ToolResult> {
     let cmd = require_str(args, "command")?;
 
@@ -19,27 +45,15 @@ pub async fn execute(args: &Value, ctx: &ToolContext, timeout: Duration) -> Resu
         .spawn()
         .map_err(|e| anyhow::anyhow!("Shell spawn error: {e}"))?;
 
-    let pgid = child.id().unwrap_or(0) as i32;
+    let group = ProcessGroup(child.id().unwrap_or(0) as i32);
 
     let output = match tokio::time::timeout(timeout, child.wait_with_output()).await {
-        Ok(Ok(output)) => output,
-        Ok(Err(e)) => return ok(format!("Shell error: {e}")),
-        Err(_) => {
-            if pgid > 0 {
-                let ret = unsafe { libc::killpg(pgid, libc::SIGKILL) };
-                if ret != 0 {
-                    tracing::warn!("killpg({pgid}) failed: {}", std::io::Error::last_os_error());
-                }
-                for _ in 0..3 {
-                    let r = unsafe { libc::waitpid(-pgid, std::ptr::null_mut(), libc::WNOHANG) };
-                    if r != 0 {
-                        break;
-                    }
-                    tokio::time::sleep(Duration::from_millis(10)).await;
-                }
-            }
-            return ok(format!("Shell timeout after {}s", timeout.as_secs()));
+        Ok(Ok(output)) => {
+            group.release();
+            output
         }
+        Ok(Err(e)) => return ok(format!("Shell error: {e}")),
+        Err(_) => return ok(format!("Shell timeout after {}s", timeout.as_secs())),
     };
 
     if output.status.success() {
@@ -52,3 +66,39 @@ pub async fn execute(args: &Value, ctx: &ToolContext, timeout: Duration) -> Resu
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_dropped_command_is_killed() {
+        let dir = std::env::temp_dir().join(format!("1koro-shell-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let ctx = ToolContext {
+            memory: Arc::new(
+                crate::memory::MemoryManager::new(&crate::config::MemoryConfig::default()).unwrap(),
+            ),
+            facts: Arc::new(crate::facts::FactStore::new(&dir, None).unwrap()),
+            base_dir: dir.clone(),
+        };
+        let args = serde_json::json!({"command": "sleep 30 & echo $! > pid; wait"});
+        tokio::select! {
+            _ = execute(&args, &ctx, Duration::from_secs(60)) => panic!("command finished"),
+            () = tokio::time::sleep(Duration::from_millis(500)) => {}
+        }
+        let pid = std::fs::read_to_string(dir.join("pid")).unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        // Gone, or a zombie waiting for init to reap it.
+        let state =
+            std::fs::read_to_string(format!("/proc/{}/stat", pid.trim())).unwrap_or_default();
+        assert!(
+            state.is_empty() || state.contains(") Z "),
+            "still running: {state}"
+        );
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
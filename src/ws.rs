@@ -0,0 +1,377 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::extract::ws::{Message as WsMessage, WebSocket, WebSocketUpgrade};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Extension, Router};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+
+use crate::agent::AgentEvent;
+use crate::api::{AppState, AttachmentUpload};
+use crate::attachments::Attachment;
+use crate::auth::Principal;
+use crate::tenant::Tenant;
+
+/// Notifications kept for clients that fall behind before older ones are
+/// dropped.
+const NOTIFICATION_BUFFER: usize = 64;
+
+/// A message the agent pushes to connected clients on its own.
+#[derive(Debug, Clone, Serialize)]
+pub struct Notification {
+    pub text: String,
+    pub at: DateTime<Local>,
+}
+
+/// Fans agent notifications out to every open WebSocket.
+pub struct Notifier {
+    tx: broadcast::Sender<Notification>,
+}
+
+impl Notifier {
+    pub fn new() -> Self {
+        Self {
+            tx: broadcast::channel(NOTIFICATION_BUFFER).0,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Notification> {
+        self.tx.subscribe()
+    }
+
+    /// Push `text` to connected clients; returns how many there were.
+    pub fn send(&self, text: &str) -> usize {
+        let note = Notification {
+            text: text.to_string(),
+            at: Local::now(),
+        };
+        self.tx.send(note).unwrap_or(0)
+    }
+}
+
+/// Frames sent by the client.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientFrame {
+    /// Run the agent. `id` is chosen by the client and echoed on every
+    /// frame about this run.
+    Message {
+        id: String,
+        #[serde(default)]
+        text: String,
+        #[serde(default = "default_channel")]
+        channel: String,
+        /// Defaults to the tenant's `default_user`.
+        user: Option<String>,
+        #[serde(default)]
+        attachments: Vec<AttachmentUpload>,
+        message_id: Option<String>,
+    },
+    /// Stop the run started by the message with this `id` (and anything
+    /// else running in its session).
+    Cancel {
+        id: String,
+    },
+    Ping,
+}
+
+fn default_channel() -> String {
+    "web".into()
+}
+
+/// `GET /ws`: the streaming chat protocol used by the web page. Auth is the
+/// same as the HTTP API (bearer token or login cookie, `message` scope).
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/ws", get(upgrade))
+}
+
+/// Browsers attach cookies to cross-site WebSocket handshakes and the
+/// upgrade is a GET, so the CSRF header check does not apply; refuse
+/// handshakes whose `Origin` is a different host instead.
+fn same_origin(headers: &HeaderMap) -> bool {
+    let Some(origin) = headers.get("origin").and_then(|v| v.to_str().ok()) else {
+        return true;
+    };
+    let host = headers.get("host").and_then(|v| v.to_str().ok());
+    let origin_host = origin.split_once("://").map_or(origin, |(_, h)| h);
+    host.is_some_and(|h| h.eq_ignore_ascii_case(origin_host))
+}
+
+async fn upgrade(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> Response {
+    if !same_origin(&headers) {
+        tracing::warn!("Rejected WebSocket from a foreign origin");
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    ws.on_upgrade(move |socket| connection(state, principal, tenant, socket))
+}
+
+async fn connection(
+    state: AppState,
+    principal: Principal,
+    tenant: Arc<Tenant>,
+    mut socket: WebSocket,
+) {
+    tracing::info!("WebSocket opened by '{}'", principal.name);
+    let (tx, mut outgoing) = mpsc::unbounded_channel::<Value>();
+    let mut notifications = tenant.notifier.subscribe();
+    // Notifications are about the owner's memory, not for keys bound to
+    // another user or channel.
+    let notify = principal.user.is_none() && principal.channel.is_none();
+    let mut runs: Runs = HashMap::new();
+    let _ = tx.send(json!({ "type": "ready", "name": state.name, "principal": principal.name }));
+
+    loop {
+        tokio::select! {
+            incoming = socket.recv() => match incoming {
+                Some(Ok(WsMessage::Text(text))) => {
+                    runs.retain(|_, (_, run)| !run.is_finished());
+                    handle_frame(&state, &principal, &tenant, &text, &tx, &mut runs);
+                }
+                Some(Ok(WsMessage::Close(_)) | Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+            Some(frame) = outgoing.recv() => {
+                if socket.send(WsMessage::Text(frame.to_string())).await.is_err() {
+                    break;
+                }
+            }
+            note = notifications.recv() => match note {
+                Ok(note) if notify => {
+                    let _ = tx.send(json!({ "type": "notification", "text": note.text, "at": note.at }));
+                }
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+        }
+    }
+    // Runs still in progress finish in the background; their replies are
+    // kept in the session.
+    tracing::info!("WebSocket closed by '{}'", principal.name);
+}
+
+/// Runs started on a connection: request id to session key and task.
+type Runs = HashMap<String, (String, JoinHandle<()>)>;
+
+fn error_frame(id: Option<&str>, message: impl std::fmt::Display) -> Value {
+    json!({ "type": "error", "id": id, "message": message.to_string() })
+}
+
+fn handle_frame(
+    state: &AppState,
+    principal: &Principal,
+    tenant: &Arc<Tenant>,
+    text: &str,
+    tx: &mpsc::UnboundedSender<Value>,
+    runs: &mut Runs,
+) {
+    let frame = match serde_json::from_str::<ClientFrame>(text) {
+        Ok(f) => f,
+        Err(e) => {
+            let _ = tx.send(error_frame(None, format!("Invalid frame: {e}")));
+            return;
+        }
+    };
+    match frame {
+        ClientFrame::Ping => {
+            let _ = tx.send(json!({ "type": "pong" }));
+        }
+        ClientFrame::Cancel { id } => {
+            let Some((session, run)) = runs.get(&id) else {
+                return;
+            };
+            tracing::info!("Run {id} cancelled by '{}'", principal.name);
+            // A started run ends with its own `cancelled` frame; one still
+            // waiting for an agent slot has done nothing yet and is dropped.
+            if tenant.agent.cancel(session) == 0 {
+                run.abort();
+                runs.remove(&id);
+                let _ = tx.send(json!({ "type": "cancelled", "id": id }));
+            }
+        }
+        ClientFrame::Message {
+            id,
+            text,
+            mut channel,
+            user,
+            attachments,
+            message_id,
+        } => {
+            if runs.contains_key(&id) {
+                let _ = tx.send(error_frame(Some(&id), "A run with this id is in progress"));
+                return;
+            }
+            let mut user = user.unwrap_or_else(|| tenant.default_user.clone());
+            principal.bind(&mut user, &mut channel);
+            let attachments = match attachments
+                .iter()
+                .map(|a| Attachment::from_base64(&a.filename, a.mime_type.as_deref(), &a.data))
+                .collect::<anyhow::Result<Vec<_>>>()
+            {
+                Ok(a) => a,
+                Err(e) => {
+                    let _ = tx.send(error_frame(Some(&id), e));
+                    return;
+                }
+            };
+            if text.trim().is_empty() && attachments.is_empty() {
+                let _ = tx.send(error_frame(
+                    Some(&id),
+                    "'text' or an attachment is required",
+                ));
+                return;
+            }
+            let session = format!("{channel}:{user}");
+            let run = Run {
+                id: id.clone(),
+                text,
+                channel,
+                user,
+                attachments,
+                message_id,
+            };
+            let task = tokio::spawn(run.execute(
+                state.clone(),
+                principal.clone(),
+                tenant.clone(),
+                tx.clone(),
+            ));
+            runs.insert(id, (session, task));
+        }
+    }
+}
+
+struct Run {
+    id: String,
+    text: String,
+    channel: String,
+    user: String,
+    attachments: Vec<Attachment>,
+    message_id: Option<String>,
+}
+
+impl Run {
+    async fn execute(
+        self,
+        state: AppState,
+        principal: Principal,
+        tenant: Arc<Tenant>,
+        tx: mpsc::UnboundedSender<Value>,
+    ) {
+        let id = self.id;
+        let session = format!("{}:{}", self.channel, self.user);
+        let admitted = match state
+            .limits
+            .check(&principal.name, &tenant.scoped(&session))
+        {
+            Ok(()) => state.limits.acquire().await,
+            Err(e) => Err(e),
+        };
+        let _permit = match admitted {
+            Ok(p) => p,
+            Err(e) => {
+                let _ = tx.send(json!({
+                    "type": "error",
+                    "id": id,
+                    "message": e.message,
+                    "retry_after": e.retry_after_secs(),
+                }));
+                return;
+            }
+        };
+        let _ = tx.send(json!({ "type": "start", "id": id, "session": session }));
+
+        let (events, mut progress) = mpsc::unbounded_channel::<AgentEvent>();
+        let forward = {
+            let (tx, id) = (tx.clone(), id.clone());
+            async move {
+                while let Some(event) = progress.recv().await {
+                    let mut frame = serde_json::to_value(event).unwrap_or_default();
+                    frame["id"] = json!(id);
+                    let _ = tx.send(frame);
+                }
+            }
+        };
+        let run = async move {
+            tenant
+                .agent
+                .handle_message(
+                    &self.text,
+                    &self.channel,
+                    &self.user,
+                    self.attachments,
+                    self.message_id.as_deref(),
+                    Some(&events),
+                )
+                .await
+        };
+        let (result, ()) = tokio::join!(run, forward);
+        let _ = tx.send(match result {
+            Ok(resp) if resp.cancelled => json!({ "type": "cancelled", "id": id }),
+            Ok(resp) => json!({
+                "type": "done",
+                "id": id,
+                "text": resp.text,
+                "actions": resp.actions,
+            }),
+            Err(e) => {
+                tracing::error!("Agent error: {e}");
+                error_frame(Some(&id), e)
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_frames() {
+        let frame: ClientFrame =
+            serde_json::from_str(r#"{"type":"message","id":"1","text":"hi"}"#).unwrap();
+        let ClientFrame::Message { channel, user, .. } = frame else {
+            panic!("expected a message frame");
+        };
+        assert_eq!((channel.as_str(), user), ("web", None));
+        assert!(matches!(
+            serde_json::from_str(r#"{"type":"cancel","id":"1"}"#).unwrap(),
+            ClientFrame::Cancel { .. }
+        ));
+        assert!(
+            serde_json::from_str::<ClientFrame>(r#"{"type":"message","text":"no id"}"#).is_err()
+        );
+    }
+
+    #[test]
+    fn test_same_origin() {
+        let mut headers = HeaderMap::new();
+        assert!(same_origin(&headers), "non-browser clients send no Origin");
+        headers.insert("host", "localhost:3000".parse().unwrap());
+        headers.insert("origin", "http://localhost:3000".parse().unwrap());
+        assert!(same_origin(&headers));
+        headers.insert("origin", "https://evil.example".parse().unwrap());
+        assert!(!same_origin(&headers));
+    }
+
+    #[tokio::test]
+    async fn test_notifier_reaches_subscribers() {
+        let notifier = Notifier::new();
+        assert_eq!(notifier.send("nobody listening"), 0);
+        let mut rx = notifier.subscribe();
+        assert_eq!(notifier.send("meeting in 10 minutes"), 1);
+        assert_eq!(rx.recv().await.unwrap().text, "meeting in 10 minutes");
+    }
+}